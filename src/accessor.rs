@@ -0,0 +1,566 @@
+//! Reading and writing the data of accessors, following the data layout rules of glTF 2.0:
+//! byte offsets and strides, padded matrix columns, normalized integers and sparse storage.
+//!
+//! Values are exchanged as f64, which holds every component type exactly.
+//! Normalized integers are converted to and from their float meaning ([0, 1] or [-1, 1]).
+
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::accessor::ComponentType;
+use gltf_json::accessor::Type;
+use gltf_json::validation::Checked::Valid;
+
+/// How the components of one element of an accessor are stored
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub component_type: ComponentType,
+    pub normalized: bool,
+    /// Number of components of an element, e.g. 3 for VEC3 and 16 for MAT4
+    pub components: usize,
+    /// Components per column. Each column of a matrix starts at a multiple of four bytes.
+    rows: usize,
+    column_stride: usize,
+    /// Size of an element in bytes, including the padding of matrix columns
+    pub element_size: usize,
+}
+
+impl Layout {
+    pub fn new(component_type: ComponentType, type_: Type, normalized: bool) -> Self {
+        let size = component_type.size();
+        let (rows, columns) = match type_ {
+            Type::Scalar => (1, 1),
+            Type::Vec2 => (2, 1),
+            Type::Vec3 => (3, 1),
+            Type::Vec4 => (4, 1),
+            Type::Mat2 => (2, 2),
+            Type::Mat3 => (3, 3),
+            Type::Mat4 => (4, 4),
+        };
+        let column_stride = if columns > 1 {
+            (rows * size + 3) & !3
+        } else {
+            rows * size
+        };
+        Self {
+            component_type,
+            normalized,
+            components: rows * columns,
+            rows,
+            column_stride,
+            element_size: column_stride * columns,
+        }
+    }
+    pub fn of(a: &gltf_json::Accessor) -> Result<Self> {
+        let component_type = match a.component_type {
+            Valid(ct) => ct.0,
+            _ => bail!("invalid componentType"),
+        };
+        let type_ = match a.type_ {
+            Valid(t) => t,
+            _ => bail!("invalid type"),
+        };
+        if a.normalized && matches!(component_type, ComponentType::U32 | ComponentType::F32) {
+            bail!("{:?} can not be normalized", component_type);
+        }
+        Ok(Self::new(component_type, type_, a.normalized))
+    }
+    fn component_offset(&self, i: usize) -> usize {
+        (i / self.rows) * self.column_stride + (i % self.rows) * self.component_type.size()
+    }
+    /// Append the values of the element stored at the head of `bytes` to `out`
+    pub fn decode(&self, bytes: &[u8], out: &mut Vec<f64>) {
+        for i in 0..self.components {
+            let b = &bytes[self.component_offset(i)..];
+            let v = match self.component_type {
+                ComponentType::I8 => b[0] as i8 as f64,
+                ComponentType::U8 => b[0] as f64,
+                ComponentType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+                ComponentType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+                ComponentType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                ComponentType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            };
+            out.push(if self.normalized {
+                self.normalize(v)
+            } else {
+                v
+            });
+        }
+    }
+    /// Store `values` (one element) at the head of `bytes`.
+    /// Integers are rounded, and values out of the range of the component type are an error.
+    pub fn encode(&self, values: &[f64], bytes: &mut [u8]) -> Result<()> {
+        for (i, v) in values.iter().enumerate().take(self.components) {
+            let v = if self.normalized {
+                self.denormalize(*v)
+            } else {
+                *v
+            };
+            let b = &mut bytes[self.component_offset(i)..];
+            let int = |min: f64, max: f64| {
+                let r = v.round();
+                if (min..=max).contains(&r) {
+                    Ok(r)
+                } else {
+                    Err(anyhow!("{} does not fit in {:?}", v, self.component_type))
+                }
+            };
+            match self.component_type {
+                ComponentType::I8 => b[0] = int(i8::MIN as f64, i8::MAX as f64)? as i8 as u8,
+                ComponentType::U8 => b[0] = int(0.0, u8::MAX as f64)? as u8,
+                ComponentType::I16 => b[..2].copy_from_slice(
+                    &(int(i16::MIN as f64, i16::MAX as f64)? as i16).to_le_bytes(),
+                ),
+                ComponentType::U16 => {
+                    b[..2].copy_from_slice(&(int(0.0, u16::MAX as f64)? as u16).to_le_bytes())
+                }
+                ComponentType::U32 => {
+                    b[..4].copy_from_slice(&(int(0.0, u32::MAX as f64)? as u32).to_le_bytes())
+                }
+                ComponentType::F32 => b[..4].copy_from_slice(&(v as f32).to_le_bytes()),
+            }
+        }
+        Ok(())
+    }
+    fn normalize(&self, v: f64) -> f64 {
+        match self.component_type {
+            ComponentType::I8 => (v / i8::MAX as f64).max(-1.0),
+            ComponentType::U8 => v / u8::MAX as f64,
+            ComponentType::I16 => (v / i16::MAX as f64).max(-1.0),
+            ComponentType::U16 => v / u16::MAX as f64,
+            _ => v,
+        }
+    }
+    fn denormalize(&self, v: f64) -> f64 {
+        match self.component_type {
+            ComponentType::I8 => v.clamp(-1.0, 1.0) * i8::MAX as f64,
+            ComponentType::U8 => v.clamp(0.0, 1.0) * u8::MAX as f64,
+            ComponentType::I16 => v.clamp(-1.0, 1.0) * i16::MAX as f64,
+            ComponentType::U16 => v.clamp(0.0, 1.0) * u16::MAX as f64,
+            _ => v,
+        }
+    }
+}
+
+/// Read `count` elements stored from `offset` of `data`, `stride` bytes apart
+/// (tightly packed if None). Returns the components of all the elements in order.
+pub fn read_elements(
+    layout: &Layout,
+    data: &[u8],
+    offset: usize,
+    stride: Option<usize>,
+    count: usize,
+) -> Result<Vec<f64>> {
+    let stride = stride.unwrap_or(layout.element_size);
+    let mut values = Vec::with_capacity(count * layout.components);
+    for i in 0..count {
+        let ofs = offset + i * stride;
+        let bytes = data
+            .get(ofs..ofs + layout.element_size)
+            .context("out of range of its bufferView")?;
+        layout.decode(bytes, &mut values);
+    }
+    Ok(values)
+}
+
+/// Overwrite elements stored from `offset` of `data` with `values`, the counterpart of `read_elements`
+pub fn write_elements(
+    layout: &Layout,
+    data: &mut [u8],
+    offset: usize,
+    stride: Option<usize>,
+    values: &[f64],
+) -> Result<()> {
+    let stride = stride.unwrap_or(layout.element_size);
+    for (i, e) in values.chunks_exact(layout.components).enumerate() {
+        let ofs = offset + i * stride;
+        let bytes = data
+            .get_mut(ofs..ofs + layout.element_size)
+            .context("out of range of its bufferView")?;
+        layout.encode(e, bytes)?;
+    }
+    Ok(())
+}
+
+/// Bytes of a buffer view, and its byte stride
+fn view<'a>(
+    root: &gltf_json::Root,
+    buffers: &'a [Vec<u8>],
+    view: usize,
+) -> Result<(&'a [u8], Option<usize>)> {
+    let v = root
+        .buffer_views
+        .get(view)
+        .with_context(|| format!("bufferView #{} does not exist", view))?;
+    let buffer = buffers
+        .get(v.buffer.value())
+        .with_context(|| format!("buffer #{} is not loaded", v.buffer.value()))?;
+    let ofs = v.byte_offset.unwrap_or(0) as usize;
+    let data = buffer
+        .get(ofs..ofs + v.byte_length as usize)
+        .with_context(|| format!("bufferView #{} is out of range of its buffer", view))?;
+    Ok((data, v.byte_stride.map(|s| s as usize)))
+}
+
+fn view_mut<'a>(
+    root: &gltf_json::Root,
+    buffers: &'a mut [Vec<u8>],
+    view: usize,
+) -> Result<(&'a mut [u8], Option<usize>)> {
+    let v = root
+        .buffer_views
+        .get(view)
+        .with_context(|| format!("bufferView #{} does not exist", view))?;
+    let buffer = buffers
+        .get_mut(v.buffer.value())
+        .with_context(|| format!("buffer #{} is not loaded", v.buffer.value()))?;
+    let ofs = v.byte_offset.unwrap_or(0) as usize;
+    let data = buffer
+        .get_mut(ofs..ofs + v.byte_length as usize)
+        .with_context(|| format!("bufferView #{} is out of range of its buffer", view))?;
+    Ok((data, v.byte_stride.map(|s| s as usize)))
+}
+
+/// Finds the bytes of a buffer view and its byte stride
+type ViewResolver<'a, 'b> = &'b dyn Fn(usize) -> Result<(&'a [u8], Option<usize>)>;
+
+/// Indices of the elements replaced by the sparse storage of an accessor, and their values
+fn read_sparse(
+    view: ViewResolver,
+    a: &gltf_json::Accessor,
+    layout: &Layout,
+) -> Result<Option<(Vec<usize>, Vec<f64>)>> {
+    let sparse = match &a.sparse {
+        Some(sparse) => sparse,
+        None => return Ok(None),
+    };
+    let count = sparse.count as usize;
+    let index_type = match sparse.indices.component_type {
+        Valid(ct) => ct.0,
+        _ => bail!("invalid componentType of sparse indices"),
+    };
+    let (data, _) = view(sparse.indices.buffer_view.value())?;
+    let indices = read_elements(
+        &Layout::new(index_type, Type::Scalar, false),
+        data,
+        sparse.indices.byte_offset as usize,
+        None,
+        count,
+    )
+    .context("sparse indices")?;
+    let (data, _) = view(sparse.values.buffer_view.value())?;
+    let values = read_elements(
+        layout,
+        data,
+        sparse.values.byte_offset as usize,
+        None,
+        count,
+    )
+    .context("sparse values")?;
+    let indices: Vec<usize> = indices.iter().map(|i| *i as usize).collect();
+    if let Some(i) = indices.iter().find(|i| **i >= a.count as usize) {
+        bail!("sparse index {} is out of range", i);
+    }
+    Ok(Some((indices, values)))
+}
+
+fn read_values_with(
+    root: &gltf_json::Root,
+    accessor: usize,
+    view: ViewResolver,
+) -> Result<(Layout, Vec<f64>)> {
+    let read = || -> Result<(Layout, Vec<f64>)> {
+        let a = root.accessors.get(accessor).context("does not exist")?;
+        let layout = Layout::of(a)?;
+        let count = a.count as usize;
+        let mut values = match a.buffer_view {
+            Some(v) => {
+                let (data, stride) = view(v.value())?;
+                read_elements(&layout, data, a.byte_offset as usize, stride, count)?
+            }
+            None => vec![0.0; count * layout.components],
+        };
+        if let Some((indices, sparse_values)) = read_sparse(view, a, &layout)? {
+            let n = layout.components;
+            for (i, v) in indices.iter().zip(sparse_values.chunks_exact(n)) {
+                values[i * n..(i + 1) * n].copy_from_slice(v);
+            }
+        }
+        Ok((layout, values))
+    };
+    read().with_context(|| format!("accessor #{}", accessor))
+}
+
+/// Read all the components of an accessor, with the sparse values applied.
+/// An accessor without bufferView is initialized with zeros as the spec says.
+pub fn read_values(
+    root: &gltf_json::Root,
+    buffers: &[Vec<u8>],
+    accessor: usize,
+) -> Result<(Layout, Vec<f64>)> {
+    read_values_with(root, accessor, &|v| view(root, buffers, v))
+}
+
+/// Same as `read_values`, but with the contents of each buffer view given separately
+pub fn read_values_in_views(
+    root: &gltf_json::Root,
+    views: &[Vec<u8>],
+    accessor: usize,
+) -> Result<(Layout, Vec<f64>)> {
+    read_values_with(root, accessor, &|v| {
+        let data = views
+            .get(v)
+            .with_context(|| format!("bufferView #{} does not exist", v))?;
+        let stride = root.buffer_views[v].byte_stride.map(|s| s as usize);
+        Ok((data.as_slice(), stride))
+    })
+}
+
+fn check_components(layout: &Layout, n: usize, accessor: usize) -> Result<()> {
+    if layout.components != n {
+        bail!(
+            "accessor #{} has {} components per element, expected {}",
+            accessor,
+            layout.components,
+            n
+        );
+    }
+    Ok(())
+}
+
+/// Read an accessor of `N` components per element as floats
+pub fn read_f32<const N: usize>(
+    root: &gltf_json::Root,
+    buffers: &[Vec<u8>],
+    accessor: usize,
+) -> Result<Vec<[f32; N]>> {
+    let (layout, values) = read_values(root, buffers, accessor)?;
+    check_components(&layout, N, accessor)?;
+    Ok(values
+        .chunks_exact(N)
+        .map(|e| {
+            let mut v = [0f32; N];
+            for (v, e) in v.iter_mut().zip(e) {
+                *v = *e as f32;
+            }
+            v
+        })
+        .collect())
+}
+
+/// Read an accessor of `N` unsigned integers per element, e.g. JOINTS_0
+pub fn read_u32<const N: usize>(
+    root: &gltf_json::Root,
+    buffers: &[Vec<u8>],
+    accessor: usize,
+) -> Result<Vec<[u32; N]>> {
+    let (layout, values) = read_values(root, buffers, accessor)?;
+    check_components(&layout, N, accessor)?;
+    if layout.normalized || layout.component_type == ComponentType::F32 {
+        bail!("accessor #{} does not hold integers", accessor);
+    }
+    if values.iter().any(|v| *v < 0.0) {
+        bail!("accessor #{} has negative values", accessor);
+    }
+    Ok(values
+        .chunks_exact(N)
+        .map(|e| {
+            let mut v = [0u32; N];
+            for (v, e) in v.iter_mut().zip(e) {
+                *v = *e as u32;
+            }
+            v
+        })
+        .collect())
+}
+
+/// Read the indices of a primitive
+pub fn read_indices(
+    root: &gltf_json::Root,
+    buffers: &[Vec<u8>],
+    accessor: usize,
+) -> Result<Vec<u32>> {
+    Ok(read_u32::<1>(root, buffers, accessor)?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// Apply `f` to every element of an accessor in place, including the sparse values.
+/// Elements shared by several accessors (e.g. through a common bufferView range) change for all of them.
+pub fn modify_f32(
+    root: &gltf_json::Root,
+    buffers: &mut [Vec<u8>],
+    accessor: usize,
+    f: &dyn Fn(&mut [f32]),
+) -> Result<()> {
+    let a = root.accessors.get(accessor).context("does not exist")?;
+    let layout = Layout::of(a)?;
+    let apply = |values: &mut Vec<f64>| {
+        let mut e = vec![0f32; layout.components];
+        for chunk in values.chunks_exact_mut(layout.components) {
+            for (e, v) in e.iter_mut().zip(chunk.iter()) {
+                *e = *v as f32;
+            }
+            f(&mut e);
+            for (v, e) in chunk.iter_mut().zip(e.iter()) {
+                *v = *e as f64;
+            }
+        }
+    };
+    let mut modify = || -> Result<()> {
+        if let Some(v) = a.buffer_view {
+            let (data, stride) = view_mut(root, buffers, v.value())?;
+            let offset = a.byte_offset as usize;
+            let mut values = read_elements(&layout, data, offset, stride, a.count as usize)?;
+            apply(&mut values);
+            write_elements(&layout, data, offset, stride, &values)?;
+        }
+        if let Some(sparse) = &a.sparse {
+            let (data, _) = view_mut(root, buffers, sparse.values.buffer_view.value())?;
+            let offset = sparse.values.byte_offset as usize;
+            let mut values = read_elements(&layout, data, offset, None, sparse.count as usize)?;
+            apply(&mut values);
+            write_elements(&layout, data, offset, None, &values)?;
+        }
+        Ok(())
+    };
+    modify().with_context(|| format!("accessor #{}", accessor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn root(buffer_views: serde_json::Value, accessors: serde_json::Value) -> gltf_json::Root {
+        serde_json::from_value(json!({
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": 64 }],
+            "bufferViews": buffer_views,
+            "accessors": accessors,
+        }))
+        .unwrap()
+    }
+
+    fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn byte_stride() {
+        // Two VEC3 positions interleaved with one more float each
+        let mut buffer = f32_bytes(&[1.0, 2.0, 3.0, 9.0, 4.0, 5.0, 6.0, 9.0]);
+        buffer.resize(64, 0);
+        let root = root(
+            json!([{ "buffer": 0, "byteLength": 32, "byteStride": 16 }]),
+            json!([{ "bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3" }]),
+        );
+        let values = read_f32::<3>(&root, &[buffer], 0).unwrap();
+        assert_eq!(values, vec![[1.0, 2.0, 3.0], [4.0, 5.0, 6.0]]);
+    }
+
+    #[test]
+    fn normalized_integers() {
+        let mut buffer = vec![0, 255, 51, 0, 0x80, 0x7f, 0x81, 0];
+        buffer.extend(u16::MAX.to_le_bytes());
+        buffer.resize(64, 0);
+        let root = root(
+            json!([{ "buffer": 0, "byteLength": 64 }]),
+            json!([
+                { "bufferView": 0, "componentType": 5121, "normalized": true, "count": 3, "type": "SCALAR" },
+                { "bufferView": 0, "byteOffset": 4, "componentType": 5120, "normalized": true, "count": 3, "type": "SCALAR" },
+                { "bufferView": 0, "byteOffset": 8, "componentType": 5123, "normalized": true, "count": 1, "type": "SCALAR" },
+            ]),
+        );
+        let buffers = [buffer];
+        assert_eq!(read_values(&root, &buffers, 0).unwrap().1, [0.0, 1.0, 0.2]);
+        // -128 and -127 are both -1
+        assert_eq!(
+            read_values(&root, &buffers, 1).unwrap().1,
+            [-1.0, 1.0, -1.0]
+        );
+        assert_eq!(read_values(&root, &buffers, 2).unwrap().1, [1.0]);
+        // Integers can't be read as normalized values and the other way around
+        assert!(read_u32::<1>(&root, &buffers, 0).is_err());
+    }
+
+    #[test]
+    fn matrix_columns_are_padded() {
+        let layout = Layout::new(ComponentType::U8, Type::Mat3, false);
+        assert_eq!(layout.element_size, 12);
+        let bytes = [1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9, 0];
+        let mut values = Vec::new();
+        layout.decode(&bytes, &mut values);
+        assert_eq!(values, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        let mut encoded = [0xff; 12];
+        layout.encode(&values, &mut encoded).unwrap();
+        assert_eq!(encoded[..3], bytes[..3]);
+        assert!(layout.encode(&[256.0; 9], &mut encoded).is_err());
+    }
+
+    #[test]
+    fn sparse_substitution() {
+        let mut buffer = f32_bytes(&[1.0, 2.0, 3.0, 4.0]);
+        buffer.extend([1, 0, 3, 0]);
+        buffer.extend(f32_bytes(&[20.0, 40.0]));
+        buffer.resize(64, 0);
+        let sparse = json!({
+            "count": 2,
+            "indices": { "bufferView": 1, "componentType": 5123 },
+            "values": { "bufferView": 2 },
+        });
+        let root = root(
+            json!([
+                { "buffer": 0, "byteLength": 16 },
+                { "buffer": 0, "byteOffset": 16, "byteLength": 4 },
+                { "buffer": 0, "byteOffset": 20, "byteLength": 8 },
+            ]),
+            json!([
+                { "bufferView": 0, "componentType": 5126, "count": 4, "type": "SCALAR", "sparse": sparse },
+                // Without bufferView, the elements that are not replaced are zeros
+                { "componentType": 5126, "count": 4, "type": "SCALAR", "sparse": sparse },
+            ]),
+        );
+        let buffers = [buffer];
+        assert_eq!(
+            read_values(&root, &buffers, 0).unwrap().1,
+            [1.0, 20.0, 3.0, 40.0]
+        );
+        assert_eq!(
+            read_values(&root, &buffers, 1).unwrap().1,
+            [0.0, 20.0, 0.0, 40.0]
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let buffer = vec![0; 64];
+        let root = root(
+            json!([
+                { "buffer": 0, "byteLength": 8 },
+                { "buffer": 0, "byteOffset": 60, "byteLength": 8 },
+                { "buffer": 0, "byteLength": 2 },
+            ]),
+            json!([
+                // 3 floats don't fit in 8 bytes
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "SCALAR" },
+                // The bufferView goes past the end of the buffer
+                { "bufferView": 1, "componentType": 5126, "count": 1, "type": "SCALAR" },
+                { "bufferView": 5, "componentType": 5126, "count": 1, "type": "SCALAR" },
+                // Sparse index 0 of an accessor of 0 elements
+                { "componentType": 5126, "count": 0, "type": "SCALAR", "sparse": {
+                    "count": 1,
+                    "indices": { "bufferView": 2, "componentType": 5123 },
+                    "values": { "bufferView": 0 },
+                } },
+            ]),
+        );
+        let buffers = [buffer];
+        // Accessor #4 does not exist
+        for accessor in 0..5 {
+            assert!(read_values(&root, &buffers, accessor).is_err());
+        }
+    }
+}
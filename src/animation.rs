@@ -0,0 +1,316 @@
+//! glTF animations of node transforms: sampling them into a [`Pose`], and writing baked rotations
+//! as a new animation. Morph target weights are not sampled.
+
+use crate::accessor;
+use crate::math;
+use crate::math::Quat;
+use crate::pose::Pose;
+use crate::Document;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::animation::Interpolation;
+use gltf_json::animation::Property;
+use gltf_json::validation::Checked::Valid;
+use serde_json::json;
+use serde_json::Value;
+
+/// A channel that animates the translation, rotation or scale of a node
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    /// Key frame times in seconds
+    pub times: Vec<f32>,
+    /// 3 (translation, scale) or 4 (rotation) values per key frame, or three times as many
+    /// for CUBICSPLINE: in-tangent, value and out-tangent
+    pub values: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+impl Animation {
+    /// Read the node transform channels of an animation
+    pub fn read(root: &gltf_json::Root, buffers: &[Vec<u8>], animation: usize) -> Result<Self> {
+        let a = root
+            .animations
+            .get(animation)
+            .with_context(|| format!("animation #{} does not exist", animation))?;
+        let mut channels = Vec::new();
+        for (i, c) in a.channels.iter().enumerate() {
+            let property = match c.target.path {
+                Valid(p @ (Property::Translation | Property::Rotation | Property::Scale)) => p,
+                _ => continue,
+            };
+            let sampler = a
+                .samplers
+                .get(c.sampler.value())
+                .with_context(|| format!("sampler of channel #{} does not exist", i))?;
+            let interpolation = match sampler.interpolation {
+                Valid(i) => i,
+                _ => bail!("interpolation of channel #{} is invalid", i),
+            };
+            let times: Vec<f32> = accessor::read_f32::<1>(root, buffers, sampler.input.value())?
+                .into_iter()
+                .flatten()
+                .collect();
+            let values: Vec<f32> = match property {
+                Property::Rotation => {
+                    accessor::read_f32::<4>(root, buffers, sampler.output.value())?
+                        .into_iter()
+                        .flatten()
+                        .collect()
+                }
+                _ => accessor::read_f32::<3>(root, buffers, sampler.output.value())?
+                    .into_iter()
+                    .flatten()
+                    .collect(),
+            };
+            let channel = Channel {
+                node: c.target.node.value(),
+                property,
+                interpolation,
+                times,
+                values,
+            };
+            if channel.times.is_empty() {
+                bail!("channel #{} has no key frames", i);
+            }
+            if channel.values.len() != channel.times.len() * channel.stride() {
+                bail!(
+                    "channel #{} has {} key frames but {} values",
+                    i,
+                    channel.times.len(),
+                    channel.values.len()
+                );
+            }
+            channels.push(channel);
+        }
+        Ok(Self {
+            name: a.name.clone(),
+            channels,
+        })
+    }
+    /// Time of the last key frame
+    pub fn duration(&self) -> f32 {
+        self.channels
+            .iter()
+            .filter_map(|c| c.times.last().copied())
+            .fold(0.0, f32::max)
+    }
+    /// Set the animated transforms of the nodes at `time`. Other properties are left as they are.
+    pub fn apply(&self, time: f32, pose: &mut Pose) {
+        for c in &self.channels {
+            let t = match pose.local.get_mut(c.node) {
+                Some(t) => t,
+                None => continue,
+            };
+            match c.property {
+                Property::Translation => t.translation = c.sample::<3>(time),
+                Property::Scale => t.scale = c.sample::<3>(time),
+                _ => t.rotation = math::quat_normalize(c.sample::<4>(time)),
+            }
+        }
+    }
+}
+
+impl Channel {
+    fn components(&self) -> usize {
+        match self.property {
+            Property::Rotation => 4,
+            _ => 3,
+        }
+    }
+    /// Number of values per key frame
+    fn stride(&self) -> usize {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.components() * 3,
+            _ => self.components(),
+        }
+    }
+    fn key<const N: usize>(&self, frame: usize, element: usize) -> [f32; N] {
+        let offset = frame * self.stride() + element * N;
+        let mut v = [0.0; N];
+        v.copy_from_slice(&self.values[offset..offset + N]);
+        v
+    }
+    /// The value at `time`, holding the first and last key frames outside of them
+    pub fn sample<const N: usize>(&self, time: f32) -> [f32; N] {
+        // The value itself is the second element of a CUBICSPLINE key frame
+        let value = match self.interpolation {
+            Interpolation::CubicSpline => 1,
+            _ => 0,
+        };
+        let last = self.times.len() - 1;
+        if time <= self.times[0] {
+            return self.key(0, value);
+        }
+        if time >= self.times[last] {
+            return self.key(last, value);
+        }
+        let k = self.times.partition_point(|t| *t <= time) - 1;
+        let dt = self.times[k + 1] - self.times[k];
+        let s = (time - self.times[k]) / dt;
+        match self.interpolation {
+            Interpolation::Step => self.key(k, 0),
+            Interpolation::Linear => {
+                let (a, b) = (self.key::<N>(k, 0), self.key::<N>(k + 1, 0));
+                let mut v = [0.0; N];
+                if N == 4 {
+                    v.copy_from_slice(&math::quat_slerp(
+                        [a[0], a[1], a[2], a[3]],
+                        [b[0], b[1], b[2], b[3]],
+                        s,
+                    ));
+                } else {
+                    for (v, (a, b)) in v.iter_mut().zip(a.iter().zip(b.iter())) {
+                        *v = a + (b - a) * s;
+                    }
+                }
+                v
+            }
+            Interpolation::CubicSpline => {
+                let (v0, b0) = (self.key::<N>(k, 1), self.key::<N>(k, 2));
+                let (a1, v1) = (self.key::<N>(k + 1, 0), self.key::<N>(k + 1, 1));
+                let (s2, s3) = (s * s, s * s * s);
+                let mut v = [0.0; N];
+                for i in 0..N {
+                    v[i] = (2.0 * s3 - 3.0 * s2 + 1.0) * v0[i]
+                        + (s3 - 2.0 * s2 + s) * dt * b0[i]
+                        + (-2.0 * s3 + 3.0 * s2) * v1[i]
+                        + (s3 - s2) * dt * a1[i];
+                }
+                v
+            }
+        }
+    }
+}
+
+/// Rotations of a node at every time of a baked animation
+#[derive(Clone, Debug)]
+pub struct RotationTrack {
+    pub node: usize,
+    pub rotations: Vec<Quat>,
+}
+
+/// Write `tracks` sampled at `times` as a new animation with LINEAR interpolation, and return its index.
+/// The channels of animation `base` are copied into it except the ones the tracks replace,
+/// so that the new animation plays the whole motion by itself.
+pub fn write_rotation_tracks(
+    doc: &mut Document,
+    name: &str,
+    times: &[f32],
+    tracks: &[RotationTrack],
+    base: Option<usize>,
+) -> Result<usize> {
+    let mut channels = Vec::new();
+    let mut samplers = Vec::new();
+    if let Some(base) = base {
+        let a = doc.json["animations"]
+            .get(base)
+            .with_context(|| format!("animation #{} does not exist", base))?;
+        let base_samplers = a["samplers"].as_array().cloned().unwrap_or_default();
+        for c in a["channels"].as_array().into_iter().flatten() {
+            let replaced = c["target"]["path"] == "rotation"
+                && tracks
+                    .iter()
+                    .any(|t| c["target"]["node"].as_u64() == Some(t.node as u64));
+            let sampler = c["sampler"]
+                .as_u64()
+                .and_then(|s| base_samplers.get(s as usize));
+            if let (false, Some(sampler)) = (replaced, sampler) {
+                let mut c = c.clone();
+                c["sampler"] = json!(samplers.len());
+                samplers.push(sampler.clone());
+                channels.push(c);
+            }
+        }
+    }
+    let input = doc.push_accessor_f32(&times.iter().map(|t| [*t]).collect::<Vec<_>>(), true);
+    for t in tracks {
+        if t.rotations.len() != times.len() {
+            bail!(
+                "node #{} has {} rotations for {} times",
+                t.node,
+                t.rotations.len(),
+                times.len()
+            );
+        }
+        let output = doc.push_accessor_f32(&t.rotations, false);
+        channels.push(json!({
+            "sampler": samplers.len(),
+            "target": { "node": t.node, "path": "rotation" },
+        }));
+        samplers.push(json!({ "input": input, "interpolation": "LINEAR", "output": output }));
+    }
+    let json = &mut doc.json;
+    if !json["animations"].is_array() {
+        json["animations"] = Value::Array(Vec::new());
+    }
+    let animations = json["animations"].as_array_mut().unwrap();
+    animations.push(json!({ "name": name, "channels": channels, "samplers": samplers }));
+    Ok(animations.len() - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(interpolation: Interpolation, times: Vec<f32>, values: Vec<f32>) -> Channel {
+        Channel {
+            node: 0,
+            property: Property::Translation,
+            interpolation,
+            times,
+            values,
+        }
+    }
+
+    #[test]
+    fn step() {
+        let c = channel(
+            Interpolation::Step,
+            vec![1.0, 2.0],
+            vec![0.0, 0.0, 0.0, 10.0, 20.0, 30.0],
+        );
+        assert_eq!(c.sample::<3>(0.5), [0.0, 0.0, 0.0]);
+        assert_eq!(c.sample::<3>(1.5), [0.0, 0.0, 0.0]);
+        assert_eq!(c.sample::<3>(2.0), [10.0, 20.0, 30.0]);
+        assert_eq!(c.sample::<3>(2.5), [10.0, 20.0, 30.0]);
+    }
+
+    #[test]
+    fn linear() {
+        let c = channel(
+            Interpolation::Linear,
+            vec![1.0, 2.0],
+            vec![0.0, 0.0, 0.0, 10.0, 20.0, 30.0],
+        );
+        assert_eq!(c.sample::<3>(0.5), [0.0, 0.0, 0.0]);
+        assert_eq!(c.sample::<3>(1.25), [2.5, 5.0, 7.5]);
+        assert_eq!(c.sample::<3>(2.5), [10.0, 20.0, 30.0]);
+    }
+
+    #[test]
+    fn cubic_spline() {
+        // In-tangent, value and out-tangent of two key frames. The in-tangent of the first and
+        // the out-tangent of the last are not used.
+        let c = channel(
+            Interpolation::CubicSpline,
+            vec![0.0, 2.0],
+            vec![
+                9.0, 9.0, 9.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, //
+                0.0, 0.0, 0.0, 2.0, 4.0, 0.0, 9.0, 9.0, 9.0,
+            ],
+        );
+        assert_eq!(c.sample::<3>(-1.0), [0.0, 0.0, 0.0]);
+        // With zero tangents, 3s^2 - 2s^3 of the way at s = 0.25
+        assert_eq!(c.sample::<3>(0.5), [0.3125, 0.625, 0.0]);
+        assert_eq!(c.sample::<3>(3.0), [2.0, 4.0, 0.0]);
+    }
+}
@@ -0,0 +1,850 @@
+use crate::image::ImageFormat;
+use crate::math;
+use crate::model::Model;
+use crate::mtoon::MToon;
+use crate::pose::Transform;
+use crate::scene::Material;
+use crate::scene::Node;
+use crate::scene::Primitive;
+use crate::scene::SceneBuilder;
+use crate::skin;
+use crate::spring_bone;
+use crate::vrm;
+use crate::Document;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::material::AlphaMode;
+use gltf_json::validation::Checked;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// An avatar described as a text file that can be reviewed in version control.
+/// Objects refer to each other by name instead of by index.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct AvatarSource {
+    pub meta: vrm::Meta,
+    #[serde(default)]
+    pub textures: Vec<TextureSource>,
+    #[serde(default)]
+    pub materials: Vec<MaterialSource>,
+    #[serde(default)]
+    pub meshes: Vec<MeshSource>,
+    /// Root nodes of the scene
+    pub nodes: Vec<NodeSource>,
+    /// Humanoid bone name (e.g. "leftUpperArm") to node name
+    pub humanoid: BTreeMap<String, String>,
+    #[serde(default)]
+    pub spring_bone: Option<SpringBoneSource>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TextureSource {
+    pub name: String,
+    /// Path to the image file, relative to the source file
+    pub uri: String,
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+fn default_roughness() -> f32 {
+    0.9
+}
+fn default_one() -> f32 {
+    1.0
+}
+fn default_alpha_cutoff() -> f32 {
+    0.5
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MaterialSource {
+    pub name: String,
+    #[serde(default = "default_color")]
+    pub base_color_factor: [f32; 4],
+    /// Name of a texture
+    #[serde(default)]
+    pub base_color_texture: Option<String>,
+    #[serde(default)]
+    pub metallic_factor: f32,
+    #[serde(default = "default_roughness")]
+    pub roughness_factor: f32,
+    /// Names of textures
+    #[serde(default)]
+    pub metallic_roughness_texture: Option<String>,
+    #[serde(default)]
+    pub normal_texture: Option<String>,
+    #[serde(default = "default_one")]
+    pub normal_scale: f32,
+    #[serde(default)]
+    pub occlusion_texture: Option<String>,
+    #[serde(default = "default_one")]
+    pub occlusion_strength: f32,
+    #[serde(default)]
+    pub emissive_factor: [f32; 3],
+    #[serde(default)]
+    pub emissive_texture: Option<String>,
+    /// "OPAQUE", "MASK" or "BLEND"
+    #[serde(default)]
+    pub alpha_mode: Checked<AlphaMode>,
+    #[serde(default = "default_alpha_cutoff")]
+    pub alpha_cutoff: f32,
+    #[serde(default)]
+    pub double_sided: bool,
+    /// Written as KHR_materials_unlit
+    #[serde(default)]
+    pub unlit: bool,
+    /// VRMC_materials_mtoon, with textures given by their indices in `textures`
+    #[serde(default)]
+    pub mtoon: Option<MToon>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MeshSource {
+    pub name: String,
+    /// Names of the nodes the vertices are bound to. Each node using the mesh gets a skin
+    /// over them, bound in the pose of the source.
+    #[serde(default)]
+    pub joints: Vec<String>,
+    pub primitives: Vec<PrimitiveSource>,
+}
+
+/// Triangles given either as vertex arrays, as shapes, or both.
+/// Normals are calculated from the triangles if omitted.
+/// In a mesh with joints, the vertices are bound with `joints` and `weights`, or all to `joint`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PrimitiveSource {
+    #[serde(default)]
+    pub positions: Vec<[f32; 3]>,
+    #[serde(default)]
+    pub normals: Vec<[f32; 3]>,
+    #[serde(default)]
+    pub tex_coords: Vec<[f32; 2]>,
+    #[serde(default)]
+    pub indices: Vec<[u32; 3]>,
+    #[serde(default)]
+    pub shapes: Vec<ShapeSource>,
+    /// Name of a material
+    #[serde(default)]
+    pub material: Option<String>,
+    /// JOINTS_0, indices into `joints` of the mesh
+    #[serde(default)]
+    pub joints: Vec<[u32; 4]>,
+    /// WEIGHTS_0
+    #[serde(default)]
+    pub weights: Vec<[f32; 4]>,
+    /// Name of a joint of the mesh that moves all the vertices, including those of the shapes
+    #[serde(default)]
+    pub joint: Option<String>,
+}
+
+fn default_segments() -> u32 {
+    16
+}
+fn default_rings() -> u32 {
+    8
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub enum ShapeSource {
+    Box {
+        center: [f32; 3],
+        size: [f32; 3],
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        #[serde(default = "default_segments")]
+        segments: u32,
+        #[serde(default = "default_rings")]
+        rings: u32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct NodeSource {
+    pub name: String,
+    #[serde(default)]
+    pub translation: Option<[f32; 3]>,
+    /// Quaternion in (x, y, z, w) order
+    #[serde(default)]
+    pub rotation: Option<[f32; 4]>,
+    #[serde(default)]
+    pub scale: Option<[f32; 3]>,
+    /// Name of a mesh
+    #[serde(default)]
+    pub mesh: Option<String>,
+    #[serde(default)]
+    pub children: Vec<NodeSource>,
+}
+
+/// VRMC_springBone. Colliders are written in the groups that use them.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SpringBoneSource {
+    #[serde(default)]
+    pub collider_groups: Vec<ColliderGroupSource>,
+    #[serde(default)]
+    pub springs: Vec<SpringSource>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ColliderGroupSource {
+    pub name: String,
+    pub colliders: Vec<ColliderSource>,
+}
+
+/// A collider needs `shape`, `extendedShape` (VRMC_springBone_extended_collider) or both.
+/// Without `shape`, the fallback of the extended shape is used as the base shape.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ColliderSource {
+    /// Name of a node
+    pub node: String,
+    #[serde(default)]
+    pub shape: Option<spring_bone::Shape>,
+    #[serde(default)]
+    pub extended_shape: Option<spring_bone::ExtendedShape>,
+}
+
+fn default_gravity_dir() -> [f32; 3] {
+    [0.0, -1.0, 0.0]
+}
+fn default_drag_force() -> f32 {
+    0.5
+}
+
+/// A spring. The settings apply to all the joints unless a joint has its own.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SpringSource {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Node names or joints, from the root of the chain
+    pub joints: Vec<JointEntry>,
+    #[serde(default)]
+    pub hit_radius: f32,
+    #[serde(default = "default_one")]
+    pub stiffness: f32,
+    #[serde(default)]
+    pub gravity_power: f32,
+    #[serde(default = "default_gravity_dir")]
+    pub gravity_dir: [f32; 3],
+    #[serde(default = "default_drag_force")]
+    pub drag_force: f32,
+    /// Names of collider groups
+    #[serde(default)]
+    pub collider_groups: Vec<String>,
+    /// Name of a node
+    #[serde(default)]
+    pub center: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum JointEntry {
+    Node(String),
+    Joint(JointSource),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct JointSource {
+    pub node: String,
+    #[serde(default)]
+    pub hit_radius: Option<f32>,
+    #[serde(default)]
+    pub stiffness: Option<f32>,
+    #[serde(default)]
+    pub gravity_power: Option<f32>,
+    #[serde(default)]
+    pub gravity_dir: Option<[f32; 3]>,
+    #[serde(default)]
+    pub drag_force: Option<f32>,
+}
+
+impl SpringSource {
+    fn to_joint(
+        &self,
+        entry: &JointEntry,
+        node_names: &HashMap<String, u32>,
+    ) -> Result<spring_bone::SpringJoint> {
+        let (node, joint) = match entry {
+            JointEntry::Node(node) => (node, None),
+            JointEntry::Joint(j) => (&j.node, Some(j)),
+        };
+        Ok(spring_bone::SpringJoint {
+            node: lookup(node_names, "node", node)?,
+            hit_radius: joint.and_then(|j| j.hit_radius).unwrap_or(self.hit_radius),
+            stiffness: joint.and_then(|j| j.stiffness).unwrap_or(self.stiffness),
+            gravity_power: joint
+                .and_then(|j| j.gravity_power)
+                .unwrap_or(self.gravity_power),
+            gravity_dir: joint
+                .and_then(|j| j.gravity_dir)
+                .unwrap_or(self.gravity_dir),
+            drag_force: joint.and_then(|j| j.drag_force).unwrap_or(self.drag_force),
+            extensions: None,
+            extras: None,
+        })
+    }
+}
+
+impl SpringBoneSource {
+    fn to_spring_bone(&self, node_names: &HashMap<String, u32>) -> Result<spring_bone::SpringBone> {
+        let mut spring_bone = spring_bone::SpringBone::default();
+        let group_names = index_by_name(
+            "collider group",
+            self.collider_groups.iter().map(|g| g.name.as_str()),
+        )?;
+        for g in &self.collider_groups {
+            let mut colliders = Vec::new();
+            for c in &g.colliders {
+                let node = lookup(node_names, "node", &c.node)?;
+                let collider = match (c.shape, c.extended_shape) {
+                    (Some(shape), extended_shape) => {
+                        let mut collider = spring_bone::Collider::new(node, shape);
+                        collider.set_extended_shape(extended_shape);
+                        collider
+                    }
+                    (None, Some(extended_shape)) => {
+                        spring_bone::Collider::new_extended(node, extended_shape)
+                    }
+                    (None, None) => bail!(
+                        "A collider of collider group {:?} has neither shape nor extendedShape",
+                        g.name
+                    ),
+                };
+                colliders.push(spring_bone.colliders.len() as u32);
+                spring_bone.colliders.push(collider);
+            }
+            spring_bone
+                .collider_groups
+                .push(spring_bone::ColliderGroup {
+                    name: Some(g.name.clone()),
+                    colliders,
+                    ..Default::default()
+                });
+        }
+        for s in &self.springs {
+            let joints = s
+                .joints
+                .iter()
+                .map(|j| s.to_joint(j, node_names))
+                .collect::<Result<_>>()?;
+            let collider_groups = s
+                .collider_groups
+                .iter()
+                .map(|g| lookup(&group_names, "collider group", g))
+                .collect::<Result<_>>()?;
+            let center = match &s.center {
+                Some(name) => Some(lookup(node_names, "node", name)?),
+                None => None,
+            };
+            spring_bone.springs.push(spring_bone::Spring {
+                name: s.name.clone(),
+                joints,
+                collider_groups,
+                center,
+                extensions: None,
+                extras: None,
+            });
+        }
+        Ok(spring_bone)
+    }
+}
+
+impl AvatarSource {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.to_string_lossy()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse {}", path.to_string_lossy()))
+    }
+}
+
+/// Calculate smooth vertex normals by averaging the normals of adjacent triangles
+fn calc_normals(vertices: &[[f32; 3]], indices: &[[u32; 3]]) -> Vec<[f32; 3]> {
+    let mut normals = vec![[0f32; 3]; vertices.len()];
+    for t in indices {
+        let [a, b, c] = t.map(|i| vertices[i as usize]);
+        let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let n = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        for i in t {
+            for (e, n) in normals[*i as usize].iter_mut().zip(n) {
+                *e += n;
+            }
+        }
+    }
+    for n in normals.iter_mut() {
+        let len = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        if len > 0.0 {
+            *n = n.map(|e| e / len);
+        } else {
+            *n = [0.0, 1.0, 0.0];
+        }
+    }
+    normals
+}
+
+/// JOINTS_0 and WEIGHTS_0 of the vertices of a primitive
+type Skinning = (Vec<[u32; 4]>, Vec<[f32; 4]>);
+
+impl PrimitiveSource {
+    /// Resolve the shapes and missing normals into plain vertex arrays
+    fn to_model(&self) -> Result<(Model, Vec<[f32; 2]>)> {
+        if !self.normals.is_empty() && self.normals.len() != self.positions.len() {
+            bail!(
+                "{} normals are given for {} positions",
+                self.normals.len(),
+                self.positions.len()
+            );
+        }
+        if !self.tex_coords.is_empty() && self.tex_coords.len() != self.positions.len() {
+            bail!(
+                "{} texCoords are given for {} positions",
+                self.tex_coords.len(),
+                self.positions.len()
+            );
+        }
+        if !self.tex_coords.is_empty() && !self.shapes.is_empty() {
+            bail!("texCoords can not be combined with shapes");
+        }
+        if !self.joints.is_empty() && self.joints.len() != self.positions.len() {
+            bail!(
+                "{} joints are given for {} positions",
+                self.joints.len(),
+                self.positions.len()
+            );
+        }
+        if self.weights.len() != self.joints.len() {
+            bail!(
+                "{} weights are given for {} joints",
+                self.weights.len(),
+                self.joints.len()
+            );
+        }
+        if !self.joints.is_empty() && !self.shapes.is_empty() {
+            bail!("joints can not be combined with shapes. Use joint instead");
+        }
+        if let Some(i) = self
+            .indices
+            .flatten()
+            .iter()
+            .find(|i| **i as usize >= self.positions.len())
+        {
+            bail!(
+                "index {} is out of range of {} positions",
+                i,
+                self.positions.len()
+            );
+        }
+        let mut model = Model {
+            vertices: self.positions.clone(),
+            normals: if self.normals.is_empty() {
+                calc_normals(&self.positions, &self.indices)
+            } else {
+                self.normals.clone()
+            },
+            indices: self.indices.clone(),
+        };
+        for s in &self.shapes {
+            match *s {
+                ShapeSource::Box { center, size } => model.add_box(center, size),
+                ShapeSource::Sphere {
+                    center,
+                    radius,
+                    segments,
+                    rings,
+                } => model.add_sphere(center, radius, segments, rings),
+            };
+        }
+        if model.indices.is_empty() {
+            bail!("primitive has no triangles");
+        }
+        Ok((model, self.tex_coords.clone()))
+    }
+    /// JOINTS_0 and WEIGHTS_0 of the `vertex_count` vertices of the model, if skinned
+    fn skinning(&self, mesh_joints: &[String], vertex_count: usize) -> Result<Option<Skinning>> {
+        let skinning = match (&self.joint, self.joints.is_empty()) {
+            (Some(_), false) => bail!("joint and joints can not be given together"),
+            (Some(name), true) => {
+                let joint = mesh_joints
+                    .iter()
+                    .position(|j| j == name)
+                    .with_context(|| format!("{:?} is not a joint of the mesh", name))?;
+                Some((
+                    vec![[joint as u32, 0, 0, 0]; vertex_count],
+                    vec![[1.0, 0.0, 0.0, 0.0]; vertex_count],
+                ))
+            }
+            (None, false) => {
+                if let Some(j) = self
+                    .joints
+                    .flatten()
+                    .iter()
+                    .find(|j| **j as usize >= mesh_joints.len())
+                {
+                    bail!(
+                        "joint {} is out of range of {} joints",
+                        j,
+                        mesh_joints.len()
+                    );
+                }
+                Some((self.joints.clone(), self.weights.clone()))
+            }
+            (None, true) => None,
+        };
+        match (mesh_joints.is_empty(), skinning.is_some()) {
+            (true, true) => bail!("the mesh has no joints"),
+            (false, false) => bail!("the mesh has joints but the primitive has no joint(s)"),
+            _ => Ok(skinning),
+        }
+    }
+}
+
+fn lookup(names: &HashMap<String, u32>, kind: &str, name: &str) -> Result<u32> {
+    names
+        .get(name)
+        .copied()
+        .with_context(|| format!("{} {:?} is not defined", kind, name))
+}
+
+fn index_by_name<'a>(
+    kind: &str,
+    names: impl Iterator<Item = &'a str>,
+) -> Result<HashMap<String, u32>> {
+    let mut map = HashMap::new();
+    for (i, name) in names.enumerate() {
+        if map.insert(name.to_string(), i as u32).is_some() {
+            bail!("{} {:?} is defined more than once", kind, name);
+        }
+    }
+    Ok(map)
+}
+
+/// The inverse bind matrix of a joint, which maps the space of the node of the mesh to the
+/// space of the joint
+fn inverse_bind_matrix(joint: &Transform, mesh: &Transform) -> [f32; 16] {
+    let map = |p| joint.inverse_transform_point(mesh.transform_point(p));
+    let origin = map([0.0; 3]);
+    let mut m = skin::IDENTITY_MATRIX;
+    for (i, axis) in [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+        .into_iter()
+        .enumerate()
+    {
+        m[i * 4..i * 4 + 3].copy_from_slice(&math::sub(map(axis), origin));
+    }
+    m[12..15].copy_from_slice(&origin);
+    m
+}
+
+/// World transform and mesh of a node added to the scene
+struct PlacedNode {
+    world: Transform,
+    mesh: Option<u32>,
+}
+
+fn add_node(
+    src: &NodeSource,
+    parent: Option<u32>,
+    scene: &mut SceneBuilder,
+    node_names: &mut HashMap<String, u32>,
+    mesh_names: &HashMap<String, u32>,
+    placed: &mut BTreeMap<u32, PlacedNode>,
+) -> Result<()> {
+    let mesh = match &src.mesh {
+        Some(name) => Some(lookup(mesh_names, "mesh", name)?),
+        None => None,
+    };
+    let idx = scene.add_node(
+        &Node {
+            name: Some(src.name.clone()),
+            translation: src.translation,
+            rotation: src.rotation,
+            scale: src.scale,
+            mesh,
+            skin: None,
+        },
+        parent,
+    )?;
+    if node_names.insert(src.name.clone(), idx).is_some() {
+        bail!("node {:?} is defined more than once", src.name);
+    }
+    let local = Transform {
+        translation: src.translation.unwrap_or([0.0; 3]),
+        rotation: src
+            .rotation
+            .map(math::quat_normalize)
+            .unwrap_or(math::IDENTITY_QUAT),
+        scale: src.scale.unwrap_or([1.0; 3]),
+    };
+    let parent_world = parent
+        .and_then(|p| placed.get(&p))
+        .map(|p| p.world)
+        .unwrap_or_default();
+    placed.insert(
+        idx,
+        PlacedNode {
+            world: parent_world.then(&local),
+            mesh,
+        },
+    );
+    for c in &src.children {
+        add_node(c, Some(idx), scene, node_names, mesh_names, placed)?;
+    }
+    Ok(())
+}
+
+/// Build the glTF JSON (including the VRMC_vrm extension) and the BIN chunk of an avatar.
+/// Paths of textures are resolved relative to `base_dir`.
+pub fn compile(src: &AvatarSource, base_dir: &Path) -> Result<Document> {
+    let mut scene = SceneBuilder::new();
+
+    //
+    // Textures
+    //
+    let texture_names = index_by_name("texture", src.textures.iter().map(|t| t.name.as_str()))?;
+    for t in &src.textures {
+        let path = base_dir.join(&t.uri);
+        let format = ImageFormat::from_path(&path)
+            .ok_or_else(|| anyhow!("Unsupported image type: {}", path.to_string_lossy()))?;
+        let data =
+            fs::read(&path).with_context(|| format!("Failed to read texture {:?}", t.name))?;
+        if ImageFormat::detect(&data) != Some(format) {
+            bail!(
+                "{} is not an {} file",
+                path.to_string_lossy(),
+                format.mime_type()
+            );
+        }
+        scene.add_texture(Some(&t.name), format, &data);
+    }
+
+    //
+    // Materials
+    //
+    let material_names = index_by_name("material", src.materials.iter().map(|m| m.name.as_str()))?;
+    for m in &src.materials {
+        let texture = |name: &Option<String>| -> Result<Option<u32>> {
+            match name {
+                Some(name) => Ok(Some(lookup(&texture_names, "texture", name)?)),
+                None => Ok(None),
+            }
+        };
+        let alpha_mode = match m.alpha_mode {
+            Checked::Valid(mode) => mode,
+            Checked::Invalid => bail!("alphaMode of material {} is invalid", m.name),
+        };
+        scene.add_material(&Material {
+            name: Some(m.name.clone()),
+            base_color_factor: m.base_color_factor,
+            base_color_texture: texture(&m.base_color_texture)?,
+            texture_transform: None,
+            metallic_factor: m.metallic_factor,
+            roughness_factor: m.roughness_factor,
+            metallic_roughness_texture: texture(&m.metallic_roughness_texture)?,
+            normal_texture: texture(&m.normal_texture)?,
+            normal_scale: m.normal_scale,
+            occlusion_texture: texture(&m.occlusion_texture)?,
+            occlusion_strength: m.occlusion_strength,
+            emissive_factor: m.emissive_factor,
+            emissive_texture: texture(&m.emissive_texture)?,
+            alpha_mode,
+            alpha_cutoff: m.alpha_cutoff,
+            double_sided: m.double_sided,
+            unlit: m.unlit,
+            mtoon: m.mtoon.clone(),
+        })?;
+    }
+
+    //
+    // Meshes
+    //
+    let mesh_names = index_by_name("mesh", src.meshes.iter().map(|m| m.name.as_str()))?;
+    for m in &src.meshes {
+        let mut models = Vec::new();
+        for (i, p) in m.primitives.iter().enumerate() {
+            let material = match &p.material {
+                Some(name) => Some(lookup(&material_names, "material", name)?),
+                None => None,
+            };
+            let context = || format!("Invalid primitive {} of mesh {:?}", i, m.name);
+            let (model, tex_coords) = p.to_model().with_context(context)?;
+            let skinning = p
+                .skinning(&m.joints, model.vertices.len())
+                .with_context(context)?;
+            models.push((model, tex_coords, material, skinning));
+        }
+        let primitives: Vec<_> = models
+            .iter()
+            .map(|(model, tex_coords, material, skinning)| Primitive {
+                vertices: &model.vertices,
+                normals: &model.normals,
+                tex_coords: (!tex_coords.is_empty()).then_some(tex_coords.as_slice()),
+                indices: &model.indices,
+                material: *material,
+                joints: skinning.as_ref().map(|(j, _)| j.as_slice()),
+                weights: skinning.as_ref().map(|(_, w)| w.as_slice()),
+                ..Default::default()
+            })
+            .collect();
+        scene.add_mesh(Some(&m.name), &primitives)?;
+    }
+
+    //
+    // Nodes
+    //
+    let mut node_names = HashMap::new();
+    let mut placed = BTreeMap::new();
+    for n in &src.nodes {
+        add_node(
+            n,
+            None,
+            &mut scene,
+            &mut node_names,
+            &mesh_names,
+            &mut placed,
+        )?;
+    }
+
+    //
+    // Skins
+    //
+    for (node, p) in &placed {
+        let mesh = match p.mesh {
+            Some(m) => &src.meshes[m as usize],
+            None => continue,
+        };
+        if mesh.joints.is_empty() {
+            continue;
+        }
+        let mut joints = Vec::new();
+        let mut inverse_bind_matrices = Vec::new();
+        for name in &mesh.joints {
+            let joint = lookup(&node_names, "node", name)?;
+            joints.push(joint);
+            inverse_bind_matrices.push(inverse_bind_matrix(&placed[&joint].world, &p.world));
+        }
+        let skin = scene.add_node_skin(&joints, &inverse_bind_matrices)?;
+        scene.set_skin(*node, skin)?;
+    }
+
+    //
+    // VRM
+    //
+    let mut humanoid = vrm::Humanoid::default();
+    for (bone, node) in &src.humanoid {
+        humanoid.set(bone, lookup(&node_names, "node", node)?)?;
+    }
+    let missing = humanoid.missing_required_bones();
+    if !missing.is_empty() {
+        bail!("Required humanoid bones are not mapped: {:?}", missing);
+    }
+    if src.meta.authors.is_empty() {
+        bail!("meta.authors must have at least one author");
+    }
+    let mut doc = scene.build()?;
+    doc.set_vrm(&vrm::Vrm::new(src.meta.clone(), humanoid))?;
+    if let Some(spring_bone) = &src.spring_bone {
+        doc.set_spring_bone(&spring_bone.to_spring_bone(&node_names)?)?;
+    }
+    Ok(doc)
+}
+
+/// Compile an avatar source file. Textures are looked up relative to the file.
+pub fn compile_file(source: &Path) -> Result<Document> {
+    let src = AvatarSource::load(source)?;
+    let base_dir = source.parent().unwrap_or_else(|| Path::new(""));
+    compile(&src, base_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate;
+    use crate::validate::Severity;
+    use serde_json::json;
+
+    #[test]
+    fn sample_is_valid_vrm() {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples/avatar.json");
+        let doc = compile_file(&source).unwrap();
+        validate::check_glb(&doc.to_vec().unwrap()).unwrap();
+        let errors: Vec<String> = validate::validate(&doc)
+            .iter()
+            .filter(|i| i.severity == Severity::Error)
+            .map(|i| i.to_string())
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+        // The body is skinned to the humanoid bones
+        let root = doc.root().unwrap();
+        let body = root
+            .nodes
+            .iter()
+            .find(|n| n.name.as_deref() == Some("Body"))
+            .unwrap();
+        let skin = &root.skins[body.skin.unwrap().value()];
+        assert_eq!(skin.joints.len(), 6);
+    }
+
+    #[test]
+    fn inverse_bind_matrix_keeps_the_mesh_in_place() {
+        let joint = Transform {
+            translation: [0.0, 1.0, 0.0],
+            rotation: math::quat_from_axis_angle([0.0, 1.0, 0.0], std::f32::consts::FRAC_PI_2),
+            scale: [2.0; 3],
+        };
+        let mesh = Transform {
+            translation: [1.0, 0.0, 0.0],
+            ..Default::default()
+        };
+        let m = inverse_bind_matrix(&joint, &mesh);
+        let p = [0.5, 0.25, -1.0];
+        let bound = [0, 1, 2].map(|i| m[i] * p[0] + m[4 + i] * p[1] + m[8 + i] * p[2] + m[12 + i]);
+        let skinned = joint.transform_point(bound);
+        let expected = mesh.transform_point(p);
+        for i in 0..3 {
+            assert!((skinned[i] - expected[i]).abs() < 1e-5, "{:?}", skinned);
+        }
+    }
+
+    #[test]
+    fn skinning_of_primitives() {
+        let joints = ["Hips".to_string(), "Head".to_string()];
+        let rigid: PrimitiveSource = serde_json::from_value(json!({ "joint": "Head" })).unwrap();
+        let (j, w) = rigid.skinning(&joints, 2).unwrap().unwrap();
+        assert_eq!(j, vec![[1, 0, 0, 0]; 2]);
+        assert_eq!(w, vec![[1.0, 0.0, 0.0, 0.0]; 2]);
+        assert!(rigid.skinning(&[], 2).is_err());
+
+        let unbound: PrimitiveSource = serde_json::from_value(json!({})).unwrap();
+        assert!(unbound.skinning(&joints, 2).is_err());
+        assert!(unbound.skinning(&[], 2).unwrap().is_none());
+
+        let out_of_range: PrimitiveSource = serde_json::from_value(json!({
+            "joints": [[2, 0, 0, 0]],
+            "weights": [[1, 0, 0, 0]],
+        }))
+        .unwrap();
+        assert!(out_of_range.skinning(&joints, 1).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! The VRMC_node_constraint extension: nodes whose rotation follows another node (the source),
+//! e.g. twist bones that take a part of the roll of the hand. [`evaluate`] applies the
+//! constraints to a pose as described in the specification.
+
+use crate::extensions;
+use crate::math;
+use crate::math::Quat;
+use crate::pose::Pose;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+pub const EXTENSION_NAME: &str = "VRMC_node_constraint";
+pub const SPEC_VERSION: &str = "1.0";
+
+/// The VRMC_node_constraint extension on a node
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeConstraint {
+    pub spec_version: String,
+    pub constraint: Constraint,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+impl NodeConstraint {
+    pub fn new(constraint: Constraint) -> Self {
+        Self {
+            spec_version: SPEC_VERSION.to_string(),
+            constraint,
+            extensions: None,
+            extras: None,
+        }
+    }
+    /// Read the VRMC_node_constraint extension of the JSON of a node, if any
+    pub fn from_node(node: &Value) -> Result<Option<Self>> {
+        extensions::get(node, EXTENSION_NAME)
+    }
+    /// Store this as the VRMC_node_constraint extension of the JSON of a node.
+    /// The extension has to be listed in extensionsUsed of the document too.
+    pub fn write_to_node(&self, node: &mut Value) -> Result<()> {
+        extensions::set(node, EXTENSION_NAME, self)
+    }
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Constraint {
+    /// Follow the rotation of the source around one axis
+    #[serde(rename_all = "camelCase")]
+    Roll {
+        source: u32,
+        roll_axis: RollAxis,
+        #[serde(default = "default_weight")]
+        weight: f32,
+    },
+    /// Turn an axis toward the source
+    #[serde(rename_all = "camelCase")]
+    Aim {
+        source: u32,
+        aim_axis: AimAxis,
+        #[serde(default = "default_weight")]
+        weight: f32,
+    },
+    /// Follow the rotation of the source
+    Rotation {
+        source: u32,
+        #[serde(default = "default_weight")]
+        weight: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RollAxis {
+    X,
+    Y,
+    Z,
+}
+
+impl RollAxis {
+    pub fn vector(self) -> math::Vec3 {
+        match self {
+            RollAxis::X => [1.0, 0.0, 0.0],
+            RollAxis::Y => [0.0, 1.0, 0.0],
+            RollAxis::Z => [0.0, 0.0, 1.0],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AimAxis {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl AimAxis {
+    pub fn vector(self) -> math::Vec3 {
+        match self {
+            AimAxis::PositiveX => [1.0, 0.0, 0.0],
+            AimAxis::NegativeX => [-1.0, 0.0, 0.0],
+            AimAxis::PositiveY => [0.0, 1.0, 0.0],
+            AimAxis::NegativeY => [0.0, -1.0, 0.0],
+            AimAxis::PositiveZ => [0.0, 0.0, 1.0],
+            AimAxis::NegativeZ => [0.0, 0.0, -1.0],
+        }
+    }
+}
+
+impl Constraint {
+    pub fn source(&self) -> u32 {
+        match *self {
+            Constraint::Roll { source, .. }
+            | Constraint::Aim { source, .. }
+            | Constraint::Rotation { source, .. } => source,
+        }
+    }
+    pub fn weight(&self) -> f32 {
+        match *self {
+            Constraint::Roll { weight, .. }
+            | Constraint::Aim { weight, .. }
+            | Constraint::Rotation { weight, .. } => weight,
+        }
+    }
+    /// Parse `roll:<axis>`, `aim:<axis>` or `rotation`, e.g. `roll:X` or `aim:NegativeZ`
+    pub fn parse(kind: &str, source: u32, weight: f32) -> Result<Self> {
+        let (name, axis) = match kind.split_once(':') {
+            Some((name, axis)) => (name, Some(axis)),
+            None => (kind, None),
+        };
+        let axis_value = || -> Result<Value> {
+            let example = if name == "aim" {
+                "aim:PositiveZ"
+            } else {
+                "roll:X"
+            };
+            let axis = axis.with_context(|| format!("{} needs an axis, e.g. {}", name, example))?;
+            Ok(Value::from(axis))
+        };
+        Ok(match name {
+            "roll" => Constraint::Roll {
+                source,
+                roll_axis: serde_json::from_value(axis_value()?)
+                    .with_context(|| format!("The axis of {} is not X, Y or Z", kind))?,
+                weight,
+            },
+            "aim" => Constraint::Aim {
+                source,
+                aim_axis: serde_json::from_value(axis_value()?).with_context(|| {
+                    format!(
+                        "The axis of {} is not PositiveX, NegativeX, ..., NegativeZ",
+                        kind
+                    )
+                })?,
+                weight,
+            },
+            "rotation" if axis.is_none() => Constraint::Rotation { source, weight },
+            _ => bail!("{} is not roll:<axis>, aim:<axis> or rotation", kind),
+        })
+    }
+}
+
+/// Indices into `constraints` in the order they have to be evaluated: a constraint comes after
+/// the ones of its source, of its ancestors and, for aim constraints, of the ancestors of the source.
+pub fn evaluation_order(
+    constraints: &[(usize, NodeConstraint)],
+    pose: &Pose,
+) -> Result<Vec<usize>> {
+    let by_node: BTreeMap<usize, usize> = constraints
+        .iter()
+        .enumerate()
+        .map(|(i, (node, _))| (*node, i))
+        .collect();
+    let dependencies = |i: usize| -> Vec<usize> {
+        let (node, c) = &constraints[i];
+        let source = c.constraint.source() as usize;
+        let mut nodes = vec![source];
+        nodes.extend(pose.ancestors(*node));
+        if let Constraint::Aim { .. } = c.constraint {
+            nodes.extend(pose.ancestors(source));
+        }
+        nodes
+            .into_iter()
+            .filter_map(|n| by_node.get(&n).copied())
+            .collect()
+    };
+    // 0: not visited, 1: being visited, 2: done
+    let mut state = vec![0u8; constraints.len()];
+    let mut order = Vec::with_capacity(constraints.len());
+    fn visit(
+        i: usize,
+        constraints: &[(usize, NodeConstraint)],
+        dependencies: &dyn Fn(usize) -> Vec<usize>,
+        state: &mut [u8],
+        order: &mut Vec<usize>,
+    ) -> Result<()> {
+        match state[i] {
+            1 => bail!(
+                "The constraint of node #{} depends on itself through its sources or ancestors",
+                constraints[i].0
+            ),
+            2 => return Ok(()),
+            _ => {}
+        }
+        state[i] = 1;
+        for d in dependencies(i) {
+            visit(d, constraints, dependencies, state, order)?;
+        }
+        state[i] = 2;
+        order.push(i);
+        Ok(())
+    }
+    for i in 0..constraints.len() {
+        visit(i, constraints, &dependencies, &mut state, &mut order)?;
+    }
+    Ok(order)
+}
+
+/// The rotation of node `node` after applying its constraint to `pose`.
+/// `rest` is the pose the constraint was authored for, usually [`Pose::rest`].
+pub fn constrained_rotation(
+    node: usize,
+    constraint: &Constraint,
+    rest: &Pose,
+    pose: &Pose,
+) -> Quat {
+    let source = constraint.source() as usize;
+    let src_rest = rest.local[source].rotation;
+    let dst_rest = rest.local[node].rotation;
+    let src_delta = math::quat_mul(math::quat_inverse(src_rest), pose.local[source].rotation);
+    let target = match *constraint {
+        Constraint::Rotation { .. } => math::quat_mul(dst_rest, src_delta),
+        Constraint::Roll { roll_axis, .. } => {
+            let delta_in_parent = math::quat_mul(
+                math::quat_mul(src_rest, src_delta),
+                math::quat_inverse(src_rest),
+            );
+            let delta_in_dst = math::quat_mul(
+                math::quat_mul(math::quat_inverse(dst_rest), delta_in_parent),
+                dst_rest,
+            );
+            let axis = roll_axis.vector();
+            let swing = math::quat_from_to(axis, math::quat_rotate(delta_in_dst, axis));
+            // The twist around the axis, with the swing taken away
+            let twist = math::quat_mul(math::quat_inverse(swing), delta_in_dst);
+            math::quat_mul(dst_rest, twist)
+        }
+        Constraint::Aim { aim_axis, .. } => {
+            let parent_rotation = pose.parent_world(node).rotation;
+            let rest_world = math::quat_mul(parent_rotation, dst_rest);
+            let from = math::quat_rotate(rest_world, aim_axis.vector());
+            let to = math::sub(pose.world(source).translation, pose.world(node).translation);
+            if math::length(to) < 1e-6 {
+                return dst_rest;
+            }
+            let turn = math::quat_from_to(from, to);
+            math::quat_mul(
+                math::quat_mul(math::quat_inverse(parent_rotation), turn),
+                rest_world,
+            )
+        }
+    };
+    math::quat_slerp(dst_rest, target, constraint.weight().clamp(0.0, 1.0))
+}
+
+/// Apply the constraints of `(node, constraint)` to `pose`, overwriting the rotations
+/// of the constrained nodes
+pub fn evaluate(
+    constraints: &[(usize, NodeConstraint)],
+    rest: &Pose,
+    pose: &mut Pose,
+) -> Result<()> {
+    for (node, c) in constraints {
+        let source = c.constraint.source() as usize;
+        if *node >= pose.local.len() || source >= pose.local.len() {
+            bail!(
+                "The constraint of node #{} refers to a node that does not exist",
+                node
+            );
+        }
+        if *node == source {
+            bail!(
+                "The source of the constraint of node #{} is the node itself",
+                node
+            );
+        }
+    }
+    for i in evaluation_order(constraints, pose)? {
+        let (node, c) = &constraints[i];
+        pose.local[*node].rotation = constrained_rotation(*node, &c.constraint, rest, pose);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pose::Transform;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    fn pose(local: Vec<Transform>, parents: Vec<Option<usize>>) -> Pose {
+        Pose { local, parents }
+    }
+
+    fn rotation(rotation: Quat) -> Transform {
+        Transform {
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    fn translation(translation: math::Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    /// q and -q are the same rotation
+    fn assert_rotation(actual: Quat, expected: Quat) {
+        let d = math::quat_mul(math::quat_inverse(expected), actual);
+        assert!(
+            d[3].abs() > 1.0 - 1e-5,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn rotation_with_half_weight() {
+        let rest = pose(vec![Transform::default(); 2], vec![None, None]);
+        // The source turns 90 degrees around Y
+        let mut current = rest.clone();
+        current.local[1] = rotation([0.0, FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2]);
+        let c = Constraint::Rotation {
+            source: 1,
+            weight: 0.5,
+        };
+        // 45 degrees around Y
+        let expected = [0.0, 0.38268343, 0.0, 0.9238795];
+        assert_rotation(constrained_rotation(0, &c, &rest, &current), expected);
+    }
+
+    #[test]
+    fn roll_takes_the_twist_only() {
+        let rest = pose(vec![Transform::default(); 2], vec![None, None]);
+        // 90 degrees around X, then 90 degrees around Y. The Y turn is the swing of X.
+        let mut current = rest.clone();
+        current.local[1] = rotation(math::quat_mul(
+            [0.0, FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2],
+            [FRAC_1_SQRT_2, 0.0, 0.0, FRAC_1_SQRT_2],
+        ));
+        let c = Constraint::Roll {
+            source: 1,
+            roll_axis: RollAxis::X,
+            weight: 1.0,
+        };
+        let expected = [FRAC_1_SQRT_2, 0.0, 0.0, FRAC_1_SQRT_2];
+        assert_rotation(constrained_rotation(0, &c, &rest, &current), expected);
+    }
+
+    #[test]
+    fn aim_turns_the_axis_toward_the_source() {
+        // Node 1 is a child of node 0 at (1, 0, 0) and the source, node 2, is above it
+        let rest = pose(
+            vec![
+                translation([1.0, 0.0, 0.0]),
+                Transform::default(),
+                translation([1.0, 1.0, 0.0]),
+            ],
+            vec![None, Some(0), None],
+        );
+        let c = Constraint::Aim {
+            source: 2,
+            aim_axis: AimAxis::PositiveZ,
+            weight: 1.0,
+        };
+        // -90 degrees around X turns +Z to +Y
+        let expected = [-FRAC_1_SQRT_2, 0.0, 0.0, FRAC_1_SQRT_2];
+        assert_rotation(constrained_rotation(1, &c, &rest, &rest), expected);
+    }
+}
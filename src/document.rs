@@ -0,0 +1,239 @@
+//! A VRM / glTF binary held as its JSON document and BIN chunk.
+//! The JSON is kept as a serde_json::Value so extensions unknown to gltf_json survive edits.
+
+use crate::constraint;
+use crate::constraint::NodeConstraint;
+use crate::extensions;
+use crate::glb::append_bytes;
+use crate::glb::to_glb;
+use crate::image;
+use crate::mtoon;
+use crate::mtoon::MToon;
+use crate::resources;
+use crate::spring_bone::SpringBone;
+use crate::validate;
+use crate::vrm::Vrm;
+use crate::vrm0::Vrm0;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug)]
+pub struct Document {
+    pub json: Value,
+    pub bin: Vec<u8>,
+}
+
+impl Document {
+    pub fn new(json: Value, bin: Vec<u8>) -> Self {
+        Self { json, bin }
+    }
+    /// Parse a .glb / .vrm file, or a glTF JSON whose buffers and images are `data:` URIs
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        Self::parse(data, None)
+    }
+    /// Load a .glb / .vrm file, or a .gltf file with the external files next to it.
+    /// Buffers and images outside the BIN chunk are embedded into it (see `resources::embed`).
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            fs::read(path).with_context(|| format!("Failed to read {}", path.to_string_lossy()))?;
+        Self::parse(&data, path.parent())
+            .with_context(|| format!("Failed to load {}", path.to_string_lossy()))
+    }
+    fn parse(data: &[u8], base_dir: Option<&Path>) -> Result<Self> {
+        let (mut json, bin): (Value, _) = if data.starts_with(b"glTF") {
+            let glb = gltf::binary::Glb::from_slice(data)?;
+            (
+                serde_json::from_slice(&glb.json)?,
+                glb.bin.map(|b| b.into_owned()),
+            )
+        } else {
+            (serde_json::from_slice(data)?, None)
+        };
+        let bin = resources::embed(&mut json, bin, base_dir)?;
+        Ok(Self { json, bin })
+    }
+    /// Contents of the buffers indexed by the buffer index, for the `accessor` module
+    pub fn buffers(&self) -> &[Vec<u8>] {
+        std::slice::from_ref(&self.bin)
+    }
+    /// Append float data to the BIN chunk as a new bufferView and an accessor of SCALAR, VEC2, VEC3
+    /// or VEC4 by `N`, and return the index of the accessor. `with_bounds` writes min and max,
+    /// which glTF requires for animation inputs.
+    pub fn push_accessor_f32<const N: usize>(
+        &mut self,
+        data: &[[f32; N]],
+        with_bounds: bool,
+    ) -> usize {
+        while self.bin.len() % 4 != 0 {
+            self.bin.push(0);
+        }
+        let (offset, length) = append_bytes(&mut self.bin, data);
+        let json = &mut self.json;
+        json["buffers"] = json!([{ "byteLength": self.bin.len() }]);
+        if !json["bufferViews"].is_array() {
+            json["bufferViews"] = json!([]);
+        }
+        let views = json["bufferViews"].as_array_mut().unwrap();
+        views.push(json!({ "buffer": 0, "byteOffset": offset, "byteLength": length }));
+        let view = views.len() - 1;
+        let type_ = match N {
+            1 => "SCALAR",
+            2 => "VEC2",
+            3 => "VEC3",
+            _ => "VEC4",
+        };
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": 5126,
+            "count": data.len(),
+            "type": type_,
+        });
+        if with_bounds && !data.is_empty() {
+            let bound = |f: fn(f32, f32) -> f32| {
+                (0..N)
+                    .map(|i| data.iter().map(|e| e[i]).reduce(f).unwrap())
+                    .collect::<Vec<_>>()
+            };
+            accessor["min"] = json!(bound(f32::min));
+            accessor["max"] = json!(bound(f32::max));
+        }
+        if !json["accessors"].is_array() {
+            json["accessors"] = json!([]);
+        }
+        let accessors = json["accessors"].as_array_mut().unwrap();
+        accessors.push(accessor);
+        accessors.len() - 1
+    }
+    /// Typed view of the JSON. Extensions that gltf_json doesn't know are not included.
+    /// A texture that has no source of glTF 2.0 (allowed with EXT_texture_webp and KHR_texture_basisu)
+    /// gets the image of the extension as its source, since gltf_json requires one.
+    pub fn root(&self) -> Result<gltf_json::Root> {
+        let mut json = self.json.clone();
+        image::fill_texture_sources(&mut json);
+        Ok(serde_json::from_value(json)?)
+    }
+    /// Validated view of the JSON to walk the scene with the gltf crate
+    pub fn gltf(&self) -> Result<gltf::Document> {
+        Ok(gltf::Document::from_json(self.root()?)?)
+    }
+    pub fn vrm(&self) -> Result<Option<Vrm>> {
+        Vrm::from_json(&self.json)
+    }
+    pub fn set_vrm(&mut self, vrm: &Vrm) -> Result<()> {
+        vrm.write_to_json(&mut self.json)
+    }
+    /// The VRMC_node_constraint extension of a node, if any
+    pub fn node_constraint(&self, node: usize) -> Result<Option<NodeConstraint>> {
+        match self.json["nodes"].get(node) {
+            Some(n) => NodeConstraint::from_node(n),
+            None => bail!("node #{} does not exist", node),
+        }
+    }
+    /// All the nodes that have VRMC_node_constraint, with their constraints
+    pub fn node_constraints(&self) -> Result<Vec<(usize, NodeConstraint)>> {
+        let mut constraints = Vec::new();
+        for (i, n) in self.json["nodes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            if let Some(c) = NodeConstraint::from_node(n)
+                .with_context(|| format!("Invalid constraint of node #{}", i))?
+            {
+                constraints.push((i, c));
+            }
+        }
+        Ok(constraints)
+    }
+    /// Set the VRMC_node_constraint extension of a node and list it in extensionsUsed.
+    /// The source has to be another node that exists.
+    pub fn set_node_constraint(&mut self, node: usize, constraint: &NodeConstraint) -> Result<()> {
+        let source = constraint.constraint.source() as usize;
+        let node_count = self.json["nodes"].as_array().map_or(0, |n| n.len());
+        if source >= node_count {
+            bail!("source node #{} does not exist", source);
+        }
+        if source == node {
+            bail!("node #{} can't be the source of its own constraint", node);
+        }
+        match self.json["nodes"].get_mut(node) {
+            Some(n) => constraint.write_to_node(n)?,
+            None => bail!("node #{} does not exist", node),
+        }
+        extensions::mark_used(&mut self.json, constraint::EXTENSION_NAME);
+        Ok(())
+    }
+    /// Remove the VRMC_node_constraint extension of a node, and from extensionsUsed
+    /// if no node has it anymore. Returns whether the node had one.
+    pub fn remove_node_constraint(&mut self, node: usize) -> Result<bool> {
+        let removed = match self.json["nodes"].get_mut(node) {
+            Some(n) => extensions::remove(n, constraint::EXTENSION_NAME),
+            None => bail!("node #{} does not exist", node),
+        };
+        if self.node_constraints()?.is_empty() {
+            extensions::unmark_used(&mut self.json, constraint::EXTENSION_NAME);
+        }
+        Ok(removed)
+    }
+    pub fn spring_bone(&self) -> Result<Option<SpringBone>> {
+        SpringBone::from_json(&self.json)
+    }
+    /// Set the VRMC_springBone extension. The nodes, colliders and collider groups it refers to
+    /// have to exist.
+    pub fn set_spring_bone(&mut self, spring_bone: &SpringBone) -> Result<()> {
+        let node_count = self.json["nodes"].as_array().map_or(0, |n| n.len());
+        spring_bone.check_indices(node_count)?;
+        spring_bone.write_to_json(&mut self.json)
+    }
+    /// The VRMC_materials_mtoon extension of a material, if any
+    pub fn mtoon(&self, material: usize) -> Result<Option<MToon>> {
+        match self.json["materials"].get(material) {
+            Some(m) => MToon::from_material(m),
+            None => bail!("material #{} does not exist", material),
+        }
+    }
+    /// Set the VRMC_materials_mtoon extension of a material and list it in extensionsUsed.
+    /// The textures it refers to have to exist.
+    pub fn set_mtoon(&mut self, material: usize, mtoon: &MToon) -> Result<()> {
+        let texture_count = self.json["textures"].as_array().map_or(0, |t| t.len());
+        if let Some((name, t)) = mtoon
+            .textures()
+            .into_iter()
+            .find(|(_, t)| *t as usize >= texture_count)
+        {
+            bail!("texture #{} of {} does not exist", t, name);
+        }
+        match self.json["materials"].get_mut(material) {
+            Some(m) => mtoon.write_to_material(m)?,
+            None => bail!("material #{} does not exist", material),
+        }
+        extensions::mark_used(&mut self.json, mtoon::EXTENSION_NAME);
+        Ok(())
+    }
+    pub fn vrm0(&self) -> Result<Option<Vrm0>> {
+        Vrm0::from_json(&self.json)
+    }
+    pub fn to_vec(&self) -> Result<Vec<u8>> {
+        to_glb(serde_json::to_string(&self.json)?.as_bytes(), &self.bin)
+    }
+    /// Write the document as a GLB file, creating the parent directory if needed.
+    /// The file is not written if it doesn't pass `validate::check_glb`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let data = self.to_vec()?;
+        validate::check_glb(&data)
+            .with_context(|| format!("Refused to write {}", path.to_string_lossy()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, data)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+//! Authoring of VRMC_vrm expressions.
+//! An expression is looked up by its name: names in `EXPRESSION_PRESETS` are presets and
+//! the others are custom expressions. Binds written as text refer to nodes, morph targets
+//! and materials either by name or by index.
+
+use crate::resolve;
+use crate::vrm::Expression;
+use crate::vrm::ExpressionOverride;
+use crate::vrm::Expressions;
+use crate::vrm::MaterialColorBind;
+use crate::vrm::MaterialColorType;
+use crate::vrm::MorphTargetBind;
+use crate::vrm::TextureTransformBind;
+use crate::vrm::EXPRESSION_PRESETS;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+pub fn is_preset(name: &str) -> bool {
+    EXPRESSION_PRESETS.contains(&name)
+}
+
+impl Expressions {
+    pub fn get(&self, name: &str) -> Option<&Expression> {
+        self.preset.get(name).or_else(|| self.custom.get(name))
+    }
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Expression> {
+        match self.preset.get_mut(name) {
+            Some(e) => Some(e),
+            None => self.custom.get_mut(name),
+        }
+    }
+    /// All expressions with their names, presets first
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Expression)> {
+        self.preset.iter().chain(self.custom.iter())
+    }
+    /// Add an expression, or replace the one with the same name
+    pub fn insert(&mut self, name: &str, expression: Expression) -> Option<Expression> {
+        if is_preset(name) {
+            self.preset.insert(name.to_string(), expression)
+        } else {
+            self.custom.insert(name.to_string(), expression)
+        }
+    }
+    pub fn remove(&mut self, name: &str) -> Option<Expression> {
+        self.preset
+            .remove(name)
+            .or_else(|| self.custom.remove(name))
+    }
+    /// Rename an expression. Renaming to a preset name makes it a preset and vice versa.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        if self.get(to).is_some() {
+            bail!("Expression {} already exists", to);
+        }
+        let e = self
+            .remove(from)
+            .with_context(|| format!("Expression {} does not exist", from))?;
+        self.insert(to, e);
+        Ok(())
+    }
+    /// Make the morph target binds on (node, index) `from` bind to `to` instead.
+    /// Returns the number of binds changed.
+    pub fn remap_morph_target(&mut self, from: (u32, u32), to: (u32, u32)) -> usize {
+        let mut count = 0;
+        for e in self.preset.values_mut().chain(self.custom.values_mut()) {
+            for b in &mut e.morph_target_binds {
+                if (b.node, b.index) == from {
+                    (b.node, b.index) = to;
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+}
+
+fn parse_floats<const N: usize>(s: &str) -> Result<[f32; N]> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("{} should be {} numbers separated by commas", s, N))?;
+    values
+        .try_into()
+        .map_err(|_| anyhow!("{} should be {} numbers separated by commas", s, N))
+}
+
+/// Parse a camelCase enum value of the VRMC_vrm schema
+fn parse_enum<T: DeserializeOwned>(s: &str) -> Result<T> {
+    serde_json::from_value(Value::from(s)).with_context(|| format!("Unknown value {}", s))
+}
+
+pub fn parse_override(s: &str) -> Result<ExpressionOverride> {
+    parse_enum(s)
+}
+
+/// Parse `<node>:<morph target>[=<weight>]`. The weight is 1.0 if omitted.
+pub fn parse_morph_target_bind(root: &gltf_json::Root, s: &str) -> Result<MorphTargetBind> {
+    let (target, weight) = match s.rsplit_once('=') {
+        Some((target, weight)) => (
+            target,
+            weight
+                .parse()
+                .with_context(|| format!("Invalid weight {}", weight))?,
+        ),
+        None => (s, 1.0),
+    };
+    let (node, index) = resolve::morph_target(root, target)?;
+    Ok(MorphTargetBind {
+        node,
+        index,
+        weight,
+        extensions: None,
+        extras: None,
+    })
+}
+
+/// Parse `<material>:<type>=<r>,<g>,<b>,<a>` where the type is e.g. `color` or `emissionColor`
+pub fn parse_material_color_bind(root: &gltf_json::Root, s: &str) -> Result<MaterialColorBind> {
+    let (target, value) = s
+        .split_once('=')
+        .with_context(|| format!("{} should be <material>:<type>=<r>,<g>,<b>,<a>", s))?;
+    let (material, type_) = target
+        .rsplit_once(':')
+        .with_context(|| format!("{} should be <material>:<type>=<r>,<g>,<b>,<a>", s))?;
+    Ok(MaterialColorBind {
+        material: resolve::material(root, material)?,
+        type_: parse_enum::<MaterialColorType>(type_)?,
+        target_value: parse_floats(value)?,
+        extensions: None,
+        extras: None,
+    })
+}
+
+/// Parse `<material>=<scale x>,<scale y>,<offset x>,<offset y>`
+pub fn parse_texture_transform_bind(
+    root: &gltf_json::Root,
+    s: &str,
+) -> Result<TextureTransformBind> {
+    let (material, value) = s
+        .split_once('=')
+        .with_context(|| format!("{} should be <material>=<sx>,<sy>,<ox>,<oy>", s))?;
+    let [sx, sy, ox, oy] = parse_floats(value)?;
+    Ok(TextureTransformBind {
+        material: resolve::material(root, material)?,
+        scale: Some([sx, sy]),
+        offset: Some([ox, oy]),
+        extensions: None,
+        extras: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn face() -> gltf_json::Root {
+        serde_json::from_value(json!({
+            "asset": { "version": "2.0" },
+            "nodes": [{ "name": "Body" }, { "name": "Face", "mesh": 0 }],
+            "meshes": [{
+                "primitives": [{
+                    "attributes": { "POSITION": 0 },
+                    "targets": [{ "POSITION": 1 }, { "POSITION": 2 }],
+                }],
+                "extras": { "targetNames": ["Fcl_MTH_A", "Fcl_EYE_Close"] },
+            }],
+            "materials": [{ "name": "Skin" }, { "name": "Eye" }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn binds_refer_by_name_or_index() {
+        let root = face();
+        let b = parse_morph_target_bind(&root, "Face:Fcl_EYE_Close").unwrap();
+        assert_eq!((b.node, b.index, b.weight), (1, 1, 1.0));
+        let b = parse_morph_target_bind(&root, "1:0=0.5").unwrap();
+        assert_eq!((b.node, b.index, b.weight), (1, 0, 0.5));
+        assert!(parse_morph_target_bind(&root, "Body:0").is_err());
+        assert!(parse_morph_target_bind(&root, "Face:2").is_err());
+
+        let b = parse_material_color_bind(&root, "Eye:emissionColor=1,0,0,1").unwrap();
+        assert_eq!(b.material, 1);
+        assert_eq!(b.type_, MaterialColorType::EmissionColor);
+        assert_eq!(b.target_value, [1.0, 0.0, 0.0, 1.0]);
+        assert!(parse_material_color_bind(&root, "Eye:glowColor=1,0,0,1").is_err());
+        assert!(parse_material_color_bind(&root, "Eye:color=1,0,0").is_err());
+
+        let b = parse_texture_transform_bind(&root, "Skin=1,1,0.5,0").unwrap();
+        assert_eq!(b.material, 0);
+        assert_eq!((b.scale, b.offset), (Some([1.0, 1.0]), Some([0.5, 0.0])));
+        assert_eq!(parse_override("block").unwrap(), ExpressionOverride::Block);
+    }
+
+    #[test]
+    fn renaming_moves_between_presets_and_custom() {
+        let root = face();
+        let mut expressions = Expressions::default();
+        let blink = Expression {
+            morph_target_binds: vec![parse_morph_target_bind(&root, "Face:Fcl_EYE_Close").unwrap()],
+            ..Default::default()
+        };
+        expressions.insert("blink", blink.clone());
+        expressions.insert("wink", blink);
+        assert!(expressions.preset.contains_key("blink"));
+        assert!(expressions.custom.contains_key("wink"));
+
+        assert!(expressions.rename("wink", "blink").is_err());
+        assert!(expressions.rename("smile", "happy").is_err());
+        expressions.rename("blink", "sleepy").unwrap();
+        expressions.rename("wink", "blinkLeft").unwrap();
+        assert_eq!(
+            expressions.preset.keys().collect::<Vec<_>>(),
+            vec!["blinkLeft"]
+        );
+        assert_eq!(
+            expressions.custom.keys().collect::<Vec<_>>(),
+            vec!["sleepy"]
+        );
+
+        assert_eq!(expressions.remap_morph_target((1, 1), (1, 0)), 2);
+        assert_eq!(expressions.remap_morph_target((1, 1), (1, 0)), 0);
+        let b = &expressions.get("sleepy").unwrap().morph_target_binds[0];
+        assert_eq!((b.node, b.index), (1, 0));
+        assert!(expressions.remove("blinkLeft").is_some());
+        assert!(expressions.get("blinkLeft").is_none());
+    }
+}
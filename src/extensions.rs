@@ -0,0 +1,70 @@
+//! Access to glTF extensions that gltf_json doesn't know about (VRMC_*, EXT_texture_webp, ...).
+//! They are read from and written into the JSON chunk as a serde_json::Value.
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Deserialize `object.extensions[name]`, or None if the object doesn't have it
+pub fn get<T: DeserializeOwned>(object: &Value, name: &str) -> Result<Option<T>> {
+    match object.get("extensions").and_then(|e| e.get(name)) {
+        Some(v) => Ok(Some(
+            serde_json::from_value(v.clone()).with_context(|| format!("Invalid {}", name))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Set `object.extensions[name]` to `value`
+pub fn set<T: Serialize>(object: &mut Value, name: &str, value: &T) -> Result<()> {
+    object["extensions"][name] = serde_json::to_value(value)?;
+    Ok(())
+}
+
+/// Remove `object.extensions[name]`, and `extensions` too if nothing is left in it.
+/// Returns whether the object had the extension.
+pub fn remove(object: &mut Value, name: &str) -> bool {
+    let extensions = match object.get_mut("extensions").and_then(|e| e.as_object_mut()) {
+        Some(e) => e,
+        None => return false,
+    };
+    let removed = extensions.remove(name).is_some();
+    if extensions.is_empty() {
+        if let Some(o) = object.as_object_mut() {
+            o.remove("extensions");
+        }
+    }
+    removed
+}
+
+fn push_name(root: &mut Value, list: &str, name: &str) {
+    if !root[list].is_array() {
+        root[list] = Value::Array(Vec::new());
+    }
+    let names = root[list].as_array_mut().unwrap();
+    if !names.iter().any(|e| e == name) {
+        names.push(Value::from(name));
+    }
+}
+
+/// Add `name` to `extensionsUsed` of the document root if it is not listed yet
+pub fn mark_used(root: &mut Value, name: &str) {
+    push_name(root, "extensionsUsed", name);
+}
+
+/// Add `name` to both `extensionsUsed` and `extensionsRequired` of the document root
+pub fn mark_required(root: &mut Value, name: &str) {
+    mark_used(root, name);
+    push_name(root, "extensionsRequired", name);
+}
+
+/// Remove `name` from `extensionsUsed` and `extensionsRequired` of the document root
+pub fn unmark_used(root: &mut Value, name: &str) {
+    for list in ["extensionsUsed", "extensionsRequired"] {
+        if let Some(names) = root.get_mut(list).and_then(|l| l.as_array_mut()) {
+            names.retain(|e| e != name);
+        }
+    }
+}
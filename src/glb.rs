@@ -0,0 +1,163 @@
+//! Writing glTF binaries: the BIN chunk layout and the GLB container.
+
+use crate::image::ImageFormat;
+use crate::morph::MorphTarget;
+use crate::scene::Material;
+use crate::scene::Node;
+use crate::scene::Primitive;
+use crate::scene::SceneBuilder;
+use crate::skin::Skinning;
+use crate::validate;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::extensions::texture::TextureTransform;
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+
+/// Calculate bounding coordinates of a list of vertices, used for the clipping distance of the model
+pub fn bounding_coords3d(points: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX, f32::MAX, f32::MAX];
+    let mut max = [f32::MIN, f32::MIN, f32::MIN];
+
+    for p in points {
+        for i in 0..3 {
+            min[i] = f32::min(min[i], p[i]);
+            max[i] = f32::max(max[i], p[i]);
+        }
+    }
+    (min, max)
+}
+pub fn bounding_coords2d(points: &[[f32; 2]]) -> ([f32; 2], [f32; 2]) {
+    let mut min = [f32::MAX, f32::MAX];
+    let mut max = [f32::MIN, f32::MIN];
+
+    for p in points {
+        for i in 0..2 {
+            min[i] = f32::min(min[i], p[i]);
+            max[i] = f32::max(max[i], p[i]);
+        }
+    }
+    (min, max)
+}
+
+/// Round `n` up to a multiple of four, as glTF requires for chunks and buffer views
+pub fn align_to_multiple_of_four(n: &mut u32) {
+    *n = (*n + 3) & !3;
+}
+
+/// Append `src` to the BIN chunk padded to four bytes, and return its (offset, length)
+pub fn append_bytes<T>(bin: &mut Vec<u8>, src: &[T]) -> (u32, u32) {
+    let ofs = bin.len();
+    assert_eq!(ofs % 4, 0);
+    let src: &[u8] = unsafe {
+        std::slice::from_raw_parts(
+            src.as_ptr() as *const u8,
+            src.len() * std::mem::size_of::<T>(),
+        )
+    };
+    let len = src.len();
+    // Append the data
+    bin.resize(bin.len() + len, 0);
+    bin[ofs..ofs + len].copy_from_slice(&src[0..len]);
+    // Insert padding if needed
+    while bin.len() % 4 != 0 {
+        bin.push(0); // pad to multiple of four bytes
+    }
+    (ofs as u32, len as u32)
+}
+
+/// Write a GLB with a single mesh primitive, optionally with texture coordinates
+/// and a base color texture (PNG, JPEG, WebP or KTX2) mapped with them.
+/// A `texture_transform` is written as KHR_texture_transform on the texture info.
+/// With `skinning`, the mesh is bound to a skin whose bones are placed at the root of the scene.
+/// Joints are written as unsigned bytes or shorts, and the weights of each vertex are normalized.
+/// Morph `targets` are written with their names in `extras.targetNames` of the mesh.
+/// The glTF JSON is also written next to it with .json extension for debugging.
+#[allow(clippy::too_many_arguments)]
+pub fn write_glb(
+    vertices: &[[f32; 3]],
+    indices: &[[u32; 3]],
+    normals: &[[f32; 3]],
+    tex_coords: Option<&[[f32; 2]]>,
+    image: Option<(ImageFormat, &[u8])>,
+    texture_transform: Option<&TextureTransform>,
+    skinning: Option<&Skinning>,
+    targets: &[MorphTarget],
+    translation: Option<[f32; 3]>,
+    path: &str,
+) -> Result<()> {
+    let mut scene = SceneBuilder::new();
+    let material = match (image, tex_coords) {
+        (Some((format, data)), Some(_)) => {
+            let texture = scene.add_texture(None, format, data);
+            Some(scene.add_material(&Material {
+                base_color_texture: Some(texture),
+                texture_transform: texture_transform.cloned(),
+                ..Default::default()
+            })?)
+        }
+        _ => None,
+    };
+    let skin = match skinning {
+        Some(skinning) => {
+            skinning.validate(vertices.len())?;
+            Some(scene.add_skin(&skinning.skeleton, None)?)
+        }
+        None => None,
+    };
+    let mesh = scene.add_mesh(
+        None,
+        &[Primitive {
+            vertices,
+            normals,
+            tex_coords,
+            indices,
+            material,
+            joints: skinning.map(|s| s.joints.as_slice()),
+            weights: skinning.map(|s| s.weights.as_slice()),
+            targets,
+        }],
+    )?;
+    scene.add_node(
+        &Node {
+            translation,
+            mesh: Some(mesh),
+            skin,
+            ..Default::default()
+        },
+        None,
+    )?;
+    let doc = scene.build()?;
+    fs::write(
+        Path::new(path).with_extension("json"),
+        serde_json::to_string_pretty(&doc.json)?,
+    )?;
+    doc.save(path)
+}
+
+/// Build a GLB container from a serialized glTF JSON document and its BIN chunk
+pub fn to_glb(json: &[u8], bin: &[u8]) -> Result<Vec<u8>> {
+    let bin_size = bin.len() as u32;
+    let mut json_offset = json.len() as u32;
+    align_to_multiple_of_four(&mut json_offset);
+    let glb = gltf::binary::Glb {
+        header: gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            length: json_offset + bin_size,
+        },
+        bin: Some(Cow::Borrowed(bin)),
+        json: Cow::Borrowed(json),
+    };
+    Ok(glb.to_vec()?)
+}
+
+/// Write a GLB file made of a serialized glTF JSON document and its BIN chunk.
+/// The file is not written if it doesn't pass `validate::check_glb`.
+pub fn write_glb_file(json_string: String, bin: Vec<u8>, path: &str) -> Result<()> {
+    let glb = to_glb(json_string.as_bytes(), &bin)?;
+    validate::check_glb(&glb).with_context(|| format!("Refused to write {}", path))?;
+    fs::write(path, glb)?;
+    Ok(())
+}
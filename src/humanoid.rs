@@ -0,0 +1,293 @@
+//! The humanoid bone mapping of VRMC_vrm: checking, editing and guessing it from node names.
+
+use crate::vrm::HumanBone;
+use crate::vrm::Humanoid;
+use crate::vrm::HUMAN_BONES;
+use crate::vrm::REQUIRED_HUMAN_BONES;
+use anyhow::bail;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+impl Humanoid {
+    /// Required bones that are not mapped to any node
+    pub fn missing_required_bones(&self) -> Vec<&'static str> {
+        REQUIRED_HUMAN_BONES
+            .iter()
+            .filter(|b| !self.human_bones.contains_key(**b))
+            .copied()
+            .collect()
+    }
+    pub fn bone_of_node(&self, node: u32) -> Option<&str> {
+        self.human_bones
+            .iter()
+            .find(|(_, b)| b.node == node)
+            .map(|(name, _)| name.as_str())
+    }
+    /// Map `bone` to `node`. A node can't be used by two bones.
+    pub fn set(&mut self, bone: &str, node: u32) -> Result<()> {
+        if !HUMAN_BONES.contains(&bone) {
+            bail!("{:?} is not a humanoid bone of VRM 1.0", bone);
+        }
+        if let Some(other) = self.bone_of_node(node).filter(|b| *b != bone) {
+            bail!("node #{} is already mapped to {}", node, other);
+        }
+        // A bone mapped again keeps its extensions and extras
+        self.human_bones
+            .entry(bone.to_string())
+            .and_modify(|b| b.node = node)
+            .or_insert(HumanBone {
+                node,
+                extensions: None,
+                extras: None,
+            });
+        Ok(())
+    }
+    pub fn remove(&mut self, bone: &str) -> Option<HumanBone> {
+        self.human_bones.remove(bone)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// Split a node name into its side and the rest in lower case without separators,
+/// e.g. "mixamorig:LeftUpLeg", "J_Bip_L_UpperLeg" and "thigh.L" all have Side::Left.
+fn normalize(name: &str) -> (Option<Side>, String) {
+    let mut name = name.to_ascii_lowercase();
+    for prefix in ["mixamorig:", "mixamorig_", "j_bip_", "j_adj_", "def-"] {
+        if let Some(s) = name.strip_prefix(prefix) {
+            name = s.to_string();
+        }
+    }
+    let mut side = None;
+    let mut base = String::new();
+    for token in name.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let token = match token {
+            "l" | "left" => {
+                side = Some(Side::Left);
+                continue;
+            }
+            "r" | "right" => {
+                side = Some(Side::Right);
+                continue;
+            }
+            // The center of VRoid
+            "c" => continue,
+            t => t,
+        };
+        let token = if let Some(t) = token.strip_prefix("left") {
+            side = Some(Side::Left);
+            t
+        } else if let Some(t) = token.strip_prefix("right") {
+            side = Some(Side::Right);
+            t
+        } else if let Some(t) = token.strip_suffix("left") {
+            side = Some(Side::Left);
+            t
+        } else if let Some(t) = token.strip_suffix("right") {
+            side = Some(Side::Right);
+            t
+        } else {
+            token
+        };
+        base.push_str(token);
+    }
+    (side, base)
+}
+
+/// Normalized names used for a bone without its side, in the order of preference
+fn aliases(bone: &str) -> Vec<String> {
+    let fixed: &[&str] = match bone {
+        "hips" => &["hips", "hip", "pelvis"],
+        "spine" => &["spine"],
+        "chest" => &["chest", "spine1", "spine01", "spine001"],
+        "upperChest" => &["upperchest", "spine2", "spine02", "spine002"],
+        "neck" => &["neck"],
+        "head" => &["head"],
+        "Eye" => &["eye", "faceeye"],
+        "jaw" => &["jaw"],
+        "UpperLeg" => &["upperleg", "upleg", "thigh"],
+        "LowerLeg" => &["lowerleg", "leg", "shin", "calf", "knee"],
+        "Foot" => &["foot", "ankle"],
+        "Toes" => &["toes", "toebase", "toe"],
+        "Shoulder" => &["shoulder", "clavicle"],
+        "UpperArm" => &["upperarm", "arm"],
+        "LowerArm" => &["lowerarm", "forearm", "elbow"],
+        "Hand" => &["hand", "wrist"],
+        _ => &[],
+    };
+    let mut names: Vec<String> = fixed.iter().map(|s| s.to_string()).collect();
+    // Fingers are numbered from the root, e.g. "LeftHandIndex1", "J_Bip_L_Index1" or "f_index.01.L"
+    for finger in ["Thumb", "Index", "Middle", "Ring", "Little"] {
+        let joints: [&str; 3] = if finger == "Thumb" {
+            ["Metacarpal", "Proximal", "Distal"]
+        } else {
+            ["Proximal", "Intermediate", "Distal"]
+        };
+        for (i, joint) in joints.iter().enumerate() {
+            if bone != format!("{}{}", finger, joint) {
+                continue;
+            }
+            let finger = finger.to_ascii_lowercase();
+            names.push(format!("{}{}", finger, joint.to_ascii_lowercase()));
+            for f in [
+                finger.as_str(),
+                if finger == "little" { "pinky" } else { "" },
+            ] {
+                if f.is_empty() {
+                    continue;
+                }
+                names.push(format!("hand{}{}", f, i + 1));
+                names.push(format!("{}{}", f, i + 1));
+                names.push(format!("{}0{}", f, i + 1));
+                names.push(format!("f{}0{}", f, i + 1));
+            }
+        }
+    }
+    names
+}
+
+/// Propose nodes for the bones that are not mapped in `humanoid` yet, from the names of the nodes.
+/// Nodes with a mesh or already used by a bone are not proposed.
+pub fn auto_map(root: &gltf_json::Root, humanoid: &Humanoid) -> BTreeMap<String, u32> {
+    let nodes: Vec<(Option<Side>, String)> = root
+        .nodes
+        .iter()
+        .map(|n| normalize(n.name.as_deref().unwrap_or_default()))
+        .collect();
+    let mut used: Vec<u32> = humanoid.human_bones.values().map(|b| b.node).collect();
+    let mut proposal = BTreeMap::new();
+    for bone in HUMAN_BONES {
+        if humanoid.human_bones.contains_key(bone) {
+            continue;
+        }
+        let (side, rest) = if let Some(rest) = bone.strip_prefix("left") {
+            (Some(Side::Left), rest)
+        } else if let Some(rest) = bone.strip_prefix("right") {
+            (Some(Side::Right), rest)
+        } else {
+            (None, bone)
+        };
+        let found = aliases(rest).iter().find_map(|alias| {
+            (0..nodes.len()).find(|i| {
+                nodes[*i] == (side, alias.clone())
+                    && root.nodes[*i].mesh.is_none()
+                    && !used.contains(&(*i as u32))
+            })
+        });
+        if let Some(node) = found {
+            used.push(node as u32);
+            proposal.insert(bone.to_string(), node as u32);
+        }
+    }
+    proposal
+}
+
+/// Lines of the node tree of the scene, with the humanoid bone mapped to each node
+pub fn tree_lines(root: &gltf_json::Root, humanoid: &Humanoid) -> Vec<String> {
+    fn visit(
+        root: &gltf_json::Root,
+        humanoid: &Humanoid,
+        node: usize,
+        depth: usize,
+        lines: &mut Vec<String>,
+    ) {
+        let n = &root.nodes[node];
+        let mut line = format!(
+            "{:width$}#{} {}",
+            "",
+            node,
+            n.name.as_deref().unwrap_or_default(),
+            width = depth * 2
+        );
+        if let Some(bone) = humanoid.bone_of_node(node as u32) {
+            line.push_str(&format!(" <- {}", bone));
+        }
+        lines.push(line);
+        for c in n.children.iter().flatten() {
+            visit(root, humanoid, c.value(), depth + 1, lines);
+        }
+    }
+    let mut lines = Vec::new();
+    let mut is_child = vec![false; root.nodes.len()];
+    for n in &root.nodes {
+        for c in n.children.iter().flatten() {
+            is_child[c.value()] = true;
+        }
+    }
+    for (i, is_child) in is_child.iter().enumerate() {
+        if !is_child {
+            visit(root, humanoid, i, 0, &mut lines);
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn named_nodes(names: &[&str]) -> gltf_json::Root {
+        let nodes: Vec<_> = names.iter().map(|n| json!({ "name": n })).collect();
+        serde_json::from_value(json!({ "asset": { "version": "2.0" }, "nodes": nodes })).unwrap()
+    }
+
+    #[test]
+    fn names_of_common_rigs_are_mapped() {
+        let root = named_nodes(&[
+            "mixamorig:Hips",
+            "mixamorig:Spine1",
+            "mixamorig:LeftUpLeg",
+            "J_Bip_R_UpperLeg",
+            "thigh.L.001",
+            "forearm.R",
+            "J_Bip_L_Index1",
+            "f_pinky.03.R",
+        ]);
+        let proposal = auto_map(&root, &Humanoid::default());
+        let expected: BTreeMap<String, u32> = [
+            ("hips", 0),
+            ("chest", 1),
+            ("leftUpperLeg", 2),
+            ("rightUpperLeg", 3),
+            ("rightLowerArm", 5),
+            ("leftIndexProximal", 6),
+            ("rightLittleDistal", 7),
+        ]
+        .iter()
+        .map(|(b, n)| (b.to_string(), *n))
+        .collect();
+        assert_eq!(proposal, expected);
+    }
+
+    #[test]
+    fn mapped_bones_and_meshes_are_kept_out() {
+        let mut root = named_nodes(&["Head", "Neck", "Hips", "Head"]);
+        root.nodes[0].mesh = Some(gltf_json::Index::new(0));
+        let mut humanoid = Humanoid::default();
+        humanoid.set("hips", 2).unwrap();
+        let proposal = auto_map(&root, &humanoid);
+        // Node 0 has a mesh, and hips is already mapped
+        assert_eq!(proposal.get("head"), Some(&3));
+        assert_eq!(proposal.get("neck"), Some(&1));
+        assert_eq!(proposal.get("hips"), None);
+        assert_eq!(proposal.len(), 2);
+    }
+
+    #[test]
+    fn set_checks_the_bone_and_the_node() {
+        let mut humanoid = Humanoid::default();
+        humanoid.set("hips", 0).unwrap();
+        assert!(humanoid.set("tail", 1).is_err());
+        assert!(humanoid.set("spine", 0).is_err());
+        humanoid.set("hips", 1).unwrap();
+        humanoid.set("spine", 0).unwrap();
+        assert_eq!(humanoid.bone_of_node(0), Some("spine"));
+        assert!(humanoid.missing_required_bones().contains(&"head"));
+        assert!(!humanoid.missing_required_bones().contains(&"hips"));
+    }
+}
@@ -0,0 +1,157 @@
+//! Image formats of textures: PNG and JPEG of glTF 2.0, WebP of EXT_texture_webp and KTX2 of KHR_texture_basisu.
+
+use serde_json::Value;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Ktx2,
+}
+
+pub const IMAGE_FORMATS: [ImageFormat; 4] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Webp,
+    ImageFormat::Ktx2,
+];
+
+impl ImageFormat {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Ktx2 => "image/ktx2",
+        }
+    }
+    /// File extension used when the image is extracted
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Ktx2 => "ktx2",
+        }
+    }
+    /// The texture extension that refers to images of this format, or None for the formats of glTF 2.0
+    pub fn texture_extension(self) -> Option<&'static str> {
+        match self {
+            ImageFormat::Png | ImageFormat::Jpeg => None,
+            ImageFormat::Webp => Some("EXT_texture_webp"),
+            ImageFormat::Ktx2 => Some("KHR_texture_basisu"),
+        }
+    }
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        IMAGE_FORMATS
+            .into_iter()
+            .find(|f| f.mime_type() == mime_type)
+    }
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "jpeg" => Some(ImageFormat::Jpeg),
+            e => IMAGE_FORMATS.into_iter().find(|f| f.file_extension() == e),
+        }
+    }
+    /// Tell the format from the signature at the beginning of the data
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(ImageFormat::Png)
+        } else if data.starts_with(b"\xff\xd8\xff") {
+            Some(ImageFormat::Jpeg)
+        } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+            Some(ImageFormat::Webp)
+        } else if data.starts_with(b"\xabKTX 20\xbb\r\n\x1a\n") {
+            Some(ImageFormat::Ktx2)
+        } else {
+            None
+        }
+    }
+}
+
+/// The image sampled by the JSON of a texture: the source of EXT_texture_webp or KHR_texture_basisu
+/// if the texture has one of them, or the source of glTF 2.0
+pub fn texture_source(texture: &Value) -> Option<u64> {
+    IMAGE_FORMATS
+        .into_iter()
+        .filter_map(|f| f.texture_extension())
+        .find_map(|e| texture["extensions"][e]["source"].as_u64())
+        .or_else(|| texture["source"].as_u64())
+}
+
+/// Give the textures that have no source of glTF 2.0 the image of their extension as the source,
+/// so that the JSON can be read by gltf_json, which requires one
+pub fn fill_texture_sources(json: &mut Value) {
+    let textures = json.get_mut("textures").and_then(|t| t.as_array_mut());
+    for t in textures.into_iter().flatten() {
+        if t.get("source").is_none() {
+            if let Some(source) = texture_source(t) {
+                t["source"] = Value::from(source);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn formats_are_told_from_the_data_and_the_path() {
+        assert_eq!(
+            ImageFormat::detect(b"\x89PNG\r\n\x1a\n"),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::detect(b"\xff\xd8\xff\xe0"),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(
+            ImageFormat::detect(b"RIFF\x04\0\0\0WEBP"),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(ImageFormat::detect(b"RIFF\x04\0\0\0WAVE"), None);
+        assert_eq!(
+            ImageFormat::detect(b"\xabKTX 20\xbb\r\n\x1a\n"),
+            Some(ImageFormat::Ktx2)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("face.JPEG")),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(
+            ImageFormat::from_path(Path::new("i0_face.ktx2")),
+            Some(ImageFormat::Ktx2)
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("face.gif")), None);
+        for f in IMAGE_FORMATS {
+            assert_eq!(ImageFormat::from_mime_type(f.mime_type()), Some(f));
+        }
+    }
+
+    #[test]
+    fn textures_sample_the_image_of_their_extension() {
+        let mut json = json!({
+            "textures": [
+                { "source": 0 },
+                { "extensions": { "EXT_texture_webp": { "source": 1 } } },
+                { "source": 0, "extensions": { "KHR_texture_basisu": { "source": 2 } } },
+            ],
+        });
+        let sources: Vec<_> = json["textures"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(texture_source)
+            .collect();
+        assert_eq!(sources, vec![Some(0), Some(1), Some(2)]);
+        fill_texture_sources(&mut json);
+        assert_eq!(json["textures"][1]["source"], 1);
+        // The fallback image stays the source of glTF 2.0
+        assert_eq!(json["textures"][2]["source"], 0);
+    }
+}
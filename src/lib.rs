@@ -0,0 +1,42 @@
+//! VRM as a Code: load, inspect, edit and write VRM / glTF binaries.
+//!
+//! [`Document`] holds a loaded file. The other modules build documents from code ([`scene`]) or an
+//! avatar source ([`compile`]), split and rebuild them ([`parts`], [`pack`]) and convert
+//! VRM 0.x files ([`migrate`]). [`validate`] checks documents against the specifications.
+//! [`constraint`] applies node constraints to a [`pose`] of the nodes. [`provenance`] checks the
+//! licenses of the files of a parts directory against the license of the avatar.
+
+#![feature(slice_flatten)]
+#![feature(assert_matches)]
+
+pub mod accessor;
+pub mod animation;
+pub mod compile;
+pub mod constraint;
+pub mod document;
+pub mod expression;
+pub mod extensions;
+pub mod glb;
+pub mod humanoid;
+pub mod image;
+pub mod math;
+pub mod meta;
+pub mod migrate;
+pub mod model;
+pub mod morph;
+pub mod mtoon;
+pub mod pack;
+pub mod parts;
+pub mod pose;
+pub mod provenance;
+pub mod resolve;
+pub mod resources;
+pub mod scene;
+pub mod simulation;
+pub mod skin;
+pub mod spring_bone;
+pub mod validate;
+pub mod vrm;
+pub mod vrm0;
+
+pub use document::Document;
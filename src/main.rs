@@ -1,648 +1,1268 @@
-#![feature(slice_flatten)]
-#![feature(assert_matches)]
-
 use anyhow::anyhow;
-use anyhow::Context;
 use anyhow::Result;
 use argh::FromArgs;
-use gltf::accessor::DataType;
-use gltf::buffer::Source;
-use gltf::Image;
 use gltf::Node;
-use gltf::Semantic;
-use gltf_json::extensions::texture::TextureTransform;
-use gltf_json::extensions::texture::TextureTransformOffset;
-use gltf_json::extensions::texture::TextureTransformRotation;
-use gltf_json::extensions::texture::TextureTransformScale;
-use gltf_json::extras::RawValue;
-use gltf_json::image::MimeType;
-use gltf_json::material::PbrBaseColorFactor;
-use gltf_json::validation::Checked::Valid;
-use gltf_json::Accessor;
-use gltf_json::Asset;
-use gltf_json::Index;
-use std::assert_matches::assert_matches;
-use std::borrow::Cow;
+use gltf_json::validation::Checked;
+use serde_json::Value;
 use std::fs;
-use std::io;
 use std::path::Path;
+use vacation::animation;
+use vacation::compile;
+use vacation::constraint;
+use vacation::expression;
+use vacation::glb::write_glb;
+use vacation::humanoid;
+use vacation::math;
+use vacation::meta;
+use vacation::migrate;
+use vacation::model::Model;
+use vacation::pack;
+use vacation::parts;
+use vacation::pose::Pose;
+use vacation::provenance;
+use vacation::resolve;
+use vacation::simulation;
+use vacation::spring_bone;
+use vacation::validate;
+use vacation::vrm;
+use vacation::Document;
 
 #[derive(FromArgs)]
 /// VRM as a Code
 struct Args {
-    /// path to .vrm file to parse
+    /// path to .vrm / .glb / .gltf file to parse
     #[argh(option)]
     input: Option<String>,
+    /// path to .glb file to generate from the model described in code
+    #[argh(option)]
+    output: Option<String>,
+    #[argh(subcommand)]
+    command: Option<Command>,
 }
 
-fn parse_node(node: &Node, depth: usize) -> Result<()> {
-    print!("{:width$}", "", width = depth);
-    println!("name: {:?}", node.name());
-    for c in node.children() {
-        parse_node(&c, depth + 1)?;
-    }
-    Ok(())
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Compile(CompileArgs),
+    Constraint(ConstraintArgs),
+    Expression(ExpressionArgs),
+    Humanoid(HumanoidArgs),
+    LicenseReport(LicenseReportArgs),
+    Material(MaterialArgs),
+    Meta(MetaArgs),
+    Migrate(MigrateArgs),
+    Pack(PackArgs),
+    Spring(SpringArgs),
+    Validate(ValidateArgs),
 }
 
-fn extract_png_data_from_image(bin: &[u8], m: &Image) -> Result<Vec<u8>> {
-    println!(" Image #{}: name = {:?}", m.index(), m.name());
-    if let gltf::image::Source::View { view, mime_type } = m.source() {
-        println!("  source_type: {mime_type}",);
-        assert_eq!(mime_type, "image/png");
-        let buffer = view.buffer();
-        assert_matches!(buffer.source(), Source::Bin);
-        let offset = view.offset();
-        let length = view.length();
-        Ok(Vec::from(&bin[offset..(offset + length)]))
-    } else {
-        Err(anyhow!("Image not found in the Glb"))
-    }
+#[derive(FromArgs)]
+/// rebuild a .vrm file from a parts directory created by --input
+#[argh(subcommand, name = "pack")]
+struct PackArgs {
+    /// path to the <name>.parts directory
+    #[argh(positional)]
+    parts_dir: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
 }
 
-fn run_input(path: &str) -> Result<()> {
-    let file = fs::File::open(&path)?;
-    let reader = io::BufReader::new(file);
-    let gltf = gltf::Gltf::from_reader(reader)?;
+#[derive(FromArgs)]
+/// check a file against the glTF 2.0 and VRM 1.0 specifications
+#[argh(subcommand, name = "validate")]
+struct ValidateArgs {
+    /// path to the .vrm / .glb / .gltf file to check
+    #[argh(positional)]
+    input: String,
+}
 
-    let file = fs::File::open(&path)?;
-    let reader = io::BufReader::new(file);
-    let bin = gltf::binary::Glb::from_reader(reader)?
-        .bin
-        .context("No binary section")?;
-    println!("BIN section has {} bytes", bin.len());
+#[derive(FromArgs)]
+/// convert a VRM 0.x file into VRM 1.0
+#[argh(subcommand, name = "migrate")]
+struct MigrateArgs {
+    /// path to the VRM 0.x file to convert
+    #[argh(positional)]
+    input: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
 
-    let parts_dir = Path::new(path).with_extension("parts");
-    fs::create_dir_all(parts_dir.clone())?;
+#[derive(FromArgs)]
+/// compile an avatar source file into a VRM 1.0 file
+#[argh(subcommand, name = "compile")]
+struct CompileArgs {
+    /// path to the avatar source (.json) to compile
+    #[argh(positional)]
+    source: String,
+    /// path to .vrm file to write (default: the source path with .vrm extension)
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+}
+
+#[derive(FromArgs)]
+/// list and edit the expressions of a VRM 1.0 file
+#[argh(subcommand, name = "expression")]
+struct ExpressionArgs {
+    #[argh(subcommand)]
+    command: ExpressionCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum ExpressionCommand {
+    List(ExpressionListArgs),
+    Set(ExpressionSetArgs),
+    Rename(ExpressionRenameArgs),
+    Delete(ExpressionDeleteArgs),
+    Remap(ExpressionRemapArgs),
+}
+
+#[derive(FromArgs)]
+/// list the expressions and their binds
+#[argh(subcommand, name = "list")]
+struct ExpressionListArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+}
+
+#[derive(FromArgs)]
+/// create an expression, or replace the one with the same name
+#[argh(subcommand, name = "set")]
+struct ExpressionSetArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// name of the expression: a preset (e.g. happy, aa, blink) or a custom name
+    #[argh(positional)]
+    name: String,
+    /// morph target bind as <node>:<morph target>[=<weight>], by name or index
+    #[argh(option)]
+    bind: Vec<String>,
+    /// material color bind as <material>:<type>=<r>,<g>,<b>,<a>
+    #[argh(option)]
+    color: Vec<String>,
+    /// texture transform bind as <material>=<scale x>,<scale y>,<offset x>,<offset y>
+    #[argh(option)]
+    texture_transform: Vec<String>,
+    /// the expression is either on or off
+    #[argh(switch)]
+    binary: bool,
+    /// how the expression overrides blink: none, block or blend
+    #[argh(option)]
+    override_blink: Option<String>,
+    /// how the expression overrides lookAt: none, block or blend
+    #[argh(option)]
+    override_look_at: Option<String>,
+    /// how the expression overrides the mouth: none, block or blend
+    #[argh(option)]
+    override_mouth: Option<String>,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// rename an expression
+#[argh(subcommand, name = "rename")]
+struct ExpressionRenameArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// current name of the expression
+    #[argh(positional)]
+    from: String,
+    /// new name of the expression
+    #[argh(positional)]
+    to: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// delete an expression
+#[argh(subcommand, name = "delete")]
+struct ExpressionDeleteArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// name of the expression
+    #[argh(positional)]
+    name: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// make all the binds on a morph target bind to another one
+#[argh(subcommand, name = "remap")]
+struct ExpressionRemapArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// morph target bound now, as <node>:<morph target>
+    #[argh(positional)]
+    from: String,
+    /// morph target to bind instead, as <node>:<morph target>
+    #[argh(positional)]
+    to: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
 
-    {
-        let mut path = parts_dir.clone();
-        path.push("input.json");
-        let json = serde_json::to_string_pretty(&gltf.clone().document.into_json())?;
-        fs::write(path, json.into_bytes())?;
+#[derive(FromArgs)]
+/// show and edit the humanoid bone mapping
+#[argh(subcommand, name = "humanoid")]
+struct HumanoidArgs {
+    #[argh(subcommand)]
+    command: HumanoidCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum HumanoidCommand {
+    List(HumanoidListArgs),
+    Auto(HumanoidAutoArgs),
+    Set(HumanoidSetArgs),
+    Unset(HumanoidUnsetArgs),
+}
+
+#[derive(FromArgs)]
+/// print the node tree with the humanoid bone of each node
+#[argh(subcommand, name = "list")]
+struct HumanoidListArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+}
+
+#[derive(FromArgs)]
+/// map the unmapped bones to nodes guessed from their names (Mixamo, VRoid, Blender)
+#[argh(subcommand, name = "auto")]
+struct HumanoidAutoArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// path to .vrm file to write. Only the proposal is printed if omitted.
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+}
+
+#[derive(FromArgs)]
+/// map a humanoid bone to a node
+#[argh(subcommand, name = "set")]
+struct HumanoidSetArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// name of the humanoid bone, e.g. leftUpperArm
+    #[argh(positional)]
+    bone: String,
+    /// name or index of the node
+    #[argh(positional)]
+    node: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// remove the mapping of a humanoid bone
+#[argh(subcommand, name = "unset")]
+struct HumanoidUnsetArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// name of the humanoid bone
+    #[argh(positional)]
+    bone: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// show and edit the MToon (VRMC_materials_mtoon) properties of the materials
+#[argh(subcommand, name = "material")]
+struct MaterialArgs {
+    #[argh(subcommand)]
+    command: MaterialCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum MaterialCommand {
+    List(MaterialListArgs),
+    Set(MaterialSetArgs),
+}
+
+#[derive(FromArgs)]
+/// list the materials and their MToon properties
+#[argh(subcommand, name = "list")]
+struct MaterialListArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+}
+
+#[derive(FromArgs)]
+/// set MToon properties of a material. The material gets MToon with the default values if it has none.
+#[argh(subcommand, name = "set")]
+struct MaterialSetArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// name or index of the material
+    #[argh(positional)]
+    material: String,
+    /// properties as <name>=<value>, e.g. shadeColorFactor=0.8,0.7,0.7 or matcapTexture=2.
+    /// An empty value removes a texture.
+    #[argh(positional)]
+    properties: Vec<String>,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// check the licenses of the parts and images of a parts directory against the license of the avatar,
+/// and write the credits. Provenance is read from <file>.provenance.json, or provenance.json of the directory.
+#[argh(subcommand, name = "license-report")]
+struct LicenseReportArgs {
+    /// path to the <name>.parts directory
+    #[argh(positional)]
+    parts_dir: String,
+    /// path to the credits file to write (default: CREDITS.md in the parts directory)
+    #[argh(option, short = 'o')]
+    output: Option<String>,
+}
+
+#[derive(FromArgs)]
+/// show and edit the meta (name, authors and license) of a VRM 1.0 file
+#[argh(subcommand, name = "meta")]
+struct MetaArgs {
+    #[argh(subcommand)]
+    command: MetaCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum MetaCommand {
+    Get(MetaGetArgs),
+    Set(MetaSetArgs),
+}
+
+#[derive(FromArgs)]
+/// print the properties of meta
+#[argh(subcommand, name = "get")]
+struct MetaGetArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// names of the properties to print (default: all of them), e.g. licenseUrl
+    #[argh(positional)]
+    properties: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// set properties of meta. Enum values are checked against the ones VRM 1.0 allows.
+#[argh(subcommand, name = "set")]
+struct MetaSetArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// properties as <name>=<value>, e.g. commercialUsage=corporation, authors=Alice,Bob or
+    /// thumbnailImage=<image name or index>. An empty value removes an optional property.
+    #[argh(positional)]
+    properties: Vec<String>,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// show and edit the spring bones (VRMC_springBone) of a VRM 1.0 file
+#[argh(subcommand, name = "spring")]
+struct SpringArgs {
+    #[argh(subcommand)]
+    command: SpringCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum SpringCommand {
+    List(SpringListArgs),
+    Set(SpringSetArgs),
+    Delete(SpringDeleteArgs),
+    Bake(SpringBakeArgs),
+}
+
+#[derive(FromArgs)]
+/// list the colliders, collider groups and springs
+#[argh(subcommand, name = "list")]
+struct SpringListArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+}
+
+#[derive(FromArgs)]
+/// set joint settings of a spring
+#[argh(subcommand, name = "set")]
+struct SpringSetArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// name or index of the spring
+    #[argh(positional)]
+    spring: String,
+    /// settings as <name>=<value>, e.g. stiffness=0.8 or gravityDir=0,-1,0.
+    /// The names are hitRadius, stiffness, gravityPower, gravityDir and dragForce.
+    #[argh(positional)]
+    properties: Vec<String>,
+    /// index of the joint in the spring to change (default: all the joints)
+    #[argh(option)]
+    joint: Option<usize>,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// delete a spring. Its colliders are kept.
+#[argh(subcommand, name = "delete")]
+struct SpringDeleteArgs {
+    /// path to the .vrm file
+    #[argh(positional)]
+    input: String,
+    /// name or index of the spring
+    #[argh(positional)]
+    spring: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// show, edit and evaluate the node constraints (VRMC_node_constraint) of a VRM 1.0 file
+#[argh(subcommand, name = "constraint")]
+struct ConstraintArgs {
+    #[argh(subcommand)]
+    command: ConstraintCommand,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+enum ConstraintCommand {
+    List(ConstraintListArgs),
+    Set(ConstraintSetArgs),
+    Delete(ConstraintDeleteArgs),
+    Evaluate(ConstraintEvaluateArgs),
+}
+
+#[derive(FromArgs)]
+/// list the constrained nodes and their sources
+#[argh(subcommand, name = "list")]
+struct ConstraintListArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+}
+
+#[derive(FromArgs)]
+/// constrain the rotation of a node to a source node, replacing its constraint if any
+#[argh(subcommand, name = "set")]
+struct ConstraintSetArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// name or index of the constrained node
+    #[argh(positional)]
+    node: String,
+    /// roll:<X|Y|Z>, aim:<PositiveX|NegativeX|...|NegativeZ> or rotation
+    #[argh(positional)]
+    kind: String,
+    /// name or index of the source node
+    #[argh(positional)]
+    source: String,
+    /// how much of the source rotation is applied, from 0 to 1 (default: 1)
+    #[argh(option, default = "1.0")]
+    weight: f32,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// remove the constraint of a node
+#[argh(subcommand, name = "delete")]
+struct ConstraintDeleteArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// name or index of the constrained node
+    #[argh(positional)]
+    node: String,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+#[derive(FromArgs)]
+/// pose nodes and print the rotations of the constrained nodes
+#[argh(subcommand, name = "evaluate")]
+struct ConstraintEvaluateArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// local rotation of a node from the rest pose as <node>=<x>,<y>,<z>,<w>
+    #[argh(option)]
+    rotate: Vec<String>,
+}
+
+#[derive(FromArgs)]
+/// simulate the springs over an animation and write the joint rotations as a new animation
+#[argh(subcommand, name = "bake")]
+struct SpringBakeArgs {
+    /// path to the .vrm / .glb file
+    #[argh(positional)]
+    input: String,
+    /// name or index of the animation to play (default: none, the rest pose)
+    #[argh(option)]
+    animation: Option<String>,
+    /// simulation steps and key frames per second (default: 60)
+    #[argh(option, default = "60.0")]
+    fps: f32,
+    /// seconds to simulate (default: the length of the animation, or 1 without one)
+    #[argh(option)]
+    duration: Option<f32>,
+    /// force added to gravity as <x>,<y>,<z>, e.g. wind (default: 0,0,0)
+    #[argh(option)]
+    external_force: Option<String>,
+    /// name of the baked animation (default: "<animation> springs" or "springs")
+    #[argh(option)]
+    name: Option<String>,
+    /// path to .vrm file to write
+    #[argh(option, short = 'o')]
+    output: String,
+}
+
+fn parse_node(node: &Node, depth: usize, humanoid: Option<&vrm::Humanoid>) -> Result<()> {
+    print!("{:width$}", "", width = depth);
+    match humanoid.and_then(|h| h.bone_of_node(node.index() as u32)) {
+        Some(bone) => println!("name: {:?} <- {}", node.name(), bone),
+        None => println!("name: {:?}", node.name()),
+    }
+    for c in node.children() {
+        parse_node(&c, depth + 1, humanoid)?;
     }
+    Ok(())
+}
+
+fn run_input(path: &str) -> Result<()> {
+    let doc = Document::load(path)?;
+    let gltf = doc.gltf()?;
+    println!("BIN section has {} bytes", doc.bin.len());
 
     println!("extensions_used: {:?}", gltf.extensions_used());
     println!("extensions_required: {:?}", gltf.extensions_required());
+    let vrm = doc.vrm()?;
+    if let Some(vrm) = &vrm {
+        println!(
+            "VRM {}: name = {:?}, authors = {:?}",
+            vrm.spec_version, vrm.meta.name, vrm.meta.authors
+        );
+        for (bone, b) in &vrm.humanoid.human_bones {
+            println!(" {}: node #{}", bone, b.node);
+        }
+        let missing = vrm.humanoid.missing_required_bones();
+        if !missing.is_empty() {
+            eprintln!(
+                "Warning: required humanoid bones are not mapped: {:?}",
+                missing
+            );
+        }
+        if let Some(e) = &vrm.expressions {
+            println!(
+                " expressions: preset = {:?}, custom = {:?}",
+                e.preset.keys().collect::<Vec<_>>(),
+                e.custom.keys().collect::<Vec<_>>()
+            );
+        }
+    }
+    if let Some(spring_bone) = doc.spring_bone()? {
+        println!(
+            " springs: {:?}",
+            spring_bone
+                .springs
+                .iter()
+                .map(|s| s.name.as_deref().unwrap_or_default())
+                .collect::<Vec<_>>()
+        );
+    }
+    if let Some(vrm0) = doc.vrm0()? {
+        println!(
+            "VRM {} (0.x): title = {:?}, author = {:?}",
+            vrm0.spec_version.as_deref().unwrap_or("unknown"),
+            vrm0.meta.title,
+            vrm0.meta.author
+        );
+        for b in &vrm0.humanoid.human_bones {
+            println!(" {}: node #{}", b.bone, b.node);
+        }
+        println!(
+            " blend shape groups: {:?}",
+            vrm0.blend_shape_master
+                .blend_shape_groups
+                .iter()
+                .map(|g| &g.name)
+                .collect::<Vec<_>>()
+        );
+        println!(" Run `vacation migrate` to convert it into VRM 1.0");
+    }
 
     for scene in gltf.scenes() {
         println!("Scene #{}", scene.index(),);
         for node in scene.nodes() {
-            parse_node(&node, 0)?;
+            parse_node(&node, 0, vrm.as_ref().map(|v| &v.humanoid))?;
         }
     }
-    let mut pcount = 0;
-    for mesh in gltf.meshes() {
-        println!(" Mesh #{}: name = {:?}", mesh.index(), mesh.name());
-        for p in mesh.primitives() {
-            assert_eq!(p.mode(), gltf::mesh::Mode::Triangles);
-            assert!(p.get(&Semantic::TexCoords(1)).is_none());
-            assert!(p.get(&Semantic::Colors(0)).is_none());
-            assert!(p.get(&Semantic::Normals).is_some());
-            assert!(p.get(&Semantic::Tangents).is_none());
-            //assert!(p.get(&Semantic::Joints(0)).is_some());
-            //assert!(p.get(&Semantic::Joints(1)).is_none());
-            //assert!(p.get(&Semantic::Weights(0)).is_some());
-            //assert!(p.get(&Semantic::Weights(1)).is_none());
-            if let (Some(ap), Some(an), Some(ai), Some(at0)) = (
-                p.get(&Semantic::Positions),
-                p.get(&Semantic::Normals),
-                p.indices(),
-                p.get(&Semantic::TexCoords(0)),
-            ) {
-                let pbr = p.material().pbr_metallic_roughness();
-                println!(
-                    "pbr_factors: base: {:?}, metallic: {:?}, roughness: {:?}",
-                    pbr.base_color_factor(),
-                    pbr.metallic_factor(),
-                    pbr.roughness_factor(),
-                );
-                let bct = pbr.base_color_texture().unwrap();
+    let parts_dir = Path::new(path).with_extension("parts");
+    let split = parts::split(&doc, &parts_dir)?;
+    for f in &split.files {
+        println!(" {}", f);
+    }
+    for e in &split.errors {
+        eprintln!("Warning: {}", e);
+    }
+    println!("Written to {}", parts_dir.to_string_lossy());
+    Ok(())
+}
+
+fn run_migrate(input: &str, output: &str) -> Result<()> {
+    let mut doc = Document::load(input)?;
+    if let Some(vrm0) = doc.vrm0()? {
+        println!(
+            "Migrating VRM {} (exported by {}) to VRM 1.0",
+            vrm0.spec_version.as_deref().unwrap_or("0.x"),
+            vrm0.exporter_version.as_deref().unwrap_or("unknown")
+        );
+    }
+    for w in migrate::migrate(&mut doc)? {
+        eprintln!("Warning: {}", w);
+    }
+    save(&doc, output)
+}
+
+fn print_expression(name: &str, e: &vrm::Expression) {
+    println!(
+        "{}{}: binary = {}, override blink = {:?}, lookAt = {:?}, mouth = {:?}",
+        name,
+        if expression::is_preset(name) {
+            " (preset)"
+        } else {
+            ""
+        },
+        e.is_binary,
+        e.override_blink,
+        e.override_look_at,
+        e.override_mouth
+    );
+    for b in &e.morph_target_binds {
+        println!(
+            " morph target #{} of node #{}: {}",
+            b.index, b.node, b.weight
+        );
+    }
+    for b in &e.material_color_binds {
+        println!(
+            " {:?} of material #{}: {:?}",
+            b.type_, b.material, b.target_value
+        );
+    }
+    for b in &e.texture_transform_binds {
+        println!(
+            " texture transform of material #{}: scale = {:?}, offset = {:?}",
+            b.material, b.scale, b.offset
+        );
+    }
+}
+
+/// Load a VRM 1.0 file, edit its expressions with `f` and save it to `output`
+fn edit_expressions(
+    input: &str,
+    output: &str,
+    f: impl FnOnce(&gltf_json::Root, &mut vrm::Expressions) -> Result<()>,
+) -> Result<()> {
+    let mut doc = Document::load(input)?;
+    let mut vrm = doc
+        .vrm()?
+        .ok_or_else(|| anyhow!("{} is not a VRM 1.0 file", input))?;
+    f(
+        &doc.root()?,
+        vrm.expressions.get_or_insert_with(Default::default),
+    )?;
+    doc.set_vrm(&vrm)?;
+    save(&doc, output)
+}
+
+fn run_expression(command: ExpressionCommand) -> Result<()> {
+    match command {
+        ExpressionCommand::List(args) => {
+            let doc = Document::load(&args.input)?;
+            let vrm = doc
+                .vrm()?
+                .ok_or_else(|| anyhow!("{} is not a VRM 1.0 file", args.input))?;
+            for (name, e) in vrm.expressions.iter().flat_map(|e| e.iter()) {
+                print_expression(name, e);
+            }
+            Ok(())
+        }
+        ExpressionCommand::Set(args) => edit_expressions(&args.input, &args.output, |root, es| {
+            let mut e = vrm::Expression {
+                is_binary: args.binary,
+                ..Default::default()
+            };
+            for b in &args.bind {
+                e.morph_target_binds
+                    .push(expression::parse_morph_target_bind(root, b)?);
+            }
+            for b in &args.color {
+                e.material_color_binds
+                    .push(expression::parse_material_color_bind(root, b)?);
+            }
+            for b in &args.texture_transform {
+                e.texture_transform_binds
+                    .push(expression::parse_texture_transform_bind(root, b)?);
+            }
+            if let Some(o) = &args.override_blink {
+                e.override_blink = expression::parse_override(o)?;
+            }
+            if let Some(o) = &args.override_look_at {
+                e.override_look_at = expression::parse_override(o)?;
+            }
+            if let Some(o) = &args.override_mouth {
+                e.override_mouth = expression::parse_override(o)?;
+            }
+            print_expression(&args.name, &e);
+            if es.insert(&args.name, e).is_some() {
+                println!("Replaced {}", args.name);
+            }
+            Ok(())
+        }),
+        ExpressionCommand::Rename(args) => edit_expressions(&args.input, &args.output, |_, es| {
+            es.rename(&args.from, &args.to)
+        }),
+        ExpressionCommand::Delete(args) => edit_expressions(&args.input, &args.output, |_, es| {
+            es.remove(&args.name)
+                .map(|_| ())
+                .ok_or_else(|| anyhow!("Expression {} does not exist", args.name))
+        }),
+        ExpressionCommand::Remap(args) => {
+            edit_expressions(&args.input, &args.output, |root, es| {
+                let from = resolve::morph_target(root, &args.from)?;
+                let to = resolve::morph_target(root, &args.to)?;
+                let count = es.remap_morph_target(from, to);
+                println!("Remapped {} binds", count);
+                Ok(())
+            })
+        }
+    }
+}
+
+/// The VRMC_vrm extension of a document, or a new one with an empty meta if it has none
+fn vrm_or_new(doc: &Document) -> Result<vrm::Vrm> {
+    Ok(match doc.vrm()? {
+        Some(vrm) => vrm,
+        None => {
+            eprintln!(
+                "Warning: the file has no VRMC_vrm extension, a new one with empty meta is created"
+            );
+            vrm::Vrm::new(Default::default(), Default::default())
+        }
+    })
+}
+
+fn print_missing_bones(humanoid: &vrm::Humanoid) {
+    let missing = humanoid.missing_required_bones();
+    if missing.is_empty() {
+        println!("All the required bones are mapped");
+    } else {
+        println!("Required bones not mapped: {:?}", missing);
+    }
+}
+
+fn run_humanoid(command: HumanoidCommand) -> Result<()> {
+    match command {
+        HumanoidCommand::List(args) => {
+            let doc = Document::load(&args.input)?;
+            let humanoid = doc.vrm()?.map(|v| v.humanoid).unwrap_or_default();
+            for line in humanoid::tree_lines(&doc.root()?, &humanoid) {
+                println!("{}", line);
+            }
+            print_missing_bones(&humanoid);
+            Ok(())
+        }
+        HumanoidCommand::Auto(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let mut vrm = vrm_or_new(&doc)?;
+            let root = doc.root()?;
+            for (bone, node) in humanoid::auto_map(&root, &vrm.humanoid) {
                 println!(
-                    "Base Color Texture: tex_coord: {}, texture.index: {}, texture.source.index: {}, {:?}, {:?}, {:?}, {:?}, {:?}",
-                    bct.tex_coord(),
-                    bct.texture().index(),
-                    bct.texture().source().index(),
-                    bct.texture().sampler().mag_filter(),
-                    bct.texture().sampler().min_filter(),
-                    bct.texture().sampler().wrap_s(),
-                    bct.texture().sampler().wrap_t(),
-                    bct.texture_transform().is_some(),
+                    "{}: node #{} {:?}",
+                    bone, node, root.nodes[node as usize].name
                 );
-                assert!(pbr.metallic_roughness_texture().is_none());
-                let png_data = extract_png_data_from_image(&bin, &bct.texture().source())
-                    .context("Failed to find a png image for a texture")?;
-                let tex_coords0 = {
-                    assert_eq!(at0.dimensions(), gltf::accessor::Dimensions::Vec2);
-                    assert_eq!(at0.data_type(), DataType::F32);
-                    let v = at0.view().context("TexCoords have no view")?;
-                    assert_eq!(v.stride(), None);
-                    let b = v.buffer();
-                    assert_matches!(b.source(), gltf::buffer::Source::Bin);
-                    let data = &bin[v.offset()..(v.offset() + v.length())];
-                    let data: Vec<f32> = data
-                        .chunks_exact(4)
-                        .map(|ve| {
-                            let mut vec = [0u8; 4];
-                            vec.copy_from_slice(ve);
-                            f32::from_le_bytes(vec)
-                        })
-                        .collect();
-                    let data: Vec<[f32; 2]> = data.chunks_exact(2).map(|v| [v[0], v[1]]).collect();
-                    data
-                };
-                let vertices = {
-                    assert_eq!(ap.dimensions(), gltf::accessor::Dimensions::Vec3);
-                    assert_eq!(ap.data_type(), DataType::F32);
-                    let v = ap.view().context("Positions have no view")?;
-                    assert_eq!(v.stride(), None);
-                    let b = v.buffer();
-                    assert_matches!(b.source(), gltf::buffer::Source::Bin);
-                    let data = &bin[v.offset()..(v.offset() + v.length())];
-                    let data: Vec<f32> = data
-                        .chunks_exact(4)
-                        .map(|ve| {
-                            let mut vec = [0u8; 4];
-                            vec.copy_from_slice(ve);
-                            f32::from_le_bytes(vec)
-                        })
-                        .collect();
-                    let data: Vec<[f32; 3]> =
-                        data.chunks_exact(3).map(|v| [v[0], v[1], v[2]]).collect();
-                    data
-                };
-                let normals = {
-                    assert_eq!(an.dimensions(), gltf::accessor::Dimensions::Vec3);
-                    assert_eq!(an.data_type(), DataType::F32);
-                    let v = an.view().context("Positions have no view")?;
-                    assert_eq!(v.stride(), None);
-                    let b = v.buffer();
-                    assert_matches!(b.source(), gltf::buffer::Source::Bin);
-                    let data = &bin[v.offset()..(v.offset() + v.length())];
-                    let data: Vec<f32> = data
-                        .chunks_exact(4)
-                        .map(|ve| {
-                            let mut vec = [0u8; 4];
-                            vec.copy_from_slice(ve);
-                            f32::from_le_bytes(vec)
-                        })
-                        .collect();
-                    let data: Vec<[f32; 3]> =
-                        data.chunks_exact(3).map(|v| [v[0], v[1], v[2]]).collect();
-                    data
-                };
+                vrm.humanoid.set(&bone, node)?;
+            }
+            print_missing_bones(&vrm.humanoid);
+            match args.output {
+                Some(output) => {
+                    doc.set_vrm(&vrm)?;
+                    save(&doc, &output)
+                }
+                None => Ok(()),
+            }
+        }
+        HumanoidCommand::Set(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let mut vrm = vrm_or_new(&doc)?;
+            let node = resolve::node(&doc.root()?, &args.node)?;
+            vrm.humanoid.set(&args.bone, node)?;
+            doc.set_vrm(&vrm)?;
+            save(&doc, &args.output)
+        }
+        HumanoidCommand::Unset(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let mut vrm = vrm_or_new(&doc)?;
+            vrm.humanoid
+                .remove(&args.bone)
+                .ok_or_else(|| anyhow!("{} is not mapped", args.bone))?;
+            doc.set_vrm(&vrm)?;
+            save(&doc, &args.output)
+        }
+    }
+}
 
-                let indices = {
-                    assert_eq!(ai.dimensions(), gltf::accessor::Dimensions::Scalar);
-                    let v = ai.view().context("Indices have no view")?;
-                    assert_eq!(v.stride(), None);
-                    let b = v.buffer();
-                    assert_matches!(b.source(), gltf::buffer::Source::Bin);
-                    let data = &bin[v.offset()..(v.offset() + v.length())];
-                    let dt = ai.data_type();
-                    let data: Vec<u32> = match dt {
-                        DataType::U32 => data
-                            .chunks_exact(4)
-                            .map(|ve| {
-                                let mut vec = [0u8; 4];
-                                vec.copy_from_slice(ve);
-                                u32::from_le_bytes(vec)
-                            })
-                            .collect(),
-                        DataType::U16 => data
-                            .chunks_exact(2)
-                            .map(|ve| {
-                                let mut vec = [0u8; 2];
-                                vec.copy_from_slice(ve);
-                                u16::from_le_bytes(vec) as u32
-                            })
-                            .collect(),
-                        dt => return Err(anyhow!("DataType {:?} is not supported yet", dt)),
-                    };
-                    let data: Vec<[u32; 3]> =
-                        data.chunks_exact(3).map(|v| [v[0], v[1], v[2]]).collect();
-                    data
+fn run_material(command: MaterialCommand) -> Result<()> {
+    match command {
+        MaterialCommand::List(args) => {
+            let doc = Document::load(&args.input)?;
+            let root = doc.root()?;
+            for (i, m) in root.materials.iter().enumerate() {
+                let alpha_mode = match m.alpha_mode {
+                    Checked::Valid(mode) => format!("{:?}", mode),
+                    Checked::Invalid => "invalid".to_string(),
                 };
                 println!(
-                    "    primitive {}: {} vertices, {} triangles in {:?}",
-                    p.index(),
-                    vertices.len(),
-                    indices.len(),
-                    p.bounding_box(),
+                    "#{} {}: alpha mode = {}, base color = {:?}",
+                    i,
+                    m.name.as_deref().unwrap_or_default(),
+                    alpha_mode,
+                    m.pbr_metallic_roughness.base_color_factor.0
                 );
-                let mut path = parts_dir.clone();
-                path.push(format!(
-                    "{}{}_{}.glb",
-                    mesh.name().unwrap_or("None"),
-                    mesh.index(),
-                    p.index(),
-                ));
-                let path = path.to_string_lossy();
-                write_glb(
-                    &vertices,
-                    &indices,
-                    &normals,
-                    Some((&png_data, tex_coords0.as_slice())),
-                    Some([0f32, 0f32, pcount as f32 / 10.0]),
-                    &path,
-                )?;
-                pcount += 1;
+                let mtoon = match doc.mtoon(i)? {
+                    Some(mtoon) => mtoon,
+                    None => continue,
+                };
+                // Through a string so that f32 is printed as is, not widened to f64
+                let json: Value = serde_json::from_str(&serde_json::to_string(&mtoon)?)?;
+                if let Value::Object(properties) = json {
+                    for (name, value) in properties {
+                        println!(" {}: {}", name, value);
+                    }
+                }
             }
+            Ok(())
+        }
+        MaterialCommand::Set(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let material = resolve::material(&doc.root()?, &args.material)? as usize;
+            let mut mtoon = doc.mtoon(material)?.unwrap_or_default();
+            for p in &args.properties {
+                let (name, value) = p
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("{} should be <name>=<value>", p))?;
+                mtoon.set_property(name, value)?;
+            }
+            doc.set_mtoon(material, &mtoon)?;
+            save(&doc, &args.output)
         }
     }
-    for t in gltf.textures() {
-        println!(" Texture #{}: name = {:?}", t.index(), t.name());
-    }
-    for m in gltf.images() {
-        let png_data = extract_png_data_from_image(&bin, &m).context("Failed to get png data")?;
-        let mut path = parts_dir.clone();
-        path.push(format!("i{}_{}.png", m.index(), m.name().unwrap_or("None"),));
-        let path = path.to_string_lossy().into_owned();
-        fs::write(path, png_data)?;
-    }
-    Ok(())
 }
 
-/// Calculate bounding coordinates of a list of vertices, used for the clipping distance of the model
-fn bounding_coords3d(points: &[[f32; 3]]) -> ([f32; 3], [f32; 3]) {
-    let mut min = [f32::MAX, f32::MAX, f32::MAX];
-    let mut max = [f32::MIN, f32::MIN, f32::MIN];
-
-    for p in points {
-        for i in 0..3 {
-            min[i] = f32::min(min[i], p[i]);
-            max[i] = f32::max(max[i], p[i]);
+fn run_meta(command: MetaCommand) -> Result<()> {
+    match command {
+        MetaCommand::Get(args) => {
+            let doc = Document::load(&args.input)?;
+            let vrm = doc
+                .vrm()?
+                .ok_or_else(|| anyhow!("{} has no {}", args.input, vrm::EXTENSION_NAME))?;
+            let names: Vec<&str> = if args.properties.is_empty() {
+                meta::PROPERTIES.to_vec()
+            } else {
+                args.properties.iter().map(|p| p.as_str()).collect()
+            };
+            for name in names {
+                match vrm.meta.property(name)? {
+                    Some(value) => println!("{}: {}", name, value),
+                    None => println!("{}: (not set)", name),
+                }
+            }
+            Ok(())
+        }
+        MetaCommand::Set(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let mut vrm = vrm_or_new(&doc)?;
+            let root = doc.root()?;
+            for p in &args.properties {
+                let (name, value) = p
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("{} should be <name>=<value>", p))?;
+                let value = match (name, value) {
+                    ("thumbnailImage", v) if !v.is_empty() => resolve::image(&root, v)?.to_string(),
+                    (_, v) => v.to_string(),
+                };
+                vrm.meta.set_property(name, &value)?;
+            }
+            for conflict in vrm.meta.license_conflicts() {
+                eprintln!("Warning: {}", conflict);
+            }
+            doc.set_vrm(&vrm)?;
+            save(&doc, &args.output)
         }
     }
-    (min, max)
 }
-fn bounding_coords2d(points: &[[f32; 2]]) -> ([f32; 2], [f32; 2]) {
-    let mut min = [f32::MAX, f32::MAX];
-    let mut max = [f32::MIN, f32::MIN];
 
-    for p in points {
-        for i in 0..2 {
-            min[i] = f32::min(min[i], p[i]);
-            max[i] = f32::max(max[i], p[i]);
+fn run_constraint(command: ConstraintCommand) -> Result<()> {
+    match command {
+        ConstraintCommand::List(args) => {
+            let doc = Document::load(&args.input)?;
+            let root = doc.root()?;
+            let name = |node: usize| root.nodes[node].name.as_deref().unwrap_or_default();
+            for (node, c) in doc.node_constraints()? {
+                let source = c.constraint.source() as usize;
+                println!(
+                    "node #{} {:?}: {:?} (source {:?})",
+                    node,
+                    name(node),
+                    c.constraint,
+                    root.nodes.get(source).and_then(|n| n.name.as_deref())
+                );
+            }
+            Ok(())
+        }
+        ConstraintCommand::Set(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let root = doc.root()?;
+            let node = resolve::node(&root, &args.node)? as usize;
+            let source = resolve::node(&root, &args.source)?;
+            let c = constraint::Constraint::parse(&args.kind, source, args.weight)?;
+            doc.set_node_constraint(node, &constraint::NodeConstraint::new(c))?;
+            save(&doc, &args.output)
+        }
+        ConstraintCommand::Delete(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let node = resolve::node(&doc.root()?, &args.node)? as usize;
+            if !doc.remove_node_constraint(node)? {
+                return Err(anyhow!("{} has no constraint", args.node));
+            }
+            save(&doc, &args.output)
+        }
+        ConstraintCommand::Evaluate(args) => {
+            let doc = Document::load(&args.input)?;
+            let root = doc.root()?;
+            let rest = Pose::rest(&root)?;
+            let mut pose = rest.clone();
+            for r in &args.rotate {
+                let (node, rotation) = r
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("{} should be <node>=<x>,<y>,<z>,<w>", r))?;
+                let node = resolve::node(&root, node)? as usize;
+                let q = rotation
+                    .split(',')
+                    .map(|v| v.trim().parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let q: [f32; 4] = q
+                    .try_into()
+                    .map_err(|_| anyhow!("{} should have 4 numbers", rotation))?;
+                let t = &mut pose.local[node];
+                t.rotation = math::quat_normalize(math::quat_mul(t.rotation, q));
+            }
+            let constraints = doc.node_constraints()?;
+            constraint::evaluate(&constraints, &rest, &mut pose)?;
+            for (node, _) in &constraints {
+                println!(
+                    "node #{} {:?}: rotation = {:?}",
+                    node,
+                    root.nodes[*node].name.as_deref().unwrap_or_default(),
+                    pose.local[*node].rotation
+                );
+            }
+            Ok(())
         }
     }
-    (min, max)
 }
 
-fn align_to_multiple_of_four(n: &mut u32) {
-    *n = (*n + 3) & !3;
+fn print_joint(root: &gltf_json::Root, joint: &spring_bone::SpringJoint) {
+    println!(
+        "  node #{} {:?}: hitRadius = {}, stiffness = {}, gravityPower = {}, gravityDir = {:?}, dragForce = {}",
+        joint.node,
+        root.nodes
+            .get(joint.node as usize)
+            .and_then(|n| n.name.as_deref())
+            .unwrap_or_default(),
+        joint.hit_radius,
+        joint.stiffness,
+        joint.gravity_power,
+        joint.gravity_dir,
+        joint.drag_force
+    );
 }
 
-fn append_bytes<T>(bin: &mut Vec<u8>, src: &[T]) -> (u32, u32) {
-    let ofs = bin.len();
-    assert_eq!(ofs % 4, 0);
-    let src: &[u8] = unsafe {
-        std::slice::from_raw_parts(
-            src.as_ptr() as *const u8,
-            src.len() * std::mem::size_of::<T>(),
-        )
+fn run_spring(command: SpringCommand) -> Result<()> {
+    let spring_bone_of = |doc: &Document, input: &str| {
+        doc.spring_bone()?
+            .ok_or_else(|| anyhow!("{} has no {}", input, spring_bone::EXTENSION_NAME))
     };
-    let len = src.len();
-    // Append the data
-    bin.resize(bin.len() + len, 0);
-    bin[ofs..ofs + len].copy_from_slice(&src[0..len]);
-    // Insert padding if needed
-    while bin.len() % 4 != 0 {
-        bin.push(0); // pad to multiple of four bytes
+    match command {
+        SpringCommand::List(args) => {
+            let doc = Document::load(&args.input)?;
+            let spring_bone = spring_bone_of(&doc, &args.input)?;
+            let root = doc.root()?;
+            for (i, c) in spring_bone.colliders.iter().enumerate() {
+                match c.extended_shape() {
+                    Some(shape) => println!(
+                        "collider #{}: node #{}, {:?} (fallback {:?})",
+                        i, c.node, shape, c.shape
+                    ),
+                    None => println!("collider #{}: node #{}, {:?}", i, c.node, c.shape),
+                }
+            }
+            for (i, g) in spring_bone.collider_groups.iter().enumerate() {
+                println!(
+                    "collider group #{} {}: colliders = {:?}",
+                    i,
+                    g.name.as_deref().unwrap_or_default(),
+                    g.colliders
+                );
+            }
+            for (i, s) in spring_bone.springs.iter().enumerate() {
+                println!(
+                    "spring #{} {}: collider groups = {:?}, center = {:?}",
+                    i,
+                    s.name.as_deref().unwrap_or_default(),
+                    s.collider_groups,
+                    s.center
+                );
+                for j in &s.joints {
+                    print_joint(&root, j);
+                }
+            }
+            Ok(())
+        }
+        SpringCommand::Set(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let mut spring_bone = spring_bone_of(&doc, &args.input)?;
+            let i = spring_bone.resolve_spring(&args.spring)?;
+            let joints = &mut spring_bone.springs[i].joints;
+            let joints = match args.joint {
+                Some(j) if j < joints.len() => &mut joints[j..j + 1],
+                Some(j) => return Err(anyhow!("Spring {} has no joint #{}", args.spring, j)),
+                None => &mut joints[..],
+            };
+            let root = doc.root()?;
+            for joint in joints {
+                for p in &args.properties {
+                    let (name, value) = p
+                        .split_once('=')
+                        .ok_or_else(|| anyhow!("{} should be <name>=<value>", p))?;
+                    joint.set_property(name, value)?;
+                }
+                print_joint(&root, joint);
+            }
+            doc.set_spring_bone(&spring_bone)?;
+            save(&doc, &args.output)
+        }
+        SpringCommand::Bake(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let root = doc.root()?;
+            let animation = match &args.animation {
+                Some(a) => Some(resolve::animation(&root, a)? as usize),
+                None => None,
+            };
+            let duration = match (args.duration, animation) {
+                (Some(d), _) => d,
+                (None, Some(a)) => animation::Animation::read(&root, doc.buffers(), a)?.duration(),
+                (None, None) => 1.0,
+            };
+            let external_force = match &args.external_force {
+                Some(f) => {
+                    let f = f
+                        .split(',')
+                        .map(|v| v.trim().parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()?;
+                    f.try_into()
+                        .map_err(|_| anyhow!("--external-force should have 3 numbers"))?
+                }
+                None => [0.0; 3],
+            };
+            let settings = simulation::Settings {
+                fps: args.fps,
+                duration,
+                external_force,
+            };
+            let (times, tracks) = simulation::bake(&doc, animation, &settings)?;
+            let name = args.name.unwrap_or_else(|| {
+                match animation.and_then(|a| root.animations[a].name.as_deref()) {
+                    Some(a) => format!("{} springs", a),
+                    None => "springs".to_string(),
+                }
+            });
+            let index =
+                animation::write_rotation_tracks(&mut doc, &name, &times, &tracks, animation)?;
+            println!(
+                "Baked {} joints over {} frames into animation #{} {:?}",
+                tracks.len(),
+                times.len(),
+                index,
+                name
+            );
+            save(&doc, &args.output)
+        }
+        SpringCommand::Delete(args) => {
+            let mut doc = Document::load(&args.input)?;
+            let mut spring_bone = spring_bone_of(&doc, &args.input)?;
+            let i = spring_bone.resolve_spring(&args.spring)?;
+            spring_bone.springs.remove(i);
+            doc.set_spring_bone(&spring_bone)?;
+            save(&doc, &args.output)
+        }
     }
-    eprintln!("append_bytes: added {} bytes at ofs {}", len, ofs);
-    (ofs as u32, len as u32)
-}
-fn write_glb(
-    vertices: &[[f32; 3]],
-    indices: &[[u32; 3]],
-    normals: &[[f32; 3]],
-    material: Option<(&[u8], &[[f32; 2]])>,
-    translation: Option<[f32; 3]>,
-    path: &str,
-) -> Result<()> {
-    eprintln!("Generating {}...", path);
-    let mut bin = Vec::new();
-    let (bin_vertices_ofs, bin_vertices_len) = append_bytes(&mut bin, &vertices);
-    let (bin_normals_ofs, bin_normals_len) = append_bytes(&mut bin, &normals);
-    let indices = indices.flatten();
-    let (bin_indices_ofs, bin_indices_len) = append_bytes(&mut bin, &indices);
-
-    //
-    // Buffer views
-    //
-    let mut buffer_views = Vec::new();
-
-    let vertex_buffer_view_idx = gltf_json::Index::new(buffer_views.len() as u32);
-    buffer_views.push(gltf_json::buffer::View {
-        buffer: gltf_json::Index::new(0),
-        byte_length: bin_vertices_len,
-        byte_offset: Some(bin_vertices_ofs),
-        byte_stride: None,
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        target: None,
-    });
-
-    let normals_buffer_view_idx = gltf_json::Index::new(buffer_views.len() as u32);
-    buffer_views.push(gltf_json::buffer::View {
-        buffer: gltf_json::Index::new(0),
-        byte_length: bin_normals_len,
-        byte_offset: Some(bin_normals_ofs),
-        byte_stride: None,
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        target: None,
-    });
-
-    let indices_buffer_view_idx = gltf_json::Index::new(buffer_views.len() as u32);
-    buffer_views.push(gltf_json::buffer::View {
-        buffer: gltf_json::Index::new(0),
-        byte_length: bin_indices_len,
-        byte_offset: Some(bin_indices_ofs),
-        byte_stride: None,
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        target: None,
-    });
-
-    //
-    // Accessors
-    //
-    let mut accessors = Vec::new();
-
-    let (min, max) = bounding_coords3d(vertices);
-    let positions_accessor_idx = gltf_json::Index::new(accessors.len() as u32);
-    accessors.push(gltf_json::Accessor {
-        buffer_view: Some(vertex_buffer_view_idx),
-        byte_offset: 0,
-        count: vertices.len() as u32,
-        component_type: Valid(gltf_json::accessor::GenericComponentType(
-            gltf_json::accessor::ComponentType::F32,
-        )),
-        extensions: Default::default(),
-        extras: Default::default(),
-        type_: Valid(gltf_json::accessor::Type::Vec3),
-        min: Some(gltf_json::Value::from(Vec::from(min))),
-        max: Some(gltf_json::Value::from(Vec::from(max))),
-        name: None,
-        normalized: false,
-        sparse: None,
-    });
-    let (min, max) = bounding_coords3d(normals);
-    let normals_accessor_idx: Index<Accessor> = gltf_json::Index::new(accessors.len() as u32);
-    accessors.push(gltf_json::Accessor {
-        buffer_view: Some(normals_buffer_view_idx),
-        byte_offset: 0,
-        count: normals.len() as u32,
-        component_type: Valid(gltf_json::accessor::GenericComponentType(
-            gltf_json::accessor::ComponentType::F32,
-        )),
-        extensions: Default::default(),
-        extras: Default::default(),
-        type_: Valid(gltf_json::accessor::Type::Vec3),
-        min: Some(gltf_json::Value::from(Vec::from(min))),
-        max: Some(gltf_json::Value::from(Vec::from(max))),
-        name: None,
-        normalized: false,
-        sparse: None,
-    });
-    let indices_accessor_idx = gltf_json::Index::new(accessors.len() as u32);
-    accessors.push(gltf_json::Accessor {
-        buffer_view: Some(indices_buffer_view_idx),
-        byte_offset: 0,
-        count: indices.len() as u32,
-        component_type: Valid(gltf_json::accessor::GenericComponentType(
-            gltf_json::accessor::ComponentType::U32,
-        )),
-        extensions: Default::default(),
-        extras: Default::default(),
-        type_: Valid(gltf_json::accessor::Type::Scalar),
-        min: None,
-        max: None,
-        name: None,
-        normalized: false,
-        sparse: None,
-    });
-
-    //
-    // Primitive
-    //
-
-    let mut attributes = std::collections::HashMap::new();
-    attributes.insert(
-        Valid(gltf_json::mesh::Semantic::Positions),
-        positions_accessor_idx,
-    );
-    attributes.insert(
-        Valid(gltf_json::mesh::Semantic::Normals),
-        normals_accessor_idx,
-    );
-    //
-    // Material related objects
-    //
-    let mut images = Vec::new();
-    let mut textures = Vec::new();
-    let mut materials = Vec::new();
-    let mut samplers = Vec::new();
-    let material = if let Some((png_data, uv)) = material {
-        let (png_ofs, png_len) = append_bytes(&mut bin, png_data);
-        let (uv_ofs, uv_len) = append_bytes(&mut bin, uv.flatten());
-        let png_buffer_view_idx = gltf_json::Index::new(buffer_views.len() as u32);
-        buffer_views.push(gltf_json::buffer::View {
-            buffer: gltf_json::Index::new(0),
-            byte_length: png_len,
-            byte_offset: Some(png_ofs),
-            byte_stride: None,
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: None,
-            target: None,
-        });
-        let uv_buffer_view_idx = gltf_json::Index::new(buffer_views.len() as u32);
-        buffer_views.push(gltf_json::buffer::View {
-            buffer: gltf_json::Index::new(0),
-            byte_length: uv_len,
-            byte_offset: Some(uv_ofs),
-            byte_stride: None,
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: None,
-            target: None,
-        });
-        let (min, max) = bounding_coords2d(uv);
-        let uv_accessor_idx = gltf_json::Index::new(accessors.len() as u32);
-        accessors.push(gltf_json::Accessor {
-            buffer_view: Some(uv_buffer_view_idx),
-            byte_offset: 0,
-            count: vertices.len() as u32,
-            component_type: Valid(gltf_json::accessor::GenericComponentType(
-                gltf_json::accessor::ComponentType::F32,
-            )),
-            extensions: Default::default(),
-            extras: Default::default(),
-            type_: Valid(gltf_json::accessor::Type::Vec2),
-            min: Some(gltf_json::Value::from(Vec::from(min))),
-            max: Some(gltf_json::Value::from(Vec::from(max))),
-            name: None,
-            normalized: false,
-            sparse: None,
-        });
-        attributes.insert(
-            Valid(gltf_json::mesh::Semantic::TexCoords(0)),
-            uv_accessor_idx,
-        );
+}
 
-        let image_idx = gltf_json::Index::new(images.len() as u32);
-        images.push(gltf_json::image::Image {
-            name: None,
-            buffer_view: Some(png_buffer_view_idx),
-            mime_type: Some(MimeType("image/png".to_string())),
-            uri: None,
-            extensions: None,
-            extras: Default::default(),
-        });
-        let sampler_idx = gltf_json::Index::new(samplers.len() as u32);
-        samplers.push(gltf_json::texture::Sampler {
-            mag_filter: Some(Valid(gltf::texture::MagFilter::Linear)),
-            min_filter: Some(Valid(gltf::texture::MinFilter::Linear)),
-            wrap_s: Valid(gltf::texture::WrappingMode::Repeat),
-            wrap_t: Valid(gltf::texture::WrappingMode::Repeat),
-            name: None,
-            extensions: None,
-            extras: Default::default(),
-        });
-
-        let info = gltf_json::extensions::texture::Info {
-            texture_transform: Some(TextureTransform {
-                offset: TextureTransformOffset([0.0, 0.0]),
-                rotation: TextureTransformRotation(0.0),
-                scale: TextureTransformScale([0.0, 0.0]),
-                tex_coord: Some(uv_accessor_idx.value() as u32),
-                extras: Default::default(),
-            }),
-        };
-        let texture_idx = gltf_json::Index::new(textures.len() as u32);
-        textures.push(gltf_json::texture::Texture {
-            name: None,
-            sampler: Some(sampler_idx),
-            source: image_idx,
-            extensions: Some(gltf_json::extensions::texture::Texture {}),
-            extras: Some(RawValue::from_string(serde_json::to_string(&info)?)?),
-        });
-
-        let pbr_metallic_roughness = gltf_json::material::PbrMetallicRoughness {
-            base_color_factor: PbrBaseColorFactor([1.0, 1.0, 1.0, 1.0]),
-            metallic_factor: gltf_json::material::StrengthFactor(0.0),
-            roughness_factor: gltf_json::material::StrengthFactor(0.9),
-            base_color_texture: Some(gltf_json::texture::Info {
-                index: texture_idx,
-                tex_coord: 0,
-                extensions: None,
-                extras: Default::default(),
-            }),
-            ..Default::default()
-        };
-        let material_idx = Some(gltf_json::Index::new(materials.len() as u32));
-        materials.push(gltf_json::material::Material {
-            pbr_metallic_roughness,
-            ..Default::default()
-        });
-        material_idx
+fn run_validate(path: &str) -> Result<()> {
+    let issues = validate::validate(&Document::load(path)?);
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    let errors = issues
+        .iter()
+        .filter(|i| i.severity == validate::Severity::Error)
+        .count();
+    println!("{} errors, {} warnings", errors, issues.len() - errors);
+    if errors > 0 {
+        Err(anyhow!("{} is not a valid VRM 1.0 file", path))
     } else {
-        None
-    };
-    let primitive = gltf_json::mesh::Primitive {
-        attributes,
-        extensions: Default::default(),
-        extras: Default::default(),
-        indices: Some(indices_accessor_idx),
-        material,
-        mode: Valid(gltf_json::mesh::Mode::Triangles),
-        targets: None,
-    };
-    let mesh = gltf_json::Mesh {
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        primitives: vec![primitive],
-        weights: None,
-    };
-    let node = gltf_json::Node {
-        camera: None,
-        children: None,
-        extensions: Default::default(),
-        extras: Default::default(),
-        matrix: None,
-        mesh: Some(gltf_json::Index::new(0)),
-        name: None,
-        rotation: None,
-        scale: None,
-        translation,
-        skin: None,
-        weights: None,
-    };
-    let bin_size = bin.len() as u32;
-    let buffer = gltf_json::Buffer {
-        byte_length: bin_size,
-        extensions: Default::default(),
-        extras: Default::default(),
-        name: None,
-        uri: None,
-    };
-    let root = gltf_json::Root {
-        accessors,
-        buffers: vec![buffer],
-        buffer_views,
-        meshes: vec![mesh],
-        nodes: vec![node],
-        scenes: vec![gltf_json::Scene {
-            extensions: Default::default(),
-            extras: Default::default(),
-            name: None,
-            nodes: vec![gltf_json::Index::new(0)],
-        }],
-        images,
-        textures,
-        materials,
-        samplers,
-        extensions_used: vec!["KHR_texture_transform".to_string()],
-        asset: Asset {
-            generator: Some("hikalium/vacation".to_string()),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
+        Ok(())
+    }
+}
+
+fn run_license_report(parts_dir: &str, output: Option<String>) -> Result<()> {
+    let parts_dir = Path::new(parts_dir);
+    let (meta, sources) = provenance::collect(parts_dir)?;
+    for s in &sources {
+        match &s.provenance {
+            Some(p) if s.is_default => {
+                println!("{}: {}, {} (default)", s.file, p.author, p.license)
+            }
+            Some(p) => println!("{}: {}, {}", s.file, p.author, p.license),
+            None => println!("{}: unknown", s.file),
+        }
+    }
+    let findings = provenance::check(&meta, &sources);
+    for f in &findings {
+        println!("{}", f);
+    }
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == validate::Severity::Error)
+        .count();
+    println!("{} errors, {} warnings", errors, findings.len() - errors);
+    let output =
+        output.unwrap_or_else(|| parts_dir.join("CREDITS.md").to_string_lossy().into_owned());
+    fs::write(&output, provenance::credits(&meta, &sources))?;
+    eprintln!("Written to {}", output);
+    if errors > 0 {
+        Err(anyhow!(
+            "The licenses of the parts don't allow what the avatar allows"
+        ))
+    } else {
+        Ok(())
+    }
+}
 
-    {
-        let json = gltf_json::serialize::to_string_pretty(&root).expect("failed to serialize");
-        let path = Path::new(path);
-        let path = path.with_extension("json");
-        fs::write(path, json.clone().into_bytes())?;
+fn save(doc: &Document, path: &str) -> Result<()> {
+    doc.save(path)?;
+    eprintln!("Written to {}", path);
+    Ok(())
+}
+
+fn run_output(path: &str) -> Result<()> {
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir)?;
     }
-    let json_string = gltf_json::serialize::to_string(&root).expect("Serialization error");
-    let mut json_offset = json_string.len() as u32;
-    align_to_multiple_of_four(&mut json_offset);
-    let glb = gltf::binary::Glb {
-        header: gltf::binary::Header {
-            magic: *b"glTF",
-            version: 2,
-            length: json_offset + bin_size,
-        },
-        bin: Some(Cow::Owned(bin)),
-        json: Cow::Owned(json_string.into_bytes()),
-    };
-    let writer = std::fs::File::create(path).expect("I/O error");
-    glb.to_writer(writer).expect("glTF binary output error");
+    let (model, skinning) = Model::rigged_figure();
+    println!(
+        "Model has {} vertices, {} triangles, {} joints",
+        model.vertices.len(),
+        model.indices.len(),
+        skinning.skeleton.joints.len()
+    );
+    write_glb(
+        &model.vertices,
+        &model.indices,
+        &model.normals,
+        None,
+        None,
+        None,
+        Some(&skinning),
+        &[],
+        None,
+        path,
+    )?;
     eprintln!("Written to {}", path);
     Ok(())
 }
+
 fn main() -> Result<()> {
     let args: Args = argh::from_env();
-    if let Some(path) = args.input {
+    if let Some(command) = args.command {
+        match command {
+            Command::Compile(args) => {
+                let output = args.output.unwrap_or_else(|| {
+                    Path::new(&args.source)
+                        .with_extension("vrm")
+                        .to_string_lossy()
+                        .into_owned()
+                });
+                save(&compile::compile_file(Path::new(&args.source))?, &output)
+            }
+            Command::Constraint(args) => run_constraint(args.command),
+            Command::Expression(args) => run_expression(args.command),
+            Command::Humanoid(args) => run_humanoid(args.command),
+            Command::LicenseReport(args) => run_license_report(&args.parts_dir, args.output),
+            Command::Material(args) => run_material(args.command),
+            Command::Meta(args) => run_meta(args.command),
+            Command::Migrate(args) => run_migrate(&args.input, &args.output),
+            Command::Pack(args) => {
+                let (doc, used) = pack::pack(Path::new(&args.parts_dir))?;
+                for path in used {
+                    println!("Packed {}", path.to_string_lossy());
+                }
+                save(&doc, &args.output)
+            }
+            Command::Spring(args) => run_spring(args.command),
+            Command::Validate(args) => run_validate(&args.input),
+        }
+    } else if let Some(path) = args.input {
         run_input(&path)
+    } else if let Some(path) = args.output {
+        run_output(&path)
     } else {
         Err(anyhow!("Run vacation --help for more information."))
     }
@@ -0,0 +1,172 @@
+//! Small vector and quaternion helpers on arrays, in the layout glTF uses:
+//! `[x, y, z]` for vectors and `[x, y, z, w]` for quaternions.
+
+pub type Vec3 = [f32; 3];
+pub type Quat = [f32; 4];
+
+pub const IDENTITY_QUAT: Quat = [0.0, 0.0, 0.0, 1.0];
+
+pub fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: Vec3, s: f32) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+/// Component-wise product
+pub fn mul(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] * b[0], a[1] * b[1], a[2] * b[2]]
+}
+
+pub fn dot(a: Vec3, b: Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: Vec3) -> f32 {
+    dot(a, a).sqrt()
+}
+
+/// `a` scaled to length 1, or `a` itself if it is (almost) zero
+pub fn normalize(a: Vec3) -> Vec3 {
+    let l = length(a);
+    if l < 1e-12 {
+        a
+    } else {
+        scale(a, 1.0 / l)
+    }
+}
+
+/// The rotation by `b` followed by the rotation by `a`
+pub fn quat_mul(a: Quat, b: Quat) -> Quat {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+/// Inverse of a unit quaternion
+pub fn quat_inverse(q: Quat) -> Quat {
+    [-q[0], -q[1], -q[2], q[3]]
+}
+
+pub fn quat_normalize(q: Quat) -> Quat {
+    let l = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+    if l < 1e-12 {
+        IDENTITY_QUAT
+    } else {
+        [q[0] / l, q[1] / l, q[2] / l, q[3] / l]
+    }
+}
+
+pub fn quat_rotate(q: Quat, v: Vec3) -> Vec3 {
+    let u = [q[0], q[1], q[2]];
+    let t = scale(cross(u, v), 2.0);
+    add(add(v, scale(t, q[3])), cross(u, t))
+}
+
+pub fn quat_from_axis_angle(axis: Vec3, angle: f32) -> Quat {
+    let axis = normalize(axis);
+    let (s, c) = (angle / 2.0).sin_cos();
+    [axis[0] * s, axis[1] * s, axis[2] * s, c]
+}
+
+/// The shortest rotation that turns the direction `from` into the direction `to`
+pub fn quat_from_to(from: Vec3, to: Vec3) -> Quat {
+    let (from, to) = (normalize(from), normalize(to));
+    let d = dot(from, to);
+    if d < -1.0 + 1e-6 {
+        // Opposite directions: turn half around any axis perpendicular to `from`
+        let mut axis = cross([1.0, 0.0, 0.0], from);
+        if length(axis) < 1e-6 {
+            axis = cross([0.0, 1.0, 0.0], from);
+        }
+        return quat_from_axis_angle(axis, std::f32::consts::PI);
+    }
+    let c = cross(from, to);
+    quat_normalize([c[0], c[1], c[2], 1.0 + d])
+}
+
+/// Spherical linear interpolation from `a` (t = 0) to `b` (t = 1) along the shorter arc
+pub fn quat_slerp(a: Quat, b: Quat, t: f32) -> Quat {
+    let mut d = a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3];
+    let mut b = b;
+    if d < 0.0 {
+        b = [-b[0], -b[1], -b[2], -b[3]];
+        d = -d;
+    }
+    if d > 0.9995 {
+        return quat_normalize([0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t));
+    }
+    let theta = d.acos();
+    let s = theta.sin();
+    let wa = ((1.0 - t) * theta).sin() / s;
+    let wb = (t * theta).sin() / s;
+    [0, 1, 2, 3].map(|i| a[i] * wa + b[i] * wb)
+}
+
+/// Split a column-major matrix without shear into translation, rotation and scale
+pub fn decompose_matrix(m: &[f32; 16]) -> (Vec3, Quat, Vec3) {
+    let translation = [m[12], m[13], m[14]];
+    let column = |c: usize| [m[c * 4], m[c * 4 + 1], m[c * 4 + 2]];
+    let mut s = [length(column(0)), length(column(1)), length(column(2))];
+    if dot(cross(column(0), column(1)), column(2)) < 0.0 {
+        s[0] = -s[0];
+    }
+    let r = |row: usize, col: usize| {
+        if s[col] == 0.0 {
+            0.0
+        } else {
+            m[col * 4 + row] / s[col]
+        }
+    };
+    let trace = r(0, 0) + r(1, 1) + r(2, 2);
+    let q = if trace > 0.0 {
+        let k = 0.5 / (trace + 1.0).sqrt();
+        [
+            (r(2, 1) - r(1, 2)) * k,
+            (r(0, 2) - r(2, 0)) * k,
+            (r(1, 0) - r(0, 1)) * k,
+            0.25 / k,
+        ]
+    } else if r(0, 0) > r(1, 1) && r(0, 0) > r(2, 2) {
+        let k = 2.0 * (1.0 + r(0, 0) - r(1, 1) - r(2, 2)).sqrt();
+        [
+            0.25 * k,
+            (r(0, 1) + r(1, 0)) / k,
+            (r(0, 2) + r(2, 0)) / k,
+            (r(2, 1) - r(1, 2)) / k,
+        ]
+    } else if r(1, 1) > r(2, 2) {
+        let k = 2.0 * (1.0 + r(1, 1) - r(0, 0) - r(2, 2)).sqrt();
+        [
+            (r(0, 1) + r(1, 0)) / k,
+            0.25 * k,
+            (r(1, 2) + r(2, 1)) / k,
+            (r(0, 2) - r(2, 0)) / k,
+        ]
+    } else {
+        let k = 2.0 * (1.0 + r(2, 2) - r(0, 0) - r(1, 1)).sqrt();
+        [
+            (r(0, 2) + r(2, 0)) / k,
+            (r(1, 2) + r(2, 1)) / k,
+            0.25 * k,
+            (r(1, 0) - r(0, 1)) / k,
+        ]
+    };
+    (translation, quat_normalize(q), s)
+}
@@ -0,0 +1,239 @@
+//! The meta (name, authors and license) of VRMC_vrm: reading and setting its properties by their
+//! names in the schema, with the values checked against the ones the specification allows.
+
+use crate::vrm::Meta;
+use crate::vrm::Modification;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Names of the properties of meta in the order of the schema
+pub const PROPERTIES: [&str; 19] = [
+    "name",
+    "version",
+    "authors",
+    "copyrightInformation",
+    "contactInformation",
+    "references",
+    "thirdPartyLicenses",
+    "thumbnailImage",
+    "licenseUrl",
+    "avatarPermission",
+    "allowExcessivelyViolentUsage",
+    "allowExcessivelySexualUsage",
+    "commercialUsage",
+    "allowPoliticalOrReligiousUsage",
+    "allowAntisocialOrHateUsage",
+    "creditNotation",
+    "allowRedistribution",
+    "modification",
+    "otherLicenseUrl",
+];
+
+/// Values allowed for the enum properties
+pub const AVATAR_PERMISSIONS: [&str; 3] =
+    ["onlyAuthor", "onlySeparatelyLicensedPerson", "everyone"];
+pub const COMMERCIAL_USAGES: [&str; 3] = ["personalNonProfit", "personalProfit", "corporation"];
+pub const CREDIT_NOTATIONS: [&str; 2] = ["required", "unnecessary"];
+pub const MODIFICATIONS: [&str; 3] = [
+    "prohibited",
+    "allowModification",
+    "allowModificationRedistribution",
+];
+
+fn parse_enum<T: DeserializeOwned>(name: &str, value: &str, allowed: &[&str]) -> Result<T> {
+    if !allowed.contains(&value) {
+        bail!("{} of {} is not one of {}", value, name, allowed.join(", "));
+    }
+    Ok(serde_json::from_value(Value::from(value))?)
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    value
+        .parse()
+        .with_context(|| format!("{} of {} is not true or false", value, name))
+}
+
+/// A list given as a JSON array of strings, or as values separated by commas
+fn parse_list(name: &str, value: &str) -> Result<Vec<String>> {
+    if value.trim_start().starts_with('[') {
+        return serde_json::from_str(value)
+            .with_context(|| format!("{} of {} is not an array of strings", value, name));
+    }
+    Ok(value
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect())
+}
+
+fn optional(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+impl Meta {
+    /// The value of a property given by its name in the schema, or None if it is not set
+    pub fn property(&self, name: &str) -> Result<Option<Value>> {
+        if !PROPERTIES.contains(&name) {
+            bail!(
+                "{} is not a property of meta. The properties are {}",
+                name,
+                PROPERTIES.join(", ")
+            );
+        }
+        let json = serde_json::to_value(self)?;
+        Ok(json.get(name).cloned())
+    }
+    /// Set a property given by its name in the schema, e.g. `commercialUsage=corporation`.
+    /// `authors` and `references` are separated by commas (or given as a JSON array),
+    /// `thumbnailImage` is the index of an image, and an empty value removes an optional property.
+    pub fn set_property(&mut self, name: &str, value: &str) -> Result<()> {
+        let required = |value: &str| {
+            if value.is_empty() {
+                bail!("{} is required and can't be empty", name);
+            }
+            Ok(value.to_string())
+        };
+        match name {
+            "name" => self.name = required(value)?,
+            "version" => self.version = optional(value),
+            "authors" => {
+                self.authors = parse_list(name, value)?;
+                if self.authors.is_empty() {
+                    bail!("authors must have at least one author");
+                }
+            }
+            "copyrightInformation" => self.copyright_information = optional(value),
+            "contactInformation" => self.contact_information = optional(value),
+            "references" => self.references = parse_list(name, value)?,
+            "thirdPartyLicenses" => self.third_party_licenses = optional(value),
+            "thumbnailImage" => {
+                self.thumbnail_image = match value {
+                    "" => None,
+                    v => Some(
+                        v.parse()
+                            .with_context(|| format!("{} of {} is not an index", v, name))?,
+                    ),
+                }
+            }
+            "licenseUrl" => self.license_url = required(value)?,
+            "avatarPermission" => {
+                self.avatar_permission = parse_enum(name, value, &AVATAR_PERMISSIONS)?
+            }
+            "allowExcessivelyViolentUsage" => {
+                self.allow_excessively_violent_usage = parse_bool(name, value)?
+            }
+            "allowExcessivelySexualUsage" => {
+                self.allow_excessively_sexual_usage = parse_bool(name, value)?
+            }
+            "commercialUsage" => {
+                self.commercial_usage = parse_enum(name, value, &COMMERCIAL_USAGES)?
+            }
+            "allowPoliticalOrReligiousUsage" => {
+                self.allow_political_or_religious_usage = parse_bool(name, value)?
+            }
+            "allowAntisocialOrHateUsage" => {
+                self.allow_antisocial_or_hate_usage = parse_bool(name, value)?
+            }
+            "creditNotation" => self.credit_notation = parse_enum(name, value, &CREDIT_NOTATIONS)?,
+            "allowRedistribution" => self.allow_redistribution = parse_bool(name, value)?,
+            "modification" => self.modification = parse_enum(name, value, &MODIFICATIONS)?,
+            "otherLicenseUrl" => self.other_license_url = optional(value),
+            _ => bail!(
+                "{} is not a property of meta. The properties are {}",
+                name,
+                PROPERTIES.join(", ")
+            ),
+        }
+        Ok(())
+    }
+    /// Combinations of the license properties that contradict each other
+    pub fn license_conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        if self.modification == Modification::AllowModificationRedistribution
+            && !self.allow_redistribution
+        {
+            conflicts.push(
+                "modification allows redistributing modified avatars but allowRedistribution is false"
+                    .to_string(),
+            );
+        }
+        conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vrm::AvatarPermission;
+    use crate::vrm::CommercialUsage;
+    use crate::vrm::CreditNotation;
+    use serde_json::json;
+
+    #[test]
+    fn enum_values_are_checked() {
+        let mut meta = Meta::default();
+        meta.set_property("avatarPermission", "everyone").unwrap();
+        meta.set_property("commercialUsage", "corporation").unwrap();
+        meta.set_property("creditNotation", "unnecessary").unwrap();
+        meta.set_property("modification", "allowModification")
+            .unwrap();
+        assert_eq!(meta.avatar_permission, AvatarPermission::Everyone);
+        assert_eq!(meta.commercial_usage, CommercialUsage::Corporation);
+        assert_eq!(meta.credit_notation, CreditNotation::Unnecessary);
+        assert_eq!(meta.modification, Modification::AllowModification);
+
+        // Values of VRM 0.x and other cases are not accepted
+        let e = meta.set_property("commercialUsage", "Allow").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Allow of commercialUsage is not one of personalNonProfit, personalProfit, corporation"
+        );
+        assert!(meta.set_property("avatarPermission", "Everyone").is_err());
+        assert!(meta.set_property("modification", "").is_err());
+        assert_eq!(meta.commercial_usage, CommercialUsage::Corporation);
+    }
+
+    #[test]
+    fn other_properties_are_checked() {
+        let mut meta = Meta::default();
+        meta.set_property("authors", "Alice, Bob").unwrap();
+        assert_eq!(meta.authors, vec!["Alice", "Bob"]);
+        meta.set_property("references", r#"["a, b", "c"]"#).unwrap();
+        assert_eq!(meta.references, vec!["a, b", "c"]);
+        assert!(meta.set_property("authors", " , ").is_err());
+        assert!(meta.set_property("name", "").is_err());
+        assert!(meta.set_property("allowRedistribution", "yes").is_err());
+        assert!(meta.set_property("thumbnailImage", "first").is_err());
+        meta.set_property("thumbnailImage", "2").unwrap();
+        assert_eq!(meta.property("thumbnailImage").unwrap(), Some(json!(2)));
+        meta.set_property("thumbnailImage", "").unwrap();
+        assert_eq!(meta.property("thumbnailImage").unwrap(), None);
+        assert!(meta.set_property("license", "CC0").is_err());
+        assert!(meta.property("license").is_err());
+    }
+
+    #[test]
+    fn all_properties_can_be_set_and_read() {
+        let mut meta = Meta::default();
+        for name in PROPERTIES {
+            let value = match name {
+                "authors" | "references" => "someone",
+                "thumbnailImage" => "0",
+                "avatarPermission" => "everyone",
+                "commercialUsage" => "personalProfit",
+                "creditNotation" => "unnecessary",
+                "modification" => "allowModificationRedistribution",
+                n if n.starts_with("allow") => "true",
+                _ => "text",
+            };
+            meta.set_property(name, value).unwrap();
+            assert!(meta.property(name).unwrap().is_some(), "{}", name);
+        }
+        assert!(meta.license_conflicts().is_empty());
+        meta.set_property("allowRedistribution", "false").unwrap();
+        assert_eq!(meta.license_conflicts().len(), 1);
+    }
+}
@@ -0,0 +1,944 @@
+use crate::accessor;
+use crate::extensions;
+use crate::mtoon;
+use crate::mtoon::MToon;
+use crate::mtoon::OutlineWidthMode;
+use crate::mtoon::TextureInfo;
+use crate::spring_bone;
+use crate::vrm;
+use crate::vrm0;
+use crate::Document;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::animation::Property;
+use gltf_json::mesh::Semantic;
+use gltf_json::validation::Checked;
+use gltf_json::validation::Checked::Valid;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+/// Blend shape presets of VRM 0.x and the expression presets of VRM 1.0 they became
+const EXPRESSION_PRESET_MAP: [(&str, &str); 17] = [
+    ("joy", "happy"),
+    ("angry", "angry"),
+    ("sorrow", "sad"),
+    ("fun", "relaxed"),
+    ("a", "aa"),
+    ("i", "ih"),
+    ("u", "ou"),
+    ("e", "ee"),
+    ("o", "oh"),
+    ("blink", "blink"),
+    ("blink_l", "blinkLeft"),
+    ("blink_r", "blinkRight"),
+    ("lookup", "lookUp"),
+    ("lookdown", "lookDown"),
+    ("lookleft", "lookLeft"),
+    ("lookright", "lookRight"),
+    ("neutral", "neutral"),
+];
+
+/// Humanoid bones that were renamed in VRM 1.0
+const HUMAN_BONE_RENAME_MAP: [(&str, &str); 4] = [
+    ("leftThumbProximal", "leftThumbMetacarpal"),
+    ("leftThumbIntermediate", "leftThumbProximal"),
+    ("rightThumbProximal", "rightThumbMetacarpal"),
+    ("rightThumbIntermediate", "rightThumbProximal"),
+];
+
+/// VRM 0.x faces -Z while VRM 1.0 faces +Z, so the whole model is turned by 180 degrees around Y
+fn turn_vec3(v: &mut [f32]) {
+    v[0] = -v[0];
+    v[2] = -v[2];
+}
+
+/// Rotate a column-major 4x4 matrix M into R * M * R^-1 where R is the turn around Y
+fn turn_mat4(m: &mut [f32]) {
+    const R: [f32; 4] = [-1.0, 1.0, -1.0, 1.0];
+    for col in 0..4 {
+        for row in 0..4 {
+            m[col * 4 + row] *= R[row] * R[col];
+        }
+    }
+}
+
+/// Convert an offset or direction of the VRM 0.x spring bone / first person data.
+/// VRM 0.x stores them with Z reversed relative to glTF, then the model is turned around.
+fn migrate_vec3(v: vrm0::Vec3) -> [f32; 3] {
+    [-v.x, v.y, v.z]
+}
+
+/// VRM 0.x stores colors of Unity materials in gamma space while glTF uses linear
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+fn color_to_linear(c: &[f32]) -> [f32; 3] {
+    let c = |i: usize| srgb_to_linear(c.get(i).copied().unwrap_or(1.0));
+    [c(0), c(1), c(2)]
+}
+
+/// How the data of an accessor has to be changed to turn the model around
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AccessorKind {
+    Vec3,
+    Tangent,
+    Quaternion,
+    Mat4,
+}
+
+/// Turn the whole model around the Y axis: node transforms, vertex attributes, morph targets,
+/// inverse bind matrices and animations.
+fn turn_around(json: &mut Value, root: &gltf_json::Root, buffers: &mut [Vec<u8>]) -> Result<()> {
+    for (i, node) in root.nodes.iter().enumerate() {
+        let n = &mut json["nodes"][i];
+        if let Some(mut t) = node.translation {
+            turn_vec3(&mut t);
+            n["translation"] = json!(t);
+        }
+        if let Some(r) = &node.rotation {
+            let mut r = r.0;
+            turn_vec3(&mut r);
+            n["rotation"] = json!(r);
+        }
+        if let Some(mut m) = node.matrix {
+            turn_mat4(&mut m);
+            n["matrix"] = json!(m);
+        }
+    }
+
+    let mut accessors = BTreeMap::new();
+    let mut add = |accessor: usize, kind: AccessorKind| -> Result<()> {
+        match accessors.insert(accessor, kind) {
+            Some(k) if k != kind => {
+                bail!("Accessor #{} is used as {:?} and {:?}", accessor, k, kind)
+            }
+            _ => Ok(()),
+        }
+    };
+    let kind_of = |s: &Checked<Semantic>| match s {
+        Valid(Semantic::Positions) | Valid(Semantic::Normals) => Some(AccessorKind::Vec3),
+        Valid(Semantic::Tangents) => Some(AccessorKind::Tangent),
+        _ => None,
+    };
+    for mesh in &root.meshes {
+        for p in &mesh.primitives {
+            for (s, a) in &p.attributes {
+                if let Some(kind) = kind_of(s) {
+                    add(a.value(), kind)?;
+                }
+            }
+            for t in p.targets.iter().flatten() {
+                for a in [&t.positions, &t.normals, &t.tangents]
+                    .into_iter()
+                    .flatten()
+                {
+                    // Tangent deltas of morph targets are VEC3
+                    add(a.value(), AccessorKind::Vec3)?;
+                }
+            }
+        }
+    }
+    for skin in &root.skins {
+        if let Some(a) = skin.inverse_bind_matrices {
+            add(a.value(), AccessorKind::Mat4)?;
+        }
+    }
+    for animation in &root.animations {
+        for c in &animation.channels {
+            let output = animation.samplers[c.sampler.value()].output.value();
+            match c.target.path {
+                Valid(Property::Translation) => add(output, AccessorKind::Vec3)?,
+                Valid(Property::Rotation) => add(output, AccessorKind::Quaternion)?,
+                _ => {}
+            }
+        }
+    }
+    for (accessor, kind) in accessors {
+        match kind {
+            AccessorKind::Vec3 | AccessorKind::Tangent | AccessorKind::Quaternion => {
+                accessor::modify_f32(root, buffers, accessor, &turn_vec3)?
+            }
+            AccessorKind::Mat4 => accessor::modify_f32(root, buffers, accessor, &turn_mat4)?,
+        }
+        if kind == AccessorKind::Vec3 {
+            // Bounds of X and Z are swapped and negated
+            let a = &mut json["accessors"][accessor];
+            if let (Some(min), Some(max)) =
+                (a["min"].as_array().cloned(), a["max"].as_array().cloned())
+            {
+                if min.len() == 3 && max.len() == 3 {
+                    let neg = |v: &Value| json!(-v.as_f64().unwrap_or(0.0));
+                    a["min"] = json!([neg(&max[0]), min[1], neg(&max[2])]);
+                    a["max"] = json!([neg(&min[0]), max[1], neg(&min[2])]);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn nodes_of_mesh(root: &gltf_json::Root, mesh: i32) -> Vec<u32> {
+    root.nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, n)| n.mesh.map(|m| m.value() as i32) == Some(mesh))
+        .map(|(i, _)| i as u32)
+        .collect()
+}
+
+fn migrate_meta(m: &vrm0::Meta, root: &gltf_json::Root) -> vrm::Meta {
+    let allow = |v: &Option<String>| v.as_deref() == Some("Allow");
+    let mut meta = vrm::Meta {
+        name: m.title.clone().unwrap_or_default(),
+        version: m.version.clone(),
+        authors: vec![m.author.clone().unwrap_or_else(|| "unknown".to_string())],
+        contact_information: m.contact_information.clone(),
+        references: m.reference.iter().cloned().collect(),
+        thumbnail_image: m
+            .texture
+            .filter(|t| *t >= 0)
+            .and_then(|t| root.textures.get(t as usize))
+            .map(|t| t.source.value() as u32),
+        license_url: "https://vrm.dev/licenses/1.0/".to_string(),
+        avatar_permission: match m.allowed_user_name.as_deref() {
+            Some("Everyone") => vrm::AvatarPermission::Everyone,
+            Some("ExplicitlyLicensedPerson") => vrm::AvatarPermission::OnlySeparatelyLicensedPerson,
+            _ => vrm::AvatarPermission::OnlyAuthor,
+        },
+        allow_excessively_violent_usage: allow(&m.violent_ussage_name),
+        allow_excessively_sexual_usage: allow(&m.sexual_ussage_name),
+        commercial_usage: if allow(&m.commercial_ussage_name) {
+            vrm::CommercialUsage::Corporation
+        } else {
+            vrm::CommercialUsage::PersonalNonProfit
+        },
+        other_license_url: m
+            .other_license_url
+            .clone()
+            .or_else(|| m.other_permission_url.clone()),
+        ..Default::default()
+    };
+    // The Creative Commons licenses of 0.x are expressed with the permission flags of 1.0
+    let license = m
+        .license_name
+        .as_deref()
+        .unwrap_or("Redistribution_Prohibited");
+    let cc_url = match license {
+        "CC0" => Some("https://creativecommons.org/publicdomain/zero/1.0/"),
+        "CC_BY" => Some("https://creativecommons.org/licenses/by/4.0/"),
+        "CC_BY_NC" => Some("https://creativecommons.org/licenses/by-nc/4.0/"),
+        "CC_BY_SA" => Some("https://creativecommons.org/licenses/by-sa/4.0/"),
+        "CC_BY_NC_SA" => Some("https://creativecommons.org/licenses/by-nc-sa/4.0/"),
+        "CC_BY_ND" => Some("https://creativecommons.org/licenses/by-nd/4.0/"),
+        "CC_BY_NC_ND" => Some("https://creativecommons.org/licenses/by-nc-nd/4.0/"),
+        _ => None,
+    };
+    if let Some(url) = cc_url {
+        meta.other_license_url = Some(url.to_string());
+        meta.allow_redistribution = true;
+        meta.modification = if license.ends_with("_ND") {
+            vrm::Modification::Prohibited
+        } else {
+            vrm::Modification::AllowModificationRedistribution
+        };
+        if license == "CC0" {
+            meta.credit_notation = vrm::CreditNotation::Unnecessary;
+        }
+        if license.contains("_NC") {
+            meta.commercial_usage = vrm::CommercialUsage::PersonalNonProfit;
+        }
+    }
+    meta
+}
+
+fn migrate_humanoid(h: &vrm0::Humanoid, warnings: &mut Vec<String>) -> vrm::Humanoid {
+    let mut human_bones = BTreeMap::new();
+    for b in &h.human_bones {
+        if b.node < 0 {
+            continue;
+        }
+        let name = HUMAN_BONE_RENAME_MAP
+            .iter()
+            .find(|(old, _)| *old == b.bone)
+            .map(|(_, new)| *new)
+            .unwrap_or(&b.bone);
+        if !vrm::HUMAN_BONES.contains(&name) {
+            warnings.push(format!("unknown humanoid bone {:?} is dropped", b.bone));
+            continue;
+        }
+        human_bones.insert(
+            name.to_string(),
+            vrm::HumanBone {
+                node: b.node as u32,
+                extensions: None,
+                extras: None,
+            },
+        );
+    }
+    vrm::Humanoid {
+        human_bones,
+        extensions: None,
+        extras: None,
+    }
+}
+
+fn migrate_first_person(
+    fp: &vrm0::FirstPerson,
+    root: &gltf_json::Root,
+    humanoid: &vrm::Humanoid,
+    warnings: &mut Vec<String>,
+) -> (vrm::FirstPerson, vrm::LookAt) {
+    let mut mesh_annotations = Vec::new();
+    for a in &fp.mesh_annotations {
+        let type_ = match a.first_person_flag.as_str() {
+            "Both" => vrm::FirstPersonType::Both,
+            "ThirdPersonOnly" => vrm::FirstPersonType::ThirdPersonOnly,
+            "FirstPersonOnly" => vrm::FirstPersonType::FirstPersonOnly,
+            _ => vrm::FirstPersonType::Auto,
+        };
+        for node in nodes_of_mesh(root, a.mesh) {
+            mesh_annotations.push(vrm::MeshAnnotation {
+                node,
+                type_,
+                extensions: None,
+                extras: None,
+            });
+        }
+    }
+    let head = humanoid.human_bones.get("head").map(|b| b.node as i32);
+    if fp.first_person_bone.is_some() && fp.first_person_bone != head {
+        warnings
+            .push("firstPersonBone is not the head, the lookAt offset is kept as is".to_string());
+    }
+    let range_map = |m: &Option<vrm0::DegreeMap>| {
+        m.as_ref().map(|m| vrm::RangeMap {
+            input_max_value: Some(m.x_range),
+            output_scale: Some(m.y_range),
+            extensions: None,
+            extras: None,
+        })
+    };
+    let look_at = vrm::LookAt {
+        offset_from_head_bone: fp.first_person_bone_offset.map(migrate_vec3),
+        type_: match fp.look_at_type_name.as_deref() {
+            Some("BlendShape") => vrm::LookAtType::Expression,
+            _ => vrm::LookAtType::Bone,
+        },
+        range_map_horizontal_inner: range_map(&fp.look_at_horizontal_inner),
+        range_map_horizontal_outer: range_map(&fp.look_at_horizontal_outer),
+        range_map_vertical_down: range_map(&fp.look_at_vertical_down),
+        range_map_vertical_up: range_map(&fp.look_at_vertical_up),
+        extensions: None,
+        extras: None,
+    };
+    let first_person = vrm::FirstPerson {
+        mesh_annotations,
+        extensions: None,
+        extras: None,
+    };
+    (first_person, look_at)
+}
+
+fn migrate_expression(
+    g: &vrm0::BlendShapeGroup,
+    root: &gltf_json::Root,
+    warnings: &mut Vec<String>,
+) -> vrm::Expression {
+    let mut e = vrm::Expression {
+        is_binary: g.is_binary,
+        ..Default::default()
+    };
+    for b in &g.binds {
+        if b.index < 0 {
+            continue;
+        }
+        for node in nodes_of_mesh(root, b.mesh) {
+            e.morph_target_binds.push(vrm::MorphTargetBind {
+                node,
+                index: b.index as u32,
+                weight: (b.weight / 100.0).clamp(0.0, 1.0),
+                extensions: None,
+                extras: None,
+            });
+        }
+    }
+    for v in &g.material_values {
+        let material = match root
+            .materials
+            .iter()
+            .position(|m| m.name.as_deref() == Some(v.material_name.as_str()))
+        {
+            Some(m) => m as u32,
+            None => {
+                warnings.push(format!("material {:?} is not found", v.material_name));
+                continue;
+            }
+        };
+        let t = |i: usize, default: f32| v.target_value.get(i).copied().unwrap_or(default);
+        let type_ = match v.property_name.as_str() {
+            "_Color" => vrm::MaterialColorType::Color,
+            "_EmissionColor" => vrm::MaterialColorType::EmissionColor,
+            "_ShadeColor" => vrm::MaterialColorType::ShadeColor,
+            "_RimColor" => vrm::MaterialColorType::RimColor,
+            "_OutlineColor" => vrm::MaterialColorType::OutlineColor,
+            "_MainTex_ST" | "_MainTex_ST_S" | "_MainTex_ST_T" => {
+                // Unity's scale/offset has its origin at the bottom left, glTF at the top left
+                let (mut scale, mut offset) = ([t(0, 1.0), t(1, 1.0)], [t(2, 0.0), t(3, 0.0)]);
+                if v.property_name == "_MainTex_ST_S" {
+                    (scale[1], offset[1]) = (1.0, 0.0);
+                }
+                if v.property_name == "_MainTex_ST_T" {
+                    (scale[0], offset[0]) = (1.0, 0.0);
+                }
+                offset[1] = 1.0 - offset[1] - scale[1];
+                e.texture_transform_binds.push(vrm::TextureTransformBind {
+                    material,
+                    scale: Some(scale),
+                    offset: Some(offset),
+                    extensions: None,
+                    extras: None,
+                });
+                continue;
+            }
+            name => {
+                warnings.push(format!(
+                    "material value {:?} is not supported in VRM 1.0",
+                    name
+                ));
+                continue;
+            }
+        };
+        let [r, g, b] = color_to_linear(&v.target_value);
+        e.material_color_binds.push(vrm::MaterialColorBind {
+            material,
+            type_,
+            target_value: [r, g, b, t(3, 1.0)],
+            extensions: None,
+            extras: None,
+        });
+    }
+    e
+}
+
+fn migrate_expressions(
+    bsm: &vrm0::BlendShapeMaster,
+    root: &gltf_json::Root,
+    warnings: &mut Vec<String>,
+) -> vrm::Expressions {
+    let mut expressions = vrm::Expressions::default();
+    for (i, g) in bsm.blend_shape_groups.iter().enumerate() {
+        let e = migrate_expression(g, root, warnings);
+        let preset = EXPRESSION_PRESET_MAP
+            .iter()
+            .find(|(old, _)| old.eq_ignore_ascii_case(&g.preset_name))
+            .map(|(_, new)| new.to_string());
+        match preset {
+            Some(p) if !expressions.preset.contains_key(&p) => {
+                expressions.preset.insert(p, e);
+            }
+            _ => {
+                let base = if g.name.is_empty() {
+                    format!("expression{}", i)
+                } else {
+                    g.name.clone()
+                };
+                let mut name = base.clone();
+                let mut n = 1;
+                while expressions.custom.contains_key(&name) {
+                    n += 1;
+                    name = format!("{}_{}", base, n);
+                }
+                expressions.custom.insert(name, e);
+            }
+        }
+    }
+    expressions
+}
+
+/// Split the spring bone tree under `node` into chains. The first child continues the chain
+/// and every other child starts a new one from `node`, since a joint only turns toward the
+/// next joint of its chain.
+fn collect_chains(
+    root: &gltf_json::Root,
+    node: u32,
+    chain: &mut Vec<u32>,
+    chains: &mut Vec<Vec<u32>>,
+) {
+    chain.push(node);
+    let children = root.nodes[node as usize]
+        .children
+        .clone()
+        .unwrap_or_default();
+    if children.is_empty() {
+        chains.push(std::mem::take(chain));
+        return;
+    }
+    for (i, c) in children.iter().enumerate() {
+        if i == 0 {
+            collect_chains(root, c.value() as u32, chain, chains);
+        } else {
+            collect_chains(root, c.value() as u32, &mut vec![node], chains);
+        }
+    }
+}
+
+fn migrate_spring_bone(
+    sa: &vrm0::SecondaryAnimation,
+    root: &gltf_json::Root,
+) -> Option<spring_bone::SpringBone> {
+    if sa.bone_groups.is_empty() {
+        return None;
+    }
+    let mut spring_bone = spring_bone::SpringBone::default();
+    let mut group_map = HashMap::new();
+    for (i, g) in sa.collider_groups.iter().enumerate() {
+        if g.node < 0 {
+            continue;
+        }
+        let mut indices = Vec::new();
+        for c in &g.colliders {
+            indices.push(spring_bone.colliders.len() as u32);
+            spring_bone.colliders.push(spring_bone::Collider::new(
+                g.node as u32,
+                spring_bone::Shape::Sphere {
+                    offset: migrate_vec3(c.offset),
+                    radius: c.radius,
+                },
+            ));
+        }
+        group_map.insert(i as i32, spring_bone.collider_groups.len() as u32);
+        spring_bone
+            .collider_groups
+            .push(spring_bone::ColliderGroup {
+                colliders: indices,
+                ..Default::default()
+            });
+    }
+    for g in &sa.bone_groups {
+        let groups: Vec<u32> = g
+            .collider_groups
+            .iter()
+            .filter_map(|i| group_map.get(i).copied())
+            .collect();
+        for bone in g.bones.iter().filter(|b| **b >= 0) {
+            let mut chains = Vec::new();
+            collect_chains(root, *bone as u32, &mut Vec::new(), &mut chains);
+            for chain in chains {
+                let joints = chain
+                    .iter()
+                    .map(|node| spring_bone::SpringJoint {
+                        node: *node,
+                        hit_radius: g.hit_radius,
+                        stiffness: g.stiffiness,
+                        gravity_power: g.gravity_power,
+                        gravity_dir: migrate_vec3(g.gravity_dir),
+                        drag_force: g.drag_force,
+                        extensions: None,
+                        extras: None,
+                    })
+                    .collect();
+                spring_bone.springs.push(spring_bone::Spring {
+                    name: g.comment.clone(),
+                    joints,
+                    collider_groups: groups.clone(),
+                    center: (g.center >= 0).then_some(g.center as u32),
+                    extensions: None,
+                    extras: None,
+                });
+            }
+        }
+    }
+    Some(spring_bone)
+}
+
+/// Convert the properties of the VRM/MToon shader into VRMC_materials_mtoon,
+/// and update the glTF material with the alpha mode and culling of the shader.
+fn migrate_mtoon(p: &vrm0::MaterialProperties, material: &mut Value) -> MToon {
+    let f = |name: &str, default: f32| p.float_properties.get(name).copied().unwrap_or(default);
+    let color = |name: &str| p.vector_properties.get(name).map(|c| color_to_linear(c));
+    let texture_index = |name: &str| {
+        p.texture_properties
+            .get(name)
+            .filter(|t| **t >= 0)
+            .map(|t| *t as u32)
+    };
+    let texture = |name: &str| texture_index(name).map(TextureInfo::new);
+    let blend_mode = f("_BlendMode", 0.0) as i32;
+    let mut mtoon = MToon {
+        transparent_with_z_write: blend_mode == 3,
+        render_queue_offset_number: match blend_mode {
+            2 => (p.render_queue - 3000).clamp(-9, 0),
+            3 => (p.render_queue - 2501).clamp(0, 9),
+            _ => 0,
+        },
+        ..Default::default()
+    };
+    // The lit range of 0.x (shadeShift, Lerp(1, shadeShift, shadeToony)) is remapped to 1.0
+    let shade_shift = f("_ShadeShift", 0.0);
+    let shade_toony = f("_ShadeToony", 0.9);
+    let range_min = shade_shift;
+    let range_max = 1.0 + (shade_shift - 1.0) * shade_toony;
+    mtoon.shading_toony_factor = ((2.0 - (range_max - range_min)) * 0.5).clamp(0.0, 1.0);
+    mtoon.shading_shift_factor = (-(range_max + range_min) * 0.5).clamp(-1.0, 1.0);
+    mtoon.gi_equalization_factor = 1.0 - f("_IndirectLightIntensity", 0.1);
+    if let Some(c) = color("_ShadeColor") {
+        mtoon.shade_color_factor = c;
+    }
+    mtoon.shade_multiply_texture = texture("_ShadeTexture");
+    mtoon.matcap_texture = texture("_SphereAdd");
+    if let Some(c) = color("_RimColor") {
+        mtoon.parametric_rim_color_factor = c;
+    }
+    mtoon.rim_multiply_texture = texture("_RimTexture");
+    mtoon.rim_lighting_mix_factor = f("_RimLightingMix", 0.0);
+    mtoon.parametric_rim_fresnel_power_factor = f("_RimFresnelPower", 1.0);
+    mtoon.parametric_rim_lift_factor = f("_RimLift", 0.0);
+    // 0.x has the outline width in centimeters (world) or in percent of the screen height (screen)
+    let width = f("_OutlineWidth", 0.0) * 0.01;
+    (mtoon.outline_width_mode, mtoon.outline_width_factor) =
+        match f("_OutlineWidthMode", 0.0) as i32 {
+            1 => (OutlineWidthMode::WorldCoordinates, width),
+            2 => (OutlineWidthMode::ScreenCoordinates, width * 0.5),
+            _ => (OutlineWidthMode::None, 0.0),
+        };
+    mtoon.outline_width_multiply_texture = texture("_OutlineWidthTexture");
+    if let Some(c) = color("_OutlineColor") {
+        mtoon.outline_color_factor = c;
+    }
+    mtoon.outline_lighting_mix_factor = if f("_OutlineColorMode", 0.0) as i32 == 1 {
+        f("_OutlineLightingMix", 1.0)
+    } else {
+        0.0
+    };
+    mtoon.uv_animation_mask_texture = texture("_UvAnimMaskTexture");
+    mtoon.uv_animation_scroll_x_speed_factor = f("_UvAnimScrollX", 0.0);
+    // V is flipped between Unity and glTF
+    mtoon.uv_animation_scroll_y_speed_factor = -f("_UvAnimScrollY", 0.0);
+    mtoon.uv_animation_rotation_speed_factor = f("_UvAnimRotation", 0.0);
+
+    match blend_mode {
+        0 => {
+            material["alphaMode"] = json!("OPAQUE");
+            material.as_object_mut().map(|m| m.remove("alphaCutoff"));
+        }
+        1 => {
+            material["alphaMode"] = json!("MASK");
+            material["alphaCutoff"] = json!(f("_Cutoff", 0.5));
+        }
+        _ => {
+            material["alphaMode"] = json!("BLEND");
+            material.as_object_mut().map(|m| m.remove("alphaCutoff"));
+        }
+    }
+    material["doubleSided"] = json!(f("_CullMode", 2.0) as i32 == 0);
+    if material.get("emissiveFactor").is_none() {
+        if let Some(c) = color("_EmissionColor") {
+            material["emissiveFactor"] = json!(c);
+        }
+    }
+    if material.get("emissiveTexture").is_none() {
+        if let Some(t) = texture_index("_EmissionMap") {
+            material["emissiveTexture"] = json!({ "index": t });
+        }
+    }
+    if material.get("normalTexture").is_none() {
+        if let Some(t) = texture_index("_BumpMap") {
+            material["normalTexture"] = json!({ "index": t, "scale": f("_BumpScale", 1.0) });
+        }
+    }
+    mtoon
+}
+
+/// Attach VRMC_materials_mtoon (or KHR_materials_unlit for the unlit shaders) to the materials
+fn migrate_materials(
+    props: &[vrm0::MaterialProperties],
+    json: &mut Value,
+    root: &gltf_json::Root,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    for (i, p) in props.iter().enumerate() {
+        // materialProperties are usually in the order of the materials, but check the names
+        let index = match root.materials.get(i) {
+            Some(m) if m.name.as_deref() == Some(p.name.as_str()) => Some(i),
+            _ => root
+                .materials
+                .iter()
+                .position(|m| m.name.as_deref() == Some(p.name.as_str())),
+        };
+        let index = match index {
+            Some(index) => index,
+            None => {
+                warnings.push(format!("material {:?} is not found", p.name));
+                continue;
+            }
+        };
+        let material = &mut json["materials"][index];
+        match p.shader.as_str() {
+            "VRM/MToon" => {
+                migrate_mtoon(p, material).write_to_material(material)?;
+                extensions::mark_used(json, mtoon::EXTENSION_NAME);
+            }
+            s if s.starts_with("VRM/Unlit") => {
+                material["extensions"]["KHR_materials_unlit"] = json!({});
+                extensions::mark_used(json, "KHR_materials_unlit");
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Convert a VRM 0.x document into VRM 1.0 in place, and return warnings about what could not be
+/// converted as is
+pub fn migrate(doc: &mut Document) -> Result<Vec<String>> {
+    let vrm0 = doc.vrm0()?.context("Not a VRM 0.x file")?;
+    let root = doc.root()?;
+    let json = &mut doc.json;
+
+    turn_around(json, &root, std::slice::from_mut(&mut doc.bin))?;
+
+    let mut warnings = Vec::new();
+    let humanoid = migrate_humanoid(&vrm0.humanoid, &mut warnings);
+    let (first_person, look_at) =
+        migrate_first_person(&vrm0.first_person, &root, &humanoid, &mut warnings);
+    let vrm = vrm::Vrm {
+        spec_version: vrm::SPEC_VERSION.to_string(),
+        meta: migrate_meta(&vrm0.meta, &root),
+        humanoid,
+        first_person: Some(first_person),
+        look_at: Some(look_at),
+        expressions: Some(migrate_expressions(
+            &vrm0.blend_shape_master,
+            &root,
+            &mut warnings,
+        )),
+        extensions: None,
+        extras: None,
+    };
+    if let Some(spring_bone) = migrate_spring_bone(&vrm0.secondary_animation, &root) {
+        spring_bone.write_to_json(json)?;
+    }
+    migrate_materials(&vrm0.material_properties, json, &root, &mut warnings)?;
+
+    if let Some(e) = json["extensions"].as_object_mut() {
+        e.remove(vrm0::EXTENSION_NAME);
+    }
+    if let Some(used) = json["extensionsUsed"].as_array_mut() {
+        used.retain(|e| e != vrm0::EXTENSION_NAME);
+    }
+    vrm.write_to_json(json)?;
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glb::bounding_coords3d;
+    use crate::math::Vec3;
+    use crate::pose::Pose;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    /// The column-major matrix of an affine map
+    fn matrix(f: impl Fn(Vec3) -> Vec3) -> [f32; 16] {
+        let origin = f([0.0; 3]);
+        let mut m = [0.0; 16];
+        for (i, axis) in [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+            .into_iter()
+            .enumerate()
+        {
+            m[i * 4..i * 4 + 3].copy_from_slice(&crate::math::sub(f(axis), origin));
+        }
+        m[12..15].copy_from_slice(&origin);
+        m[15] = 1.0;
+        m
+    }
+
+    fn transform(m: &[f32], p: Vec3) -> Vec3 {
+        [0, 1, 2].map(|i| m[i] * p[0] + m[4 + i] * p[1] + m[8 + i] * p[2] + m[12 + i])
+    }
+
+    fn f32_bytes<'a>(values: impl IntoIterator<Item = &'a f32>) -> Vec<u8> {
+        values.into_iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// A VRM 0.x document with a skinned mesh, a rotation animation and a branching spring
+    fn vrm0_doc() -> Document {
+        let mut json = json!({
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["VRM"],
+            "nodes": [
+                { "name": "Hips", "translation": [1.0, 2.0, 3.0],
+                  "rotation": [0.0, FRAC_1_SQRT_2, 0.0, FRAC_1_SQRT_2], "children": [1] },
+                { "name": "Thumb", "translation": [0.0, 1.0, 0.5] },
+                { "name": "Body", "mesh": 0, "skin": 0 },
+                { "name": "Hair", "children": [4, 5] },
+                { "name": "Hair1", "translation": [0.0, -0.1, 0.0], "children": [6] },
+                { "name": "Hair2", "translation": [0.1, -0.1, 0.0] },
+                { "name": "Hair1_end", "translation": [0.0, -0.1, 0.0] },
+            ],
+            "scenes": [{ "nodes": [0, 2, 3] }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 } }] }],
+            "skins": [{ "joints": [1], "inverseBindMatrices": 1 }],
+            "animations": [{
+                "channels": [{ "sampler": 0, "target": { "node": 1, "path": "rotation" } }],
+                "samplers": [{ "input": 2, "output": 3 }],
+            }],
+            "extensions": { "VRM": {
+                "meta": {
+                    "title": "Test", "author": "Alice", "allowedUserName": "Everyone",
+                    "commercialUssageName": "Allow", "licenseName": "CC_BY_NC",
+                },
+                "humanoid": { "humanBones": [
+                    { "bone": "hips", "node": 0 },
+                    { "bone": "leftThumbProximal", "node": 1 },
+                ] },
+                "blendShapeMaster": { "blendShapeGroups": [
+                    { "name": "Joy", "presetName": "joy",
+                      "binds": [{ "mesh": 0, "index": 0, "weight": 100 }] },
+                    { "name": "Joy2", "presetName": "joy" },
+                    { "name": "Wink", "presetName": "unknown" },
+                ] },
+                "secondaryAnimation": { "boneGroups": [{ "bones": [3] }] },
+            } },
+        });
+        let positions = [[1.0, 0.0, 2.0], [-3.0, 1.0, -4.0]];
+        let doc = Document::new(json.clone(), Vec::new());
+        let world = Pose::rest(&doc.root().unwrap()).unwrap().world(1);
+        let ibm = matrix(|p| world.inverse_transform_point(p));
+        let mut bin = f32_bytes(positions.iter().flatten());
+        bin.extend(f32_bytes(&ibm));
+        bin.extend(f32_bytes(&[0.0]));
+        bin.extend(f32_bytes(&[0.1, 0.2, 0.3, 0.927_362]));
+        json["buffers"] = json!([{ "byteLength": bin.len() }]);
+        json["bufferViews"] = json!([
+            { "buffer": 0, "byteOffset": 0, "byteLength": 24 },
+            { "buffer": 0, "byteOffset": 24, "byteLength": 64 },
+            { "buffer": 0, "byteOffset": 88, "byteLength": 4 },
+            { "buffer": 0, "byteOffset": 92, "byteLength": 16 },
+        ]);
+        json["accessors"] = json!([
+            { "bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3",
+              "min": [-3.0, 0.0, -4.0], "max": [1.0, 1.0, 2.0] },
+            { "bufferView": 1, "componentType": 5126, "count": 1, "type": "MAT4" },
+            { "bufferView": 2, "componentType": 5126, "count": 1, "type": "SCALAR",
+              "min": [0.0], "max": [0.0] },
+            { "bufferView": 3, "componentType": 5126, "count": 1, "type": "VEC4" },
+        ]);
+        Document::new(json, bin)
+    }
+
+    fn migrated() -> Document {
+        let mut doc = vrm0_doc();
+        migrate(&mut doc).unwrap();
+        doc
+    }
+
+    #[test]
+    fn turned_positions_keep_valid_bounds() {
+        let doc = migrated();
+        let root = doc.root().unwrap();
+        let positions = accessor::read_f32::<3>(&root, doc.buffers(), 0).unwrap();
+        assert_eq!(positions, vec![[-1.0, 0.0, -2.0], [3.0, 1.0, 4.0]]);
+        let (min, max) = bounding_coords3d(&positions);
+        assert_eq!(doc.json["accessors"][0]["min"], json!(min));
+        assert_eq!(doc.json["accessors"][0]["max"], json!(max));
+    }
+
+    #[test]
+    fn inverse_bind_matrices_match_turned_nodes() {
+        let doc = migrated();
+        let root = doc.root().unwrap();
+        assert_eq!(root.nodes[0].translation, Some([-1.0, 2.0, -3.0]));
+        let ibm = accessor::read_f32::<16>(&root, doc.buffers(), 1).unwrap()[0];
+        let world = Pose::rest(&root).unwrap().world(1);
+        for p in [[0.0, 0.0, 0.0], [1.0, 2.0, 3.0], [-0.5, 0.0, 4.0]] {
+            let q = world.transform_point(transform(&ibm, p));
+            for i in 0..3 {
+                assert!((q[i] - p[i]).abs() < 1e-5, "{:?} is not {:?}", q, p);
+            }
+        }
+        // Rotation keys are turned like the node rotations
+        let rotation = accessor::read_f32::<4>(&root, doc.buffers(), 3).unwrap()[0];
+        assert_eq!(rotation, [-0.1, 0.2, -0.3, 0.927_362]);
+    }
+
+    #[test]
+    fn meta_and_humanoid() {
+        let vrm = migrated().vrm().unwrap().unwrap();
+        let meta = &vrm.meta;
+        assert_eq!(meta.name, "Test");
+        assert_eq!(meta.authors, vec!["Alice".to_string()]);
+        assert_eq!(meta.avatar_permission, vrm::AvatarPermission::Everyone);
+        // CC BY-NC overrides commercialUssageName
+        assert_eq!(
+            meta.commercial_usage,
+            vrm::CommercialUsage::PersonalNonProfit
+        );
+        assert_eq!(
+            meta.other_license_url.as_deref(),
+            Some("https://creativecommons.org/licenses/by-nc/4.0/")
+        );
+        assert!(meta.allow_redistribution);
+        assert_eq!(
+            meta.modification,
+            vrm::Modification::AllowModificationRedistribution
+        );
+        assert_eq!(vrm.humanoid.human_bones["leftThumbMetacarpal"].node, 1);
+    }
+
+    #[test]
+    fn expression_presets() {
+        let expressions = migrated().vrm().unwrap().unwrap().expressions.unwrap();
+        let happy = &expressions.preset["happy"];
+        assert_eq!(happy.morph_target_binds.len(), 1);
+        assert_eq!(happy.morph_target_binds[0].node, 2);
+        assert_eq!(happy.morph_target_binds[0].weight, 1.0);
+        // A second group of the same preset and an unknown preset become custom expressions
+        let custom: Vec<&String> = expressions.custom.keys().collect();
+        assert_eq!(custom, vec!["Joy2", "Wink"]);
+    }
+
+    #[test]
+    fn spring_branches_start_at_the_branching_joint() {
+        let spring_bone = migrated().spring_bone().unwrap().unwrap();
+        let chains: Vec<Vec<u32>> = spring_bone
+            .springs
+            .iter()
+            .map(|s| s.joints.iter().map(|j| j.node).collect())
+            .collect();
+        assert_eq!(chains, vec![vec![3, 4, 6], vec![3, 5]]);
+        // The UniVRM defaults of a boneGroup without settings
+        let joint = &spring_bone.springs[0].joints[0];
+        assert_eq!(joint.stiffness, 1.0);
+        assert_eq!(joint.drag_force, 0.4);
+        assert_eq!(joint.gravity_dir, [0.0, -1.0, 0.0]);
+        assert_eq!(spring_bone.springs[0].center, None);
+    }
+
+    #[test]
+    fn transparent_render_queue_offset() {
+        let mtoon = |blend_mode: f32, render_queue: i32| {
+            let p = vrm0::MaterialProperties {
+                shader: "VRM/MToon".to_string(),
+                render_queue,
+                float_properties: [("_BlendMode".to_string(), blend_mode)].into(),
+                ..Default::default()
+            };
+            migrate_mtoon(&p, &mut json!({}))
+        };
+        // Without ZWrite, the offset is in -9..=0
+        assert_eq!(mtoon(2.0, 2995).render_queue_offset_number, -5);
+        assert_eq!(mtoon(2.0, 3005).render_queue_offset_number, 0);
+        assert!(!mtoon(2.0, 3005).transparent_with_z_write);
+        // With ZWrite, in 0..=9
+        assert_eq!(mtoon(3.0, 2505).render_queue_offset_number, 4);
+        assert!(mtoon(3.0, 2505).transparent_with_z_write);
+    }
+}
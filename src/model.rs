@@ -0,0 +1,113 @@
+use crate::skin::Skeleton;
+use crate::skin::Skinning;
+use std::f32::consts::PI;
+
+/// A triangle mesh authored as Rust code, ready to be passed to `write_glb`
+#[derive(Default)]
+pub struct Model {
+    pub vertices: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+impl Model {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Append an axis-aligned box centered at `center` with the given edge lengths.
+    /// Each face has its own four vertices so that the normals stay flat.
+    pub fn add_box(&mut self, center: [f32; 3], size: [f32; 3]) -> &mut Self {
+        let h = [size[0] / 2.0, size[1] / 2.0, size[2] / 2.0];
+        // (normal, tangent u, tangent v) of each face, chosen so that u x v == normal
+        let faces: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+            ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+            ([-1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+            ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+            ([0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            ([0.0, 0.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+            ([0.0, 0.0, -1.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]),
+        ];
+        for (n, u, v) in faces {
+            let base = self.vertices.len() as u32;
+            for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+                let mut p = [0f32; 3];
+                for i in 0..3 {
+                    p[i] = center[i] + (n[i] + u[i] * su + v[i] * sv) * h[i];
+                }
+                self.vertices.push(p);
+                self.normals.push(n);
+            }
+            self.indices.push([base, base + 1, base + 2]);
+            self.indices.push([base, base + 2, base + 3]);
+        }
+        self
+    }
+    /// Append a UV sphere centered at `center`
+    pub fn add_sphere(
+        &mut self,
+        center: [f32; 3],
+        radius: f32,
+        segments: u32,
+        rings: u32,
+    ) -> &mut Self {
+        let base = self.vertices.len() as u32;
+        for r in 0..=rings {
+            let theta = PI * r as f32 / rings as f32;
+            for s in 0..=segments {
+                let phi = 2.0 * PI * s as f32 / segments as f32;
+                let n = [
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                    theta.sin() * phi.cos(),
+                ];
+                self.vertices.push([
+                    center[0] + n[0] * radius,
+                    center[1] + n[1] * radius,
+                    center[2] + n[2] * radius,
+                ]);
+                self.normals.push(n);
+            }
+        }
+        let stride = segments + 1;
+        for r in 0..rings {
+            for s in 0..segments {
+                let i0 = base + r * stride + s;
+                let i1 = i0 + stride;
+                self.indices.push([i0, i1, i0 + 1]);
+                self.indices.push([i0 + 1, i1, i1 + 1]);
+            }
+        }
+        self
+    }
+    /// A simple figure standing on the origin and facing +Z, about 1.6m tall
+    pub fn figure() -> Self {
+        Self::rigged_figure().0
+    }
+    /// The figure with each of its shapes bound to a bone of a simple skeleton.
+    /// This is the model written by `--output`.
+    pub fn rigged_figure() -> (Self, Skinning) {
+        let mut s = Skeleton::default();
+        let hips = s.add_joint("Hips", None, [0.0, 0.8, 0.0]);
+        let spine = s.add_joint("Spine", Some(hips), [0.0, 0.05, 0.0]);
+        let head = s.add_joint("Head", Some(spine), [0.0, 0.45, 0.0]);
+        let left_arm = s.add_joint("LeftUpperArm", Some(spine), [0.26, 0.45, 0.0]);
+        let right_arm = s.add_joint("RightUpperArm", Some(spine), [-0.26, 0.45, 0.0]);
+        let left_leg = s.add_joint("LeftUpperLeg", Some(hips), [0.1, 0.0, 0.0]);
+        let right_leg = s.add_joint("RightUpperLeg", Some(hips), [-0.1, 0.0, 0.0]);
+        let mut m = Self::new();
+        let mut joints = Vec::new();
+        m.add_sphere([0.0, 1.45, 0.0], 0.15, 16, 8);
+        joints.resize(m.vertices.len(), head);
+        m.add_box([0.0, 1.05, 0.0], [0.36, 0.5, 0.2]);
+        joints.resize(m.vertices.len(), spine);
+        m.add_box([-0.26, 1.05, 0.0], [0.1, 0.5, 0.1]);
+        joints.resize(m.vertices.len(), right_arm);
+        m.add_box([0.26, 1.05, 0.0], [0.1, 0.5, 0.1]);
+        joints.resize(m.vertices.len(), left_arm);
+        m.add_box([-0.1, 0.4, 0.0], [0.12, 0.8, 0.12]);
+        joints.resize(m.vertices.len(), right_leg);
+        m.add_box([0.1, 0.4, 0.0], [0.12, 0.8, 0.12]);
+        joints.resize(m.vertices.len(), left_leg);
+        (m, Skinning::rigid(&joints, s))
+    }
+}
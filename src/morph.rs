@@ -0,0 +1,134 @@
+//! Morph targets (blend shapes): per-vertex displacements blended by the weights of a mesh.
+
+use crate::accessor;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+
+/// Displacements of the vertices of a primitive. Attributes that the target doesn't move are None.
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    pub name: Option<String>,
+    pub positions: Option<Vec<[f32; 3]>>,
+    pub normals: Option<Vec<[f32; 3]>>,
+    pub tangents: Option<Vec<[f32; 3]>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TargetNames {
+    target_names: Vec<String>,
+}
+
+fn target_names_in(extras: &gltf_json::extras::Extras) -> Option<Vec<String>> {
+    let extras = extras.as_ref()?;
+    serde_json::from_str::<TargetNames>(extras.get())
+        .ok()
+        .map(|e| e.target_names)
+}
+
+/// Names of the targets of a primitive, from `extras.targetNames` of the primitive or the mesh
+pub fn target_names(mesh: &gltf_json::Mesh, primitive: usize) -> Vec<String> {
+    mesh.primitives
+        .get(primitive)
+        .and_then(|p| target_names_in(&p.extras))
+        .or_else(|| target_names_in(&mesh.extras))
+        .unwrap_or_default()
+}
+
+/// Read all the morph targets of a primitive
+pub fn read_morph_targets(
+    root: &gltf_json::Root,
+    buffers: &[Vec<u8>],
+    mesh: usize,
+    primitive: usize,
+) -> Result<Vec<MorphTarget>> {
+    let m = root
+        .meshes
+        .get(mesh)
+        .with_context(|| format!("mesh #{} does not exist", mesh))?;
+    let p = m
+        .primitives
+        .get(primitive)
+        .with_context(|| format!("primitive #{} does not exist", primitive))?;
+    let names = target_names(m, primitive);
+    let read = |a: Option<gltf_json::Index<gltf_json::Accessor>>| {
+        a.map(|a| accessor::read_f32::<3>(root, buffers, a.value()))
+            .transpose()
+    };
+    let mut targets = Vec::new();
+    for (i, t) in p.targets.iter().flatten().enumerate() {
+        targets.push(MorphTarget {
+            name: names.get(i).cloned(),
+            positions: read(t.positions).with_context(|| format!("target #{} POSITION", i))?,
+            normals: read(t.normals).with_context(|| format!("target #{} NORMAL", i))?,
+            tangents: read(t.tangents).with_context(|| format!("target #{} TANGENT", i))?,
+        });
+    }
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glb::write_glb;
+    use crate::model::Model;
+    use crate::Document;
+    use serde_json::json;
+
+    #[test]
+    fn names_of_the_primitive_come_before_those_of_the_mesh() {
+        let mesh: gltf_json::Mesh = serde_json::from_value(json!({
+            "primitives": [
+                { "attributes": {}, "extras": { "targetNames": ["blink"] } },
+                { "attributes": {} },
+            ],
+            "extras": { "targetNames": ["aa", "oh"] },
+        }))
+        .unwrap();
+        assert_eq!(target_names(&mesh, 0), vec!["blink"]);
+        assert_eq!(target_names(&mesh, 1), vec!["aa", "oh"]);
+    }
+
+    #[test]
+    fn written_targets_read_back_with_their_names() {
+        let model = Model::figure();
+        let lift = MorphTarget {
+            name: Some("lift".to_string()),
+            positions: Some(vec![[0.0, 0.5, 0.0]; model.vertices.len()]),
+            ..Default::default()
+        };
+        let bulge = MorphTarget {
+            name: Some("bulge".to_string()),
+            positions: Some(model.vertices.iter().map(|v| v.map(|x| x * 0.1)).collect()),
+            normals: Some(vec![[0.0; 3]; model.vertices.len()]),
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("vacation-morph-{}.glb", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_glb(
+            &model.vertices,
+            &model.indices,
+            &model.normals,
+            None,
+            None,
+            None,
+            None,
+            &[lift.clone(), bulge.clone()],
+            None,
+            path,
+        )
+        .unwrap();
+        let doc = Document::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let targets = read_morph_targets(&doc.root().unwrap(), doc.buffers(), 0, 0).unwrap();
+        assert_eq!(targets.len(), 2);
+        for (read, written) in targets.iter().zip([lift, bulge]) {
+            assert_eq!(read.name, written.name);
+            assert_eq!(read.positions, written.positions);
+            assert_eq!(read.normals, written.normals);
+            assert_eq!(read.tangents, None);
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! The VRMC_materials_mtoon extension: the toon shading material of VRM 1.0.
+//! Missing properties are read as their default values in the specification.
+
+use crate::extensions;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+pub const EXTENSION_NAME: &str = "VRMC_materials_mtoon";
+pub const SPEC_VERSION: &str = "1.0";
+
+/// Reference to a texture of the document
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextureInfo {
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tex_coord: Option<u32>,
+    /// Used for KHR_texture_transform
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+}
+
+impl TextureInfo {
+    pub fn new(index: u32) -> Self {
+        Self {
+            index,
+            tex_coord: None,
+            extensions: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShadingShiftTextureInfo {
+    pub index: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tex_coord: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OutlineWidthMode {
+    #[default]
+    None,
+    WorldCoordinates,
+    ScreenCoordinates,
+}
+
+/// The VRMC_materials_mtoon extension on a material.
+/// The lit color and texture are the baseColor of the glTF material.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MToon {
+    pub spec_version: String,
+    pub transparent_with_z_write: bool,
+    pub render_queue_offset_number: i32,
+    pub shade_color_factor: [f32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shade_multiply_texture: Option<TextureInfo>,
+    pub shading_shift_factor: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shading_shift_texture: Option<ShadingShiftTextureInfo>,
+    pub shading_toony_factor: f32,
+    pub gi_equalization_factor: f32,
+    pub matcap_factor: [f32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matcap_texture: Option<TextureInfo>,
+    pub parametric_rim_color_factor: [f32; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rim_multiply_texture: Option<TextureInfo>,
+    pub rim_lighting_mix_factor: f32,
+    pub parametric_rim_fresnel_power_factor: f32,
+    pub parametric_rim_lift_factor: f32,
+    pub outline_width_mode: OutlineWidthMode,
+    pub outline_width_factor: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outline_width_multiply_texture: Option<TextureInfo>,
+    pub outline_color_factor: [f32; 3],
+    pub outline_lighting_mix_factor: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uv_animation_mask_texture: Option<TextureInfo>,
+    pub uv_animation_scroll_x_speed_factor: f32,
+    pub uv_animation_scroll_y_speed_factor: f32,
+    pub uv_animation_rotation_speed_factor: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+impl Default for MToon {
+    fn default() -> Self {
+        Self {
+            spec_version: SPEC_VERSION.to_string(),
+            transparent_with_z_write: false,
+            render_queue_offset_number: 0,
+            shade_color_factor: [1.0, 1.0, 1.0],
+            shade_multiply_texture: None,
+            shading_shift_factor: 0.0,
+            shading_shift_texture: None,
+            shading_toony_factor: 0.9,
+            gi_equalization_factor: 0.9,
+            matcap_factor: [1.0, 1.0, 1.0],
+            matcap_texture: None,
+            parametric_rim_color_factor: [0.0, 0.0, 0.0],
+            rim_multiply_texture: None,
+            rim_lighting_mix_factor: 1.0,
+            parametric_rim_fresnel_power_factor: 5.0,
+            parametric_rim_lift_factor: 0.0,
+            outline_width_mode: OutlineWidthMode::None,
+            outline_width_factor: 0.0,
+            outline_width_multiply_texture: None,
+            outline_color_factor: [0.0, 0.0, 0.0],
+            outline_lighting_mix_factor: 1.0,
+            uv_animation_mask_texture: None,
+            uv_animation_scroll_x_speed_factor: 0.0,
+            uv_animation_scroll_y_speed_factor: 0.0,
+            uv_animation_rotation_speed_factor: 0.0,
+            extensions: None,
+            extras: None,
+        }
+    }
+}
+
+/// Properties that hold a texture info, given by a texture index in `MToon::set_property`
+const TEXTURE_PROPERTIES: [&str; 6] = [
+    "shadeMultiplyTexture",
+    "shadingShiftTexture",
+    "matcapTexture",
+    "rimMultiplyTexture",
+    "outlineWidthMultiplyTexture",
+    "uvAnimationMaskTexture",
+];
+
+impl MToon {
+    /// Read the VRMC_materials_mtoon extension of the JSON of a material, if any
+    pub fn from_material(material: &Value) -> Result<Option<Self>> {
+        extensions::get(material, EXTENSION_NAME)
+    }
+    /// Store this as the VRMC_materials_mtoon extension of the JSON of a material.
+    /// The extension has to be listed in extensionsUsed of the document too.
+    pub fn write_to_material(&self, material: &mut Value) -> Result<()> {
+        extensions::set(material, EXTENSION_NAME, self)
+    }
+    /// Indices of all the textures used
+    pub fn textures(&self) -> Vec<(&'static str, u32)> {
+        [
+            ("shadeMultiplyTexture", &self.shade_multiply_texture),
+            ("matcapTexture", &self.matcap_texture),
+            ("rimMultiplyTexture", &self.rim_multiply_texture),
+            (
+                "outlineWidthMultiplyTexture",
+                &self.outline_width_multiply_texture,
+            ),
+            ("uvAnimationMaskTexture", &self.uv_animation_mask_texture),
+        ]
+        .into_iter()
+        .filter_map(|(name, t)| t.as_ref().map(|t| (name, t.index)))
+        .chain(
+            self.shading_shift_texture
+                .as_ref()
+                .map(|t| ("shadingShiftTexture", t.index)),
+        )
+        .collect()
+    }
+    /// Set a property given by its name in the schema, e.g. `shadeColorFactor`.
+    /// `value` is JSON, or numbers separated by commas for colors. A texture is given by its index,
+    /// and an empty value removes an optional property.
+    pub fn set_property(&mut self, name: &str, value: &str) -> Result<()> {
+        let mut json = serde_json::to_value(&*self)?;
+        let is_known = json.get(name).is_some()
+            || TEXTURE_PROPERTIES.contains(&name)
+            || name == "extensions"
+            || name == "extras";
+        if !is_known || name == "specVersion" {
+            bail!(
+                "{} is not a property of {} that can be set",
+                name,
+                EXTENSION_NAME
+            );
+        }
+        let object = json.as_object_mut().expect("MToon should be an object");
+        if value.is_empty() {
+            object.remove(name);
+        } else {
+            let value = serde_json::from_str::<Value>(value)
+                .or_else(|_| serde_json::from_str::<Value>(&format!("[{}]", value)))
+                .or_else(|_| Ok::<_, serde_json::Error>(Value::from(value)))?;
+            let value = match value {
+                Value::Number(n) if TEXTURE_PROPERTIES.contains(&name) => {
+                    serde_json::json!({ "index": n })
+                }
+                v => v,
+            };
+            object.insert(name.to_string(), value);
+        }
+        *self = serde_json::from_value(json).with_context(|| format!("Invalid {}", name))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageFormat;
+    use crate::scene::Material;
+    use crate::scene::Node;
+    use crate::scene::SceneBuilder;
+    use crate::validate;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn properties_are_set_by_their_names() {
+        let mut mtoon = MToon::default();
+        mtoon
+            .set_property("shadeColorFactor", "0.5,0.25,1")
+            .unwrap();
+        mtoon
+            .set_property("outlineWidthMode", "worldCoordinates")
+            .unwrap();
+        mtoon.set_property("outlineWidthFactor", "0.01").unwrap();
+        mtoon.set_property("matcapTexture", "2").unwrap();
+        mtoon
+            .set_property("shadingShiftTexture", r#"{"index": 1, "scale": 0.5}"#)
+            .unwrap();
+        assert_eq!(mtoon.shade_color_factor, [0.5, 0.25, 1.0]);
+        assert_eq!(mtoon.outline_width_mode, OutlineWidthMode::WorldCoordinates);
+        assert_eq!(mtoon.outline_width_factor, 0.01);
+        assert_eq!(mtoon.matcap_texture, Some(TextureInfo::new(2)));
+        assert_eq!(
+            mtoon.shading_shift_texture.as_ref().unwrap().scale,
+            Some(0.5)
+        );
+        assert_eq!(
+            mtoon.textures(),
+            vec![("matcapTexture", 2), ("shadingShiftTexture", 1)]
+        );
+
+        mtoon.set_property("matcapTexture", "").unwrap();
+        assert_eq!(mtoon.matcap_texture, None);
+        // A required property gets its default value back
+        mtoon.set_property("shadeColorFactor", "").unwrap();
+        assert_eq!(mtoon.shade_color_factor, [1.0, 1.0, 1.0]);
+
+        assert!(mtoon.set_property("specVersion", "\"0.0\"").is_err());
+        assert!(mtoon.set_property("shadeColor", "1,1,1").is_err());
+        assert!(mtoon.set_property("outlineWidthMode", "thick").is_err());
+        assert!(mtoon.set_property("shadeColorFactor", "1,1").is_err());
+    }
+
+    #[test]
+    fn written_with_the_material() {
+        let mut scene = SceneBuilder::new();
+        let texture = scene.add_texture(Some("shade"), ImageFormat::Png, PNG);
+        let mtoon = MToon {
+            shade_color_factor: [0.8, 0.6, 0.6],
+            shade_multiply_texture: Some(TextureInfo::new(texture)),
+            outline_width_mode: OutlineWidthMode::ScreenCoordinates,
+            outline_width_factor: 0.005,
+            ..Default::default()
+        };
+        let material = scene
+            .add_material(&Material {
+                base_color_texture: Some(texture),
+                mtoon: Some(mtoon.clone()),
+                ..Default::default()
+            })
+            .unwrap();
+        scene.add_material(&Material::default()).unwrap();
+        scene.add_node(&Node::default(), None).unwrap();
+        let doc = scene.build().unwrap();
+        validate::check_glb(&doc.to_vec().unwrap()).unwrap();
+
+        assert_eq!(doc.mtoon(material as usize).unwrap(), Some(mtoon));
+        assert_eq!(doc.mtoon(material as usize + 1).unwrap(), None);
+        assert_eq!(
+            doc.json["extensionsUsed"],
+            serde_json::json!([EXTENSION_NAME])
+        );
+
+        let mut doc = doc;
+        let missing = MToon {
+            matcap_texture: Some(TextureInfo::new(texture + 1)),
+            ..Default::default()
+        };
+        assert!(doc.set_mtoon(1, &missing).is_err());
+    }
+}
@@ -0,0 +1,588 @@
+use crate::accessor;
+use crate::accessor::Layout;
+use crate::glb::bounding_coords3d;
+use crate::image;
+use crate::image::ImageFormat;
+use crate::morph;
+use crate::morph::MorphTarget;
+use crate::parts::find_image_file;
+use crate::parts::part_file_name;
+use crate::skin;
+use crate::skin::JointsAndWeights;
+use crate::Document;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::mesh::Semantic;
+use gltf_json::validation::Checked::Valid;
+use serde_json::json;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Vertex data read back from a part GLB written by `parts::split`
+struct Part {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    tex_coords: Option<Vec<[f32; 2]>>,
+    /// JOINTS_0 and WEIGHTS_0 if the part is skinned
+    skinning: Option<JointsAndWeights>,
+    targets: Vec<MorphTarget>,
+    indices: Vec<u32>,
+}
+
+impl Part {
+    fn load(path: &Path) -> Result<Self> {
+        let doc = Document::load(path)?;
+        let root = doc.root()?;
+        let p = root
+            .meshes
+            .first()
+            .and_then(|m| m.primitives.first())
+            .context("No primitive in the part")?;
+        let get = |semantic: Semantic| p.attributes.get(&Valid(semantic)).map(|a| a.value());
+        let buffers = doc.buffers();
+        Ok(Self {
+            positions: accessor::read_f32(
+                &root,
+                buffers,
+                get(Semantic::Positions).context("Positions not found")?,
+            )?,
+            normals: accessor::read_f32(
+                &root,
+                buffers,
+                get(Semantic::Normals).context("Normals not found")?,
+            )?,
+            tex_coords: get(Semantic::TexCoords(0))
+                .map(|a| accessor::read_f32(&root, buffers, a))
+                .transpose()?,
+            skinning: skin::read_joints_and_weights(&root, buffers, p)?,
+            targets: morph::read_morph_targets(&root, buffers, 0, 0)?,
+            indices: accessor::read_indices(
+                &root,
+                buffers,
+                p.indices.context("Indices not found")?.value(),
+            )?,
+        })
+    }
+}
+
+fn to_f64(values: &[f32]) -> Vec<f64> {
+    values.iter().map(|v| *v as f64).collect()
+}
+
+/// An accessor written from a part, which the other parts sharing it have to agree with
+struct Written {
+    values: Vec<f64>,
+    /// Name of the part file
+    file: String,
+    /// Index of the accessor that holds the data
+    accessor: usize,
+}
+
+/// A glTF document being rebuilt from a parts directory.
+/// `views` holds the contents of each buffer view of the BIN chunk, and `original_views` the
+/// contents in input.bin.
+struct Packer {
+    json: Value,
+    root: gltf_json::Root,
+    views: Vec<Vec<u8>>,
+    original_views: Vec<Vec<u8>>,
+    written: HashMap<usize, Written>,
+    relayout: bool,
+}
+
+impl Packer {
+    fn new(json: Value, bin: &[u8]) -> Result<Self> {
+        let mut root_json = json.clone();
+        image::fill_texture_sources(&mut root_json);
+        let root: gltf_json::Root = serde_json::from_value(root_json)?;
+        if root.buffers.len() != 1 || root.buffers[0].uri.is_some() {
+            bail!("Only a single buffer in the BIN chunk is supported");
+        }
+        let mut views = Vec::new();
+        for v in &root.buffer_views {
+            let ofs = v.byte_offset.unwrap_or(0) as usize;
+            let len = v.byte_length as usize;
+            views.push(
+                bin.get(ofs..ofs + len)
+                    .context("bufferView is out of range of input.bin")?
+                    .to_vec(),
+            );
+        }
+        Ok(Self {
+            json,
+            root,
+            original_views: views.clone(),
+            views,
+            written: HashMap::new(),
+            relayout: false,
+        })
+    }
+    fn replace_view(&mut self, view: usize, data: Vec<u8>) {
+        if self.views[view].len() != data.len() {
+            self.relayout = true;
+        }
+        self.views[view] = data;
+    }
+    /// Append a dense and tightly packed accessor with `values` on a new buffer view and return its index
+    fn append_accessor(&mut self, accessor: usize, values: &[f64]) -> Result<usize> {
+        let layout = Layout::of(&self.root.accessors[accessor])?;
+        let count = values.len() / layout.components;
+        let mut data = vec![0u8; count * layout.element_size];
+        accessor::write_elements(&layout, &mut data, 0, None, values)?;
+        let view = self.views.len();
+        self.json["bufferViews"]
+            .as_array_mut()
+            .expect("bufferViews should exist")
+            .push(json!({ "buffer": 0, "byteLength": data.len() }));
+        self.views.push(data);
+        self.relayout = true;
+        let mut new_accessor = self.json["accessors"][accessor].clone();
+        new_accessor["bufferView"] = json!(view);
+        new_accessor["byteOffset"] = json!(0);
+        new_accessor["count"] = json!(count);
+        if let Some(a) = new_accessor.as_object_mut() {
+            a.remove("min");
+            a.remove("max");
+            a.remove("sparse");
+        }
+        let accessors = self.json["accessors"]
+            .as_array_mut()
+            .expect("accessors should exist");
+        accessors.push(new_accessor);
+        Ok(accessors.len() - 1)
+    }
+    /// Overwrite the elements of an accessor with `values`.
+    /// Returns the index of the accessor that holds the data afterwards.
+    fn write_accessor(&mut self, accessor: usize, values: &[f64]) -> Result<usize> {
+        let a = &self.root.accessors[accessor];
+        let layout = Layout::of(a)?;
+        // Sparse accessors are replaced with dense ones
+        let view_idx = match (a.buffer_view, &a.sparse) {
+            (Some(view), None) => view.value(),
+            _ => return self.append_accessor(accessor, values),
+        };
+        if a.count as usize * layout.components != values.len() {
+            return self.append_accessor(accessor, values);
+        }
+        let stride = self.root.buffer_views[view_idx]
+            .byte_stride
+            .map(|s| s as usize);
+        let offset = a.byte_offset as usize;
+        accessor::write_elements(&layout, &mut self.views[view_idx], offset, stride, values)
+            .with_context(|| format!("Accessor #{}", accessor))?;
+        Ok(accessor)
+    }
+    /// Put `values` from part file `file` into an accessor unless they are the contents in
+    /// input.bin. Primitives can share accessors, so the parts writing to the same accessor
+    /// have to agree on its contents.
+    /// Returns the index of the accessor that holds the data, or None if nothing was changed.
+    fn update_accessor(
+        &mut self,
+        accessor: usize,
+        values: Vec<f64>,
+        file: &str,
+    ) -> Result<Option<usize>> {
+        let original = accessor::read_values_in_views(&self.root, &self.original_views, accessor);
+        if original.ok().map(|(_, v)| v).as_ref() == Some(&values) {
+            return Ok(None);
+        }
+        if let Some(w) = self.written.get(&accessor) {
+            if w.values != values {
+                bail!(
+                    "{} and {} write different data to accessor #{}, which their primitives share",
+                    w.file,
+                    file,
+                    accessor
+                );
+            }
+            return Ok(Some(w.accessor));
+        }
+        let new_accessor = self.write_accessor(accessor, &values)?;
+        self.written.insert(
+            accessor,
+            Written {
+                values,
+                file: file.to_string(),
+                accessor: new_accessor,
+            },
+        );
+        Ok(Some(new_accessor))
+    }
+    /// Put the data of `part`, read from file `file`, into a primitive
+    fn pack_primitive(
+        &mut self,
+        mesh: usize,
+        primitive: usize,
+        part: &Part,
+        file: &str,
+    ) -> Result<()> {
+        let p = &self.root.meshes[mesh].primitives[primitive];
+        let vertex_count = part.positions.len();
+        if part.normals.len() != vertex_count
+            || part.tex_coords.as_ref().map(|t| t.len() != vertex_count) == Some(true)
+            || part.skinning.as_ref().map(|s| s.0.len() != vertex_count) == Some(true)
+        {
+            bail!("The numbers of positions, normals, texCoords and joints are different");
+        }
+        let mut attributes = Vec::new();
+        for (k, v) in &p.attributes {
+            let semantic = match k {
+                Valid(s) => s.clone(),
+                _ => bail!("Unknown attribute semantic"),
+            };
+            attributes.push((semantic, v.value()));
+        }
+        let indices = p.indices.context("Primitive has no indices")?.value();
+        let original_targets = p.targets.clone().unwrap_or_default();
+        // Parts are written without morph targets when they can't be read
+        let carries_targets = !part.targets.is_empty();
+        if carries_targets && part.targets.len() != original_targets.len() {
+            bail!(
+                "The part has {} morph targets while the primitive has {}",
+                part.targets.len(),
+                original_targets.len()
+            );
+        }
+        let ap = attributes
+            .iter()
+            .find(|(s, _)| *s == Semantic::Positions)
+            .context("Primitive has no positions")?
+            .1;
+        let known = 2 + part.tex_coords.is_some() as usize + 2 * part.skinning.is_some() as usize;
+        if self.root.accessors[ap].count as usize != vertex_count
+            && ((!original_targets.is_empty() && !carries_targets) || attributes.len() > known)
+        {
+            bail!("The number of vertices can not be changed since the primitive has other vertex attributes or morph targets");
+        }
+        let path = format!("/meshes/{}/primitives/{}", mesh, primitive);
+        for (semantic, accessor) in attributes {
+            let (name, data) = match (semantic, &part.tex_coords, &part.skinning) {
+                (Semantic::Positions, _, _) => ("POSITION", to_f64(part.positions.flatten())),
+                (Semantic::Normals, _, _) => ("NORMAL", to_f64(part.normals.flatten())),
+                (Semantic::TexCoords(0), Some(t), _) => ("TEXCOORD_0", to_f64(t.flatten())),
+                (Semantic::Joints(0), _, Some((j, _))) => {
+                    ("JOINTS_0", j.flatten().iter().map(|j| *j as f64).collect())
+                }
+                (Semantic::Weights(0), _, Some((_, w))) => ("WEIGHTS_0", to_f64(w.flatten())),
+                _ => continue,
+            };
+            let new_accessor = match self.update_accessor(accessor, data, file)? {
+                Some(a) => a,
+                None => continue,
+            };
+            if name == "POSITION" {
+                let (min, max) = bounding_coords3d(&part.positions);
+                self.json["accessors"][new_accessor]["min"] = json!(min);
+                self.json["accessors"][new_accessor]["max"] = json!(max);
+            }
+            if new_accessor != accessor {
+                self.json
+                    .pointer_mut(&format!("{}/attributes", path))
+                    .unwrap()[name] = json!(new_accessor);
+            }
+        }
+        for (t, (original, target)) in original_targets.iter().zip(&part.targets).enumerate() {
+            for (name, accessor, data) in [
+                ("POSITION", original.positions, &target.positions),
+                ("NORMAL", original.normals, &target.normals),
+                ("TANGENT", original.tangents, &target.tangents),
+            ] {
+                let (accessor, data) = match (accessor, data) {
+                    (Some(a), Some(data)) => (a.value(), data),
+                    (None, None) => continue,
+                    _ => bail!("Morph target #{} has a different set of attributes", t),
+                };
+                let new_accessor =
+                    match self.update_accessor(accessor, to_f64(data.flatten()), file)? {
+                        Some(a) => a,
+                        None => continue,
+                    };
+                if name == "POSITION" {
+                    let (min, max) = bounding_coords3d(data);
+                    self.json["accessors"][new_accessor]["min"] = json!(min);
+                    self.json["accessors"][new_accessor]["max"] = json!(max);
+                }
+                if new_accessor != accessor {
+                    self.json
+                        .pointer_mut(&format!("{}/targets/{}", path, t))
+                        .unwrap()[name] = json!(new_accessor);
+                }
+            }
+        }
+        let data = part.indices.iter().map(|i| *i as f64).collect();
+        if let Some(new_accessor) = self.update_accessor(indices, data, file)? {
+            self.json.pointer_mut(&path).unwrap()["indices"] = json!(new_accessor);
+        }
+        Ok(())
+    }
+    /// Build the BIN chunk from the buffer views.
+    /// The original layout is kept as long as no buffer view changed its size.
+    fn finish(mut self, mut bin: Vec<u8>) -> (Value, Vec<u8>) {
+        if self.relayout {
+            bin.clear();
+            for (i, data) in self.views.iter().enumerate() {
+                let v = &mut self.json["bufferViews"][i];
+                v["byteOffset"] = json!(bin.len());
+                v["byteLength"] = json!(data.len());
+                bin.extend_from_slice(data);
+                while bin.len() % 4 != 0 {
+                    bin.push(0);
+                }
+            }
+            self.json["buffers"][0]["byteLength"] = json!(bin.len());
+        } else {
+            for (v, data) in self.root.buffer_views.iter().zip(self.views.iter()) {
+                let ofs = v.byte_offset.unwrap_or(0) as usize;
+                bin[ofs..ofs + data.len()].copy_from_slice(data);
+            }
+        }
+        (self.json, bin)
+    }
+}
+
+/// Rebuild a VRM from a parts directory written by `parts::split`.
+/// Images and primitives that have a file in the directory replace the original data,
+/// so the output is the same document as the input when nothing was changed.
+/// Returns the document and the files of the directory that were used.
+pub fn pack(parts_dir: &Path) -> Result<(Document, Vec<PathBuf>)> {
+    let json: Value = serde_json::from_slice(
+        &fs::read(parts_dir.join("input.json")).context("Failed to read input.json")?,
+    )?;
+    let bin = fs::read(parts_dir.join("input.bin")).context("Failed to read input.bin")?;
+    let mut packer = Packer::new(json, &bin)?;
+    let mut used = Vec::new();
+
+    for (i, image) in packer.root.images.clone().iter().enumerate() {
+        let view = match image.buffer_view {
+            Some(view) => view,
+            None => continue,
+        };
+        let (format, path) = match find_image_file(parts_dir, i, image.name.as_deref())? {
+            Some(found) => found,
+            None => continue,
+        };
+        let data = fs::read(&path)?;
+        if ImageFormat::detect(&data) != Some(format) {
+            bail!(
+                "{} is not an {} file",
+                path.to_string_lossy(),
+                format.mime_type()
+            );
+        }
+        let original = image
+            .mime_type
+            .as_ref()
+            .and_then(|m| ImageFormat::from_mime_type(&m.0));
+        if original != Some(format) {
+            // PNG and JPEG are sampled the same way, but the others need their texture extension
+            if format.texture_extension().is_some()
+                || original.and_then(|f| f.texture_extension()).is_some()
+            {
+                bail!(
+                    "Image #{} ({:?}) can't be replaced by {}",
+                    i,
+                    original.map(|f| f.mime_type()),
+                    path.to_string_lossy()
+                );
+            }
+            packer.json["images"][i]["mimeType"] = json!(format.mime_type());
+        }
+        packer.replace_view(view.value(), data);
+        used.push(path);
+    }
+    for (m, mesh) in packer.root.meshes.clone().iter().enumerate() {
+        for p in 0..mesh.primitives.len() {
+            let path = parts_dir.join(part_file_name(mesh.name.as_deref(), m, p));
+            if !path.exists() {
+                continue;
+            }
+            let part = Part::load(&path)
+                .with_context(|| format!("Failed to load {}", path.to_string_lossy()))?;
+            let file = path.file_name().unwrap_or_default().to_string_lossy();
+            packer
+                .pack_primitive(m, p, &part, &file)
+                .with_context(|| format!("Failed to pack {}", path.to_string_lossy()))?;
+            used.push(path);
+        }
+    }
+
+    let (json, bin) = packer.finish(bin);
+    Ok((Document::new(json, bin), used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parts;
+    use crate::parts::image_file_name;
+    use crate::scene::Node;
+    use crate::scene::SceneBuilder;
+    use crate::validate;
+
+    /// A quad split into two primitives that share the POSITION and NORMAL accessors
+    fn shared_quad() -> Document {
+        let positions: [[f32; 3]; 4] = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let mut bin: Vec<u8> = positions
+            .iter()
+            .chain(&[[0.0, 0.0, 1.0]; 4])
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        bin.extend([0u32, 1, 2, 0, 2, 3].iter().flat_map(|i| i.to_le_bytes()));
+        let json = json!({
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": bin.len() }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 48, "target": 34962 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 48, "target": 34962 },
+                { "buffer": 0, "byteOffset": 96, "byteLength": 24, "target": 34963 },
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                  "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+                { "bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC3" },
+                { "bufferView": 2, "byteOffset": 0, "componentType": 5125, "count": 3, "type": "SCALAR" },
+                { "bufferView": 2, "byteOffset": 12, "componentType": 5125, "count": 3, "type": "SCALAR" },
+            ],
+            "meshes": [{
+                "name": "Quad",
+                "primitives": [
+                    { "attributes": { "POSITION": 0, "NORMAL": 1 }, "indices": 2 },
+                    { "attributes": { "POSITION": 0, "NORMAL": 1 }, "indices": 3 },
+                ],
+            }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }],
+            "scene": 0,
+        });
+        Document::new(json, bin)
+    }
+
+    fn parts_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vacation-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Move vertex `vertex` of the POSITION of a part file to `x` on the X axis
+    fn move_vertex(path: &Path, vertex: usize, x: f32) {
+        let mut doc = Document::load(path).unwrap();
+        let root = doc.root().unwrap();
+        let a = root.meshes[0].primitives[0].attributes[&Valid(Semantic::Positions)].value();
+        let a = &root.accessors[a];
+        let view = &root.buffer_views[a.buffer_view.unwrap().value()];
+        let ofs = view.byte_offset.unwrap_or(0) as usize + a.byte_offset as usize + vertex * 12;
+        doc.bin[ofs..ofs + 4].copy_from_slice(&x.to_le_bytes());
+        fs::write(path, doc.to_vec().unwrap()).unwrap();
+    }
+
+    fn positions(doc: &Document, primitive: usize) -> Vec<[f32; 3]> {
+        let root = doc.root().unwrap();
+        let a = root.meshes[0].primitives[primitive].attributes[&Valid(Semantic::Positions)];
+        accessor::read_f32(&root, doc.buffers(), a.value()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_is_identical() {
+        let dir = parts_dir("round-trip");
+        let doc = shared_quad();
+        parts::split(&doc, &dir).unwrap();
+        let (packed, used) = pack(&dir).unwrap();
+        assert_eq!(used.len(), 2);
+        assert_eq!(packed.to_vec().unwrap(), doc.to_vec().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn edit_of_a_shared_accessor_is_kept() {
+        let dir = parts_dir("shared-edit");
+        parts::split(&shared_quad(), &dir).unwrap();
+        // Only the first part is edited. The second one still has the original positions.
+        move_vertex(&dir.join(part_file_name(Some("Quad"), 0, 0)), 1, 2.0);
+        let (packed, _) = pack(&dir).unwrap();
+        for p in 0..2 {
+            assert_eq!(positions(&packed, p)[1], [2.0, 0.0, 0.0]);
+        }
+        assert_eq!(packed.json["accessors"][0]["max"], json!([2.0, 1.0, 0.0]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn conflicting_edits_of_a_shared_accessor_fail() {
+        let dir = parts_dir("shared-conflict");
+        parts::split(&shared_quad(), &dir).unwrap();
+        let first = part_file_name(Some("Quad"), 0, 0);
+        let second = part_file_name(Some("Quad"), 0, 1);
+        move_vertex(&dir.join(&first), 1, 2.0);
+        move_vertex(&dir.join(&second), 1, 3.0);
+        let e = format!("{:#}", pack(&dir).unwrap_err());
+        assert!(e.contains(&first) && e.contains(&second), "{}", e);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+    const JPEG: &[u8] = b"\xff\xd8\xff\xe0";
+    const WEBP: &[u8] = b"RIFF\x04\0\0\0WEBP";
+
+    fn image_data(doc: &Document, image: usize) -> &[u8] {
+        let view = &doc.json["bufferViews"]
+            [doc.json["images"][image]["bufferView"].as_u64().unwrap() as usize];
+        let ofs = view["byteOffset"].as_u64().unwrap_or(0) as usize;
+        &doc.bin[ofs..ofs + view["byteLength"].as_u64().unwrap() as usize]
+    }
+
+    #[test]
+    fn images_keep_their_formats() {
+        let mut scene = SceneBuilder::new();
+        scene.add_texture(Some("skin"), ImageFormat::Png, PNG);
+        scene.add_texture(Some("hair"), ImageFormat::Webp, WEBP);
+        scene.add_node(&Node::default(), None).unwrap();
+        let doc = scene.build().unwrap();
+        let dir = parts_dir("image-formats");
+        parts::split(&doc, &dir).unwrap();
+        let png = dir.join(image_file_name(0, Some("skin"), ImageFormat::Png));
+        let webp = dir.join(image_file_name(1, Some("hair"), ImageFormat::Webp));
+        assert_eq!(fs::read(&png).unwrap(), PNG);
+        assert_eq!(fs::read(&webp).unwrap(), WEBP);
+
+        // PNG can be replaced by JPEG, and WebP by another WebP
+        fs::remove_file(&png).unwrap();
+        let jpeg = dir.join(image_file_name(0, Some("skin"), ImageFormat::Jpeg));
+        fs::write(&jpeg, JPEG).unwrap();
+        let new_webp = [WEBP, b"VP8L"].concat();
+        fs::write(&webp, &new_webp).unwrap();
+        let (packed, used) = pack(&dir).unwrap();
+        assert_eq!(used, vec![jpeg.clone(), webp.clone()]);
+        assert_eq!(packed.json["images"][0]["mimeType"], "image/jpeg");
+        assert_eq!(image_data(&packed, 0), JPEG);
+        assert_eq!(packed.json["images"][1]["mimeType"], "image/webp");
+        assert_eq!(image_data(&packed, 1), new_webp);
+        assert_eq!(
+            packed.json["textures"][1]["extensions"]["EXT_texture_webp"]["source"],
+            1
+        );
+        validate::check_glb(&packed.to_vec().unwrap()).unwrap();
+
+        // WebP is sampled through its extension, so it can't become PNG
+        fs::remove_file(&webp).unwrap();
+        fs::write(
+            dir.join(image_file_name(1, Some("hair"), ImageFormat::Png)),
+            PNG,
+        )
+        .unwrap();
+        assert!(pack(&dir).is_err());
+        // The data should be in the format of the file extension
+        fs::write(&jpeg, PNG).unwrap();
+        assert!(pack(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,664 @@
+//! The parts directory: a VRM split into one GLB per mesh primitive and one file per image,
+//! next to the original JSON and BIN chunks so that `pack` can put it back together.
+
+use crate::accessor;
+use crate::glb::write_glb;
+use crate::image::ImageFormat;
+use crate::image::IMAGE_FORMATS;
+use crate::morph;
+use crate::morph::MorphTarget;
+use crate::skin;
+use crate::skin::Skeleton;
+use crate::skin::Skinning;
+use crate::Document;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf::buffer::Source;
+use gltf::mesh::Mode;
+use gltf::Accessor;
+use gltf::Image;
+use gltf::Primitive;
+use gltf::Semantic;
+use gltf_json::extensions::texture::TextureTransform;
+use gltf_json::extensions::texture::TextureTransformOffset;
+use gltf_json::extensions::texture::TextureTransformRotation;
+use gltf_json::extensions::texture::TextureTransformScale;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// A mesh primitive of the document, used to tell where a problem was found
+#[derive(Clone, Debug)]
+pub struct PrimitiveLocation {
+    pub mesh: usize,
+    pub mesh_name: Option<String>,
+    pub primitive: usize,
+}
+
+impl PrimitiveLocation {
+    fn new(p: &Primitive, mesh: &gltf::Mesh) -> Self {
+        Self {
+            mesh: mesh.index(),
+            mesh_name: mesh.name().map(|s| s.to_string()),
+            primitive: p.index(),
+        }
+    }
+}
+
+impl fmt::Display for PrimitiveLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mesh #{}", self.mesh)?;
+        if let Some(name) = &self.mesh_name {
+            write!(f, " ({})", name)?;
+        }
+        write!(f, " primitive #{}", self.primitive)
+    }
+}
+
+/// A file written to the parts directory
+#[derive(Clone, Debug)]
+pub enum PartFile {
+    Primitive {
+        at: PrimitiveLocation,
+        path: PathBuf,
+        vertices: usize,
+        triangles: usize,
+        /// Names of the morph targets written to the part
+        morph_targets: Vec<String>,
+    },
+    Image {
+        image: usize,
+        name: Option<String>,
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for PartFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartFile::Primitive {
+                at,
+                path,
+                vertices,
+                triangles,
+                morph_targets,
+            } => {
+                write!(f, "{}: {} vertices, {} triangles", at, vertices, triangles)?;
+                if !morph_targets.is_empty() {
+                    write!(f, ", morph targets {:?}", morph_targets)?;
+                }
+                write!(f, " -> {}", path.to_string_lossy())
+            }
+            PartFile::Image { image, name, path } => {
+                write!(
+                    f,
+                    "image #{} ({:?}) -> {}",
+                    image,
+                    name,
+                    path.to_string_lossy()
+                )
+            }
+        }
+    }
+}
+
+/// What [`split`] wrote and what it could not
+#[derive(Debug, Default)]
+pub struct Split {
+    pub files: Vec<PartFile>,
+    pub errors: Vec<PartError>,
+}
+
+/// A primitive or an image that could not be written to the parts directory as is.
+/// None of them stop the split; the affected part is skipped or written without the data.
+#[derive(Debug)]
+pub enum PartError {
+    /// Only TRIANGLES can be written as a part. The primitive is skipped.
+    UnsupportedMode { at: PrimitiveLocation, mode: Mode },
+    /// A required attribute (or the indices) is missing. The primitive is skipped.
+    MissingAttribute {
+        at: PrimitiveLocation,
+        semantic: String,
+    },
+    /// The data of an accessor can't be read. The primitive is skipped.
+    UnsupportedAccessor {
+        at: PrimitiveLocation,
+        semantic: String,
+        accessor: usize,
+        reason: String,
+    },
+    /// An attribute that parts don't hold. `pack` keeps the original data.
+    IgnoredAttribute {
+        at: PrimitiveLocation,
+        semantic: String,
+        accessor: usize,
+    },
+    /// JOINTS_0 and WEIGHTS_0 can't be carried with their skin.
+    /// The part is written unskinned and `pack` keeps the original data.
+    UnsupportedSkin {
+        at: PrimitiveLocation,
+        reason: String,
+    },
+    /// The morph targets can't be read. The part is written without them
+    /// and `pack` keeps the original data.
+    UnsupportedMorphTargets {
+        at: PrimitiveLocation,
+        reason: String,
+    },
+    /// The part is written without the base color texture.
+    MissingTexture {
+        at: PrimitiveLocation,
+        reason: String,
+    },
+    /// The image is not in the BIN chunk or its format is unknown. No file is written for it.
+    UnsupportedImage {
+        image: usize,
+        name: Option<String>,
+        reason: String,
+    },
+}
+
+impl fmt::Display for PartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PartError::UnsupportedMode { at, mode } => {
+                write!(f, "{}: mode {:?} is not supported, skipped", at, mode)
+            }
+            PartError::MissingAttribute { at, semantic } => {
+                write!(f, "{}: {} is missing, skipped", at, semantic)
+            }
+            PartError::UnsupportedAccessor {
+                at,
+                semantic,
+                reason,
+                ..
+            } => write!(f, "{}: {} can't be read: {}, skipped", at, semantic, reason),
+            PartError::IgnoredAttribute {
+                at,
+                semantic,
+                accessor,
+            } => write!(
+                f,
+                "{}: {} (accessor #{}) is not stored in the part, the original is kept",
+                at, semantic, accessor
+            ),
+            PartError::UnsupportedSkin { at, reason } => {
+                write!(f, "{}: written without skin since {}", at, reason)
+            }
+            PartError::UnsupportedMorphTargets { at, reason } => {
+                write!(f, "{}: written without morph targets since {}", at, reason)
+            }
+            PartError::MissingTexture { at, reason } => {
+                write!(f, "{}: written without texture since {}", at, reason)
+            }
+            PartError::UnsupportedImage {
+                image,
+                name,
+                reason,
+            } => {
+                write!(f, "image #{} ({:?}): {}, skipped", image, name, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PartError {}
+
+fn semantic_name(semantic: &Semantic) -> String {
+    match semantic {
+        Semantic::Positions => "POSITION".to_string(),
+        Semantic::Normals => "NORMAL".to_string(),
+        Semantic::Tangents => "TANGENT".to_string(),
+        Semantic::Colors(n) => format!("COLOR_{}", n),
+        Semantic::TexCoords(n) => format!("TEXCOORD_{}", n),
+        Semantic::Joints(n) => format!("JOINTS_{}", n),
+        Semantic::Weights(n) => format!("WEIGHTS_{}", n),
+        Semantic::Extras(name) => format!("_{}", name),
+    }
+}
+
+/// Copy the data of an image stored in the BIN chunk, in one of `image::IMAGE_FORMATS`
+pub fn extract_image_data(bin: &[u8], m: &Image) -> Result<(ImageFormat, Vec<u8>), PartError> {
+    let error = |reason: String| PartError::UnsupportedImage {
+        image: m.index(),
+        name: m.name().map(|s| s.to_string()),
+        reason,
+    };
+    match m.source() {
+        gltf::image::Source::View { view, mime_type } => {
+            let format = ImageFormat::from_mime_type(mime_type)
+                .ok_or_else(|| error(format!("{} is not supported", mime_type)))?;
+            if !matches!(view.buffer().source(), Source::Bin) {
+                return Err(error("the image is not in the BIN chunk".to_string()));
+            }
+            let offset = view.offset();
+            let length = view.length();
+            bin.get(offset..(offset + length))
+                .map(|data| (format, Vec::from(data)))
+                .ok_or_else(|| error("the bufferView is out of range".to_string()))
+        }
+        gltf::image::Source::Uri { uri, .. } => {
+            Err(error(format!("external image {:?} is not supported", uri)))
+        }
+    }
+}
+
+/// Read an attribute of `N` floats per element
+fn read_f32_accessor<const N: usize>(
+    doc: &Document,
+    root: &gltf_json::Root,
+    at: &PrimitiveLocation,
+    semantic: &Semantic,
+    a: &Accessor,
+) -> Result<Vec<[f32; N]>, PartError> {
+    accessor::read_f32(root, doc.buffers(), a.index())
+        .map_err(|e| unsupported_accessor(at, &semantic_name(semantic), a, e))
+}
+
+fn read_indices(
+    doc: &Document,
+    root: &gltf_json::Root,
+    at: &PrimitiveLocation,
+    a: &Accessor,
+) -> Result<Vec<[u32; 3]>, PartError> {
+    let indices = accessor::read_indices(root, doc.buffers(), a.index())
+        .map_err(|e| unsupported_accessor(at, "indices", a, e))?;
+    Ok(indices
+        .chunks_exact(3)
+        .map(|v| [v[0], v[1], v[2]])
+        .collect())
+}
+
+fn unsupported_accessor(
+    at: &PrimitiveLocation,
+    semantic: &str,
+    a: &Accessor,
+    error: anyhow::Error,
+) -> PartError {
+    PartError::UnsupportedAccessor {
+        at: at.clone(),
+        semantic: semantic.to_string(),
+        accessor: a.index(),
+        reason: format!("{:#}", error),
+    }
+}
+
+/// Read the skinning of a primitive with the skin of a node that uses its mesh
+fn read_skinning(
+    doc: &Document,
+    root: &gltf_json::Root,
+    mesh: &gltf::Mesh,
+    p: &Primitive,
+    vertex_count: usize,
+) -> Result<Option<Skinning>> {
+    let primitive = &root.meshes[mesh.index()].primitives[p.index()];
+    let (joints, weights) = match skin::read_joints_and_weights(root, doc.buffers(), primitive)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let skin = root
+        .nodes
+        .iter()
+        .filter(|n| n.mesh.map(|m| m.value()) == Some(mesh.index()))
+        .find_map(|n| n.skin)
+        .context("no node uses the mesh with a skin")?;
+    let skeleton = Skeleton::read(root, doc.buffers(), skin.value())?;
+    let skinning = Skinning {
+        joints,
+        weights,
+        skeleton,
+    };
+    skinning
+        .validate(vertex_count)
+        .with_context(|| format!("skin #{}", skin.value()))?;
+    Ok(Some(skinning))
+}
+
+/// Read the morph targets of a primitive that has `vertex_count` vertices
+fn read_morph_targets(
+    doc: &Document,
+    root: &gltf_json::Root,
+    mesh: &gltf::Mesh,
+    p: &Primitive,
+    vertex_count: usize,
+) -> Result<Vec<MorphTarget>> {
+    let targets = morph::read_morph_targets(root, doc.buffers(), mesh.index(), p.index())?;
+    for (i, t) in targets.iter().enumerate() {
+        for data in [&t.positions, &t.normals, &t.tangents]
+            .into_iter()
+            .flatten()
+        {
+            if data.len() != vertex_count {
+                bail!(
+                    "target #{} has {} elements for {} vertices",
+                    i,
+                    data.len(),
+                    vertex_count
+                );
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// File name of the GLB in a parts directory that holds a primitive of a mesh
+pub fn part_file_name(
+    mesh_name: Option<&str>,
+    mesh_index: usize,
+    primitive_index: usize,
+) -> String {
+    format!(
+        "{}{}_{}.glb",
+        mesh_name.unwrap_or("None"),
+        mesh_index,
+        primitive_index
+    )
+}
+
+/// File name of an image in a parts directory, with the file extension of its format
+pub fn image_file_name(
+    image_index: usize,
+    image_name: Option<&str>,
+    format: ImageFormat,
+) -> String {
+    format!(
+        "i{}_{}.{}",
+        image_index,
+        image_name.unwrap_or("None"),
+        format.file_extension()
+    )
+}
+
+/// The file of image `image_index` in a parts directory and its format, whichever format it is in.
+/// Fails if there are files in more than one format.
+pub fn find_image_file(
+    parts_dir: &Path,
+    image_index: usize,
+    image_name: Option<&str>,
+) -> Result<Option<(ImageFormat, PathBuf)>> {
+    let found: Vec<(ImageFormat, PathBuf)> = IMAGE_FORMATS
+        .into_iter()
+        .map(|f| {
+            (
+                f,
+                parts_dir.join(image_file_name(image_index, image_name, f)),
+            )
+        })
+        .filter(|(_, path)| path.exists())
+        .collect();
+    match found.as_slice() {
+        [] => Ok(None),
+        [found] => Ok(Some(found.clone())),
+        _ => bail!(
+            "Image #{} has more than one file in the parts directory",
+            image_index
+        ),
+    }
+}
+
+/// Base color texture of a primitive as image data and its KHR_texture_transform,
+/// if it can be written to the part
+fn base_color_texture(
+    bin: &[u8],
+    at: &PrimitiveLocation,
+    p: &Primitive,
+) -> Result<(ImageFormat, Vec<u8>, Option<TextureTransform>), PartError> {
+    let missing = |reason: String| PartError::MissingTexture {
+        at: at.clone(),
+        reason,
+    };
+    let pbr = p.material().pbr_metallic_roughness();
+    let bct = pbr
+        .base_color_texture()
+        .ok_or_else(|| missing("the material has no base color texture".to_string()))?;
+    let transform = bct.texture_transform();
+    let tex_coord = transform
+        .as_ref()
+        .and_then(|t| t.tex_coord())
+        .unwrap_or(bct.tex_coord());
+    if tex_coord != 0 {
+        return Err(missing(format!(
+            "the base color texture uses TEXCOORD_{}",
+            tex_coord
+        )));
+    }
+    let transform = transform.map(|t| TextureTransform {
+        offset: TextureTransformOffset(t.offset()),
+        rotation: TextureTransformRotation(t.rotation()),
+        scale: TextureTransformScale(t.scale()),
+        tex_coord: Some(0),
+        extras: Default::default(),
+    });
+    let (format, data) =
+        extract_image_data(bin, &bct.texture().source()).map_err(|e| missing(e.to_string()))?;
+    Ok((format, data, transform))
+}
+
+/// Write one primitive as a part GLB. Problems that don't prevent it are pushed to `errors`.
+fn split_primitive(
+    doc: &Document,
+    root: &gltf_json::Root,
+    mesh: &gltf::Mesh,
+    p: &Primitive,
+    translation: [f32; 3],
+    parts_dir: &Path,
+    errors: &mut Vec<PartError>,
+) -> Result<Result<PartFile, PartError>> {
+    let at = PrimitiveLocation::new(p, mesh);
+    if p.mode() != Mode::Triangles {
+        return Ok(Err(PartError::UnsupportedMode { at, mode: p.mode() }));
+    }
+    let get = |semantic: Semantic| {
+        p.get(&semantic).ok_or_else(|| PartError::MissingAttribute {
+            at: at.clone(),
+            semantic: semantic_name(&semantic),
+        })
+    };
+    let (ap, an) = match (get(Semantic::Positions), get(Semantic::Normals)) {
+        (Ok(ap), Ok(an)) => (ap, an),
+        (Err(e), _) | (_, Err(e)) => return Ok(Err(e)),
+    };
+    let ai = match p.indices() {
+        Some(ai) => ai,
+        None => {
+            return Ok(Err(PartError::MissingAttribute {
+                at,
+                semantic: "indices".to_string(),
+            }))
+        }
+    };
+    let skinning = match read_skinning(doc, root, mesh, p, ap.count()) {
+        Ok(skinning) => skinning,
+        Err(e) => {
+            errors.push(PartError::UnsupportedSkin {
+                at: at.clone(),
+                reason: format!("{:#}", e),
+            });
+            None
+        }
+    };
+    for (semantic, a) in p.attributes() {
+        match semantic {
+            Semantic::Positions | Semantic::Normals | Semantic::TexCoords(0) => {}
+            Semantic::Joints(0) | Semantic::Weights(0) if skinning.is_some() => {}
+            semantic => errors.push(PartError::IgnoredAttribute {
+                at: at.clone(),
+                semantic: semantic_name(&semantic),
+                accessor: a.index(),
+            }),
+        }
+    }
+    let vertices = match read_f32_accessor::<3>(doc, root, &at, &Semantic::Positions, &ap) {
+        Ok(v) => v,
+        Err(e) => return Ok(Err(e)),
+    };
+    let normals = match read_f32_accessor::<3>(doc, root, &at, &Semantic::Normals, &an) {
+        Ok(v) => v,
+        Err(e) => return Ok(Err(e)),
+    };
+    let indices = match read_indices(doc, root, &at, &ai) {
+        Ok(v) => v,
+        Err(e) => return Ok(Err(e)),
+    };
+    let tex_coords0 = match p.get(&Semantic::TexCoords(0)) {
+        Some(at0) => match read_f32_accessor::<2>(doc, root, &at, &Semantic::TexCoords(0), &at0) {
+            Ok(v) => Some(v),
+            Err(e) => return Ok(Err(e)),
+        },
+        None => None,
+    };
+    let texture = match tex_coords0 {
+        Some(_) => base_color_texture(&doc.bin, &at, p)
+            .map_err(|e| errors.push(e))
+            .ok(),
+        None => None,
+    };
+    let targets = match read_morph_targets(doc, root, mesh, p, vertices.len()) {
+        Ok(targets) => targets,
+        Err(e) => {
+            errors.push(PartError::UnsupportedMorphTargets {
+                at: at.clone(),
+                reason: format!("{:#}", e),
+            });
+            Vec::new()
+        }
+    };
+    let path = parts_dir.join(part_file_name(mesh.name(), mesh.index(), p.index()));
+    write_glb(
+        &vertices,
+        &indices,
+        &normals,
+        tex_coords0.as_deref(),
+        texture.as_ref().map(|t| (t.0, t.1.as_slice())),
+        texture.as_ref().and_then(|t| t.2.as_ref()),
+        skinning.as_ref(),
+        &targets,
+        Some(translation),
+        &path.to_string_lossy(),
+    )?;
+    Ok(Ok(PartFile::Primitive {
+        at,
+        path,
+        vertices: vertices.len(),
+        triangles: indices.len(),
+        morph_targets: targets.into_iter().filter_map(|t| t.name).collect(),
+    }))
+}
+
+/// Write the parts of `doc` into `parts_dir`.
+/// Primitives and images that can't be written are skipped and returned as errors.
+pub fn split(doc: &Document, parts_dir: &Path) -> Result<Split> {
+    let root = doc.root()?;
+    let gltf = doc.gltf()?;
+    let bin = &doc.bin;
+    let json = &doc.json;
+    fs::create_dir_all(parts_dir)?;
+
+    {
+        // Keep the JSON chunk as is, since gltf_json drops the extensions it doesn't know (e.g. VRMC_vrm)
+        let mut path = parts_dir.to_path_buf();
+        path.push("input.json");
+        let json = serde_json::to_string_pretty(json)?;
+        fs::write(path, json.into_bytes())?;
+        let mut path = parts_dir.to_path_buf();
+        path.push("input.bin");
+        fs::write(path, bin)?;
+    }
+
+    let mut split = Split::default();
+    for mesh in gltf.meshes() {
+        for p in mesh.primitives() {
+            let translation = [0f32, 0f32, split.files.len() as f32 / 10.0];
+            let errors = &mut split.errors;
+            match split_primitive(doc, &root, &mesh, &p, translation, parts_dir, errors)? {
+                Ok(file) => split.files.push(file),
+                Err(e) => split.errors.push(e),
+            }
+        }
+    }
+    for m in gltf.images() {
+        let (format, data) = match extract_image_data(bin, &m) {
+            Ok(image) => image,
+            Err(e) => {
+                split.errors.push(e);
+                continue;
+            }
+        };
+        let path = parts_dir.join(image_file_name(m.index(), m.name(), format));
+        fs::write(&path, data)?;
+        split.files.push(PartFile::Image {
+            image: m.index(),
+            name: m.name().map(|s| s.to_string()),
+            path,
+        });
+    }
+    Ok(split)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::scene::Node;
+    use crate::scene::Primitive;
+    use crate::scene::SceneBuilder;
+
+    fn parts_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vacation-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Split a document with a single primitive and load the part of it
+    fn split_one(doc: &Document, name: &str) -> Document {
+        let dir = parts_dir(name);
+        let split = split(doc, &dir).unwrap();
+        assert!(split.errors.is_empty(), "{:?}", split.errors);
+        let part = Document::load(dir.join(part_file_name(None, 0, 0))).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        part
+    }
+
+    #[test]
+    fn skin_is_carried_into_the_part() {
+        let (model, skinning) = Model::rigged_figure();
+        let mut scene = SceneBuilder::new();
+        let skin = scene.add_skin(&skinning.skeleton, None).unwrap();
+        let mesh = scene
+            .add_mesh(
+                None,
+                &[Primitive {
+                    vertices: &model.vertices,
+                    normals: &model.normals,
+                    indices: &model.indices,
+                    joints: Some(&skinning.joints),
+                    weights: Some(&skinning.weights),
+                    ..Default::default()
+                }],
+            )
+            .unwrap();
+        let node = Node {
+            mesh: Some(mesh),
+            skin: Some(skin),
+            ..Default::default()
+        };
+        scene.add_node(&node, None).unwrap();
+        let part = split_one(&scene.build().unwrap(), "skinned-part");
+
+        let root = part.root().unwrap();
+        let skeleton = Skeleton::read(&root, part.buffers(), 0).unwrap();
+        assert_eq!(skeleton.joints.len(), skinning.skeleton.joints.len());
+        assert_eq!(
+            skeleton.inverse_bind_matrices,
+            skinning.skeleton.inverse_bind_matrices
+        );
+        let (joints, weights) =
+            skin::read_joints_and_weights(&root, part.buffers(), &root.meshes[0].primitives[0])
+                .unwrap()
+                .unwrap();
+        assert_eq!(joints, skinning.joints);
+        assert_eq!(weights, skinning.weights);
+    }
+}
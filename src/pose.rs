@@ -0,0 +1,146 @@
+//! Poses of the node hierarchy: the local transform of every node, from which world transforms
+//! are computed. The rest pose is the one written in the document.
+
+use crate::math;
+use crate::math::Quat;
+use crate::math::Vec3;
+use anyhow::bail;
+use anyhow::Result;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: math::IDENTITY_QUAT,
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl Transform {
+    /// The local transform of a node, given by its matrix or its TRS properties
+    pub fn of_node(node: &gltf_json::Node) -> Self {
+        if let Some(m) = &node.matrix {
+            let (translation, rotation, scale) = math::decompose_matrix(m);
+            return Self {
+                translation,
+                rotation,
+                scale,
+            };
+        }
+        Self {
+            translation: node.translation.unwrap_or([0.0; 3]),
+            rotation: node
+                .rotation
+                .map(|r| math::quat_normalize(r.0))
+                .unwrap_or(math::IDENTITY_QUAT),
+            scale: node.scale.unwrap_or([1.0; 3]),
+        }
+    }
+    /// The transform of `child` in the space `self` is in. Non-uniform scale under rotation
+    /// (which would be shear) is approximated by scaling each axis.
+    pub fn then(&self, child: &Transform) -> Transform {
+        Transform {
+            translation: self.transform_point(child.translation),
+            rotation: math::quat_normalize(math::quat_mul(self.rotation, child.rotation)),
+            scale: math::mul(self.scale, child.scale),
+        }
+    }
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        math::add(
+            self.translation,
+            math::quat_rotate(self.rotation, math::mul(self.scale, p)),
+        )
+    }
+    pub fn inverse_transform_point(&self, p: Vec3) -> Vec3 {
+        let p = math::quat_rotate(
+            math::quat_inverse(self.rotation),
+            math::sub(p, self.translation),
+        );
+        [0, 1, 2].map(|i| {
+            if self.scale[i] == 0.0 {
+                0.0
+            } else {
+                p[i] / self.scale[i]
+            }
+        })
+    }
+}
+
+/// Local transforms of all the nodes of a document
+#[derive(Clone, Debug, Default)]
+pub struct Pose {
+    pub local: Vec<Transform>,
+    pub parents: Vec<Option<usize>>,
+}
+
+/// The parent of every node. Fails if a node is a child of two nodes or is its own ancestor,
+/// since a glTF node hierarchy must be a forest.
+pub fn parents(root: &gltf_json::Root) -> Result<Vec<Option<usize>>> {
+    let mut parents = vec![None; root.nodes.len()];
+    for (i, n) in root.nodes.iter().enumerate() {
+        for c in n.children.iter().flatten() {
+            if let Some(p) = parents.get_mut(c.value()) {
+                if let Some(other) = *p {
+                    bail!(
+                        "node #{} is a child of both #{} and #{}",
+                        c.value(),
+                        other,
+                        i
+                    );
+                }
+                *p = Some(i);
+            }
+        }
+    }
+    for i in 0..parents.len() {
+        // A walk longer than the number of nodes is in a cycle that doesn't contain `i`,
+        // which is reported when one of its nodes is checked
+        let mut parent = parents[i];
+        for _ in 0..parents.len() {
+            match parent {
+                Some(p) if p == i => bail!("node #{} is its own ancestor", i),
+                Some(p) => parent = parents[p],
+                None => break,
+            }
+        }
+    }
+    Ok(parents)
+}
+
+impl Pose {
+    /// The pose the nodes have in the document
+    pub fn rest(root: &gltf_json::Root) -> Result<Self> {
+        Ok(Self {
+            local: root.nodes.iter().map(Transform::of_node).collect(),
+            parents: parents(root)?,
+        })
+    }
+    /// The transform of a node in the scene
+    pub fn world(&self, node: usize) -> Transform {
+        let mut world = self.local[node];
+        let mut parent = self.parents[node];
+        while let Some(p) = parent {
+            world = self.local[p].then(&world);
+            parent = self.parents[p];
+        }
+        world
+    }
+    /// The world transform of the parent of a node, or the identity for a root node
+    pub fn parent_world(&self, node: usize) -> Transform {
+        self.parents[node]
+            .map(|p| self.world(p))
+            .unwrap_or_default()
+    }
+    /// Ancestors of a node from its parent up to the root
+    pub fn ancestors(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(self.parents[node], |p| self.parents[*p])
+    }
+}
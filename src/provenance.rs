@@ -0,0 +1,636 @@
+//! Provenance of the parts and images of a parts directory: who made them, under which license and
+//! where they came from. A file can have its own `<file>.provenance.json` next to it, and
+//! `provenance.json` of the directory applies to the files without one. [`check`] compares the
+//! licenses with the license of the avatar and [`credits`] lists them.
+
+use crate::image;
+use crate::parts::find_image_file;
+use crate::parts::part_file_name;
+use crate::validate::Severity;
+use crate::vrm;
+use crate::vrm::CommercialUsage;
+use crate::vrm::CreditNotation;
+use crate::vrm::Meta;
+use crate::vrm::Modification;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Name of the file that gives the provenance of the files without their own
+pub const DEFAULT_FILE_NAME: &str = "provenance.json";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Provenance {
+    pub author: String,
+    /// Name of the license, e.g. an SPDX identifier such as CC-BY-4.0
+    pub license: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_url: Option<String>,
+    /// Where the file was obtained
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    /// The terms below can be left out for the licenses [`known_terms`] knows
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_redistribution: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_modification: Option<bool>,
+    /// The widest commercial usage allowed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commercial_usage: Option<CommercialUsage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credit_notation: Option<CreditNotation>,
+    /// Whether an avatar using the file has to be under the same license
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_alike: Option<bool>,
+}
+
+/// What a license allows, in the terms of the VRM meta. None for the terms that are not known.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Terms {
+    pub allow_redistribution: Option<bool>,
+    pub allow_modification: Option<bool>,
+    pub commercial_usage: Option<CommercialUsage>,
+    pub credit_notation: Option<CreditNotation>,
+    pub share_alike: Option<bool>,
+}
+
+/// Terms of common licenses given by their SPDX identifiers
+pub fn known_terms(license: &str) -> Option<Terms> {
+    use CommercialUsage::Corporation;
+    use CommercialUsage::PersonalNonProfit;
+    use CreditNotation::Required;
+    use CreditNotation::Unnecessary;
+    let (allow_redistribution, allow_modification, commercial_usage, credit_notation, share_alike) =
+        match license {
+            "CC0-1.0" => (true, true, Corporation, Unnecessary, false),
+            "CC-BY-4.0" | "MIT" => (true, true, Corporation, Required, false),
+            "CC-BY-SA-4.0" => (true, true, Corporation, Required, true),
+            "CC-BY-NC-4.0" => (true, true, PersonalNonProfit, Required, false),
+            "CC-BY-NC-SA-4.0" => (true, true, PersonalNonProfit, Required, true),
+            "CC-BY-ND-4.0" => (true, false, Corporation, Required, false),
+            "CC-BY-NC-ND-4.0" => (true, false, PersonalNonProfit, Required, false),
+            _ => return None,
+        };
+    Some(Terms {
+        allow_redistribution: Some(allow_redistribution),
+        allow_modification: Some(allow_modification),
+        commercial_usage: Some(commercial_usage),
+        credit_notation: Some(credit_notation),
+        share_alike: Some(share_alike),
+    })
+}
+
+/// URL of the legal text of a license given by its SPDX identifier, for the licenses whose
+/// terms [`known_terms`] knows and which have one
+fn known_url(license: &str) -> Option<&'static str> {
+    Some(match license {
+        "CC0-1.0" => "https://creativecommons.org/publicdomain/zero/1.0/",
+        "CC-BY-4.0" => "https://creativecommons.org/licenses/by/4.0/",
+        "CC-BY-SA-4.0" => "https://creativecommons.org/licenses/by-sa/4.0/",
+        "CC-BY-NC-4.0" => "https://creativecommons.org/licenses/by-nc/4.0/",
+        "CC-BY-NC-SA-4.0" => "https://creativecommons.org/licenses/by-nc-sa/4.0/",
+        "CC-BY-ND-4.0" => "https://creativecommons.org/licenses/by-nd/4.0/",
+        "CC-BY-NC-ND-4.0" => "https://creativecommons.org/licenses/by-nc-nd/4.0/",
+        _ => return None,
+    })
+}
+
+/// Whether `url` is the page of the license at `license_url` or one under it, e.g. its
+/// translations. The scheme, `www.` and the trailing slash don't matter.
+fn is_license_url(url: &str, license_url: &str) -> bool {
+    fn normalize(url: &str) -> &str {
+        let url = url.trim();
+        let url = url
+            .strip_prefix("https://")
+            .or_else(|| url.strip_prefix("http://"))
+            .unwrap_or(url);
+        let url = url.strip_prefix("www.").unwrap_or(url);
+        url.trim_end_matches('/')
+    }
+    let (url, license_url) = (normalize(url), normalize(license_url));
+    !license_url.is_empty()
+        && (url == license_url
+            || url
+                .strip_prefix(license_url)
+                .map_or(false, |rest| rest.starts_with('/')))
+}
+
+impl Provenance {
+    /// The terms of the license: the ones given in the provenance, and the others from [`known_terms`]
+    pub fn terms(&self) -> Terms {
+        let known = known_terms(&self.license).unwrap_or_default();
+        Terms {
+            allow_redistribution: self.allow_redistribution.or(known.allow_redistribution),
+            allow_modification: self.allow_modification.or(known.allow_modification),
+            commercial_usage: self.commercial_usage.or(known.commercial_usage),
+            credit_notation: self.credit_notation.or(known.credit_notation),
+            share_alike: self.share_alike.or(known.share_alike),
+        }
+    }
+}
+
+/// Path of the provenance of a file in a parts directory
+pub fn provenance_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".provenance.json");
+    PathBuf::from(path)
+}
+
+fn read(path: &Path) -> Result<Option<Provenance>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let provenance = serde_json::from_slice(&fs::read(path)?)
+        .with_context(|| format!("Invalid provenance in {}", path.to_string_lossy()))?;
+    Ok(Some(provenance))
+}
+
+/// A part or image of a parts directory
+#[derive(Clone, Debug)]
+pub struct Source {
+    /// Name of the file in the parts directory
+    pub file: String,
+    pub provenance: Option<Provenance>,
+    /// Whether the provenance is the default of the directory rather than the file's own
+    pub is_default: bool,
+}
+
+/// The meta of the avatar in input.json, and the parts and images `pack` would use with their provenance
+pub fn collect(parts_dir: &Path) -> Result<(Meta, Vec<Source>)> {
+    let json: Value = serde_json::from_slice(
+        &fs::read(parts_dir.join("input.json")).context("Failed to read input.json")?,
+    )?;
+    let meta = vrm::Vrm::from_json(&json)?
+        .context("input.json has no VRMC_vrm, so the license of the avatar is not known")?
+        .meta;
+    let mut root_json = json;
+    image::fill_texture_sources(&mut root_json);
+    let root: gltf_json::Root = serde_json::from_value(root_json)?;
+    let default = read(&parts_dir.join(DEFAULT_FILE_NAME))?;
+
+    let mut files = Vec::new();
+    for (m, mesh) in root.meshes.iter().enumerate() {
+        for p in 0..mesh.primitives.len() {
+            let path = parts_dir.join(part_file_name(mesh.name.as_deref(), m, p));
+            if path.exists() {
+                files.push(path);
+            }
+        }
+    }
+    for (i, image) in root.images.iter().enumerate() {
+        if let Some((_, path)) = find_image_file(parts_dir, i, image.name.as_deref())? {
+            files.push(path);
+        }
+    }
+    let mut sources = Vec::new();
+    for path in files {
+        let own = read(&provenance_path(&path))?;
+        sources.push(Source {
+            file: path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            is_default: own.is_none() && default.is_some(),
+            provenance: own.or_else(|| default.clone()),
+        });
+    }
+    Ok((meta, sources))
+}
+
+/// A license problem of a file
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub file: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.file, self.message)
+    }
+}
+
+/// The value of an enum as written in JSON, e.g. personalNonProfit
+fn json_name<T: Serialize>(value: T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+/// Check that the licenses of the files allow what the meta of the avatar allows
+pub fn check(meta: &Meta, sources: &[Source]) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = meta
+        .license_conflicts()
+        .into_iter()
+        .map(|message| Finding {
+            severity: Severity::Warning,
+            file: "input.json".to_string(),
+            message,
+        })
+        .collect();
+    for s in sources {
+        let mut push = |severity, message: String| {
+            findings.push(Finding {
+                severity,
+                file: s.file.clone(),
+                message,
+            })
+        };
+        let p = match &s.provenance {
+            Some(p) => p,
+            None => {
+                push(Severity::Warning, "has no provenance".to_string());
+                continue;
+            }
+        };
+        if p.author.is_empty() {
+            push(Severity::Warning, "the author is empty".to_string());
+        }
+        let terms = p.terms();
+        let unknown: Vec<&str> = [
+            ("allowRedistribution", terms.allow_redistribution.is_none()),
+            ("allowModification", terms.allow_modification.is_none()),
+            ("commercialUsage", terms.commercial_usage.is_none()),
+            ("creditNotation", terms.credit_notation.is_none()),
+            ("shareAlike", terms.share_alike.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(name, unknown)| unknown.then_some(name))
+        .collect();
+        if !unknown.is_empty() {
+            push(
+                Severity::Warning,
+                format!(
+                    "{} of {} are not known. Add them to the provenance",
+                    unknown.join(", "),
+                    p.license
+                ),
+            );
+        }
+        if meta.allow_redistribution && terms.allow_redistribution == Some(false) {
+            push(
+                Severity::Error,
+                format!(
+                    "{} doesn't allow redistribution but the avatar has allowRedistribution",
+                    p.license
+                ),
+            );
+        }
+        if meta.modification != Modification::Prohibited && terms.allow_modification == Some(false)
+        {
+            push(
+                Severity::Error,
+                format!(
+                    "{} doesn't allow modification but the avatar has modification {}",
+                    p.license,
+                    json_name(meta.modification)
+                ),
+            );
+        }
+        if let Some(usage) = terms
+            .commercial_usage
+            .filter(|u| meta.commercial_usage > *u)
+        {
+            push(
+                Severity::Error,
+                format!(
+                    "{} allows commercial usage up to {} but the avatar allows {}",
+                    p.license,
+                    json_name(usage),
+                    json_name(meta.commercial_usage)
+                ),
+            );
+        }
+        if meta.credit_notation == CreditNotation::Unnecessary
+            && terms.credit_notation == Some(CreditNotation::Required)
+        {
+            push(
+                Severity::Error,
+                format!(
+                    "{} requires credit but the avatar has creditNotation unnecessary",
+                    p.license
+                ),
+            );
+        }
+        if terms.share_alike == Some(true) {
+            let urls: Vec<&str> = p
+                .license_url
+                .iter()
+                .map(String::as_str)
+                .chain(known_url(&p.license))
+                .collect();
+            let same = std::iter::once(&meta.license_url)
+                .chain(&meta.other_license_url)
+                .any(|url| urls.iter().any(|u| is_license_url(url, u)));
+            if urls.is_empty() {
+                push(
+                    Severity::Warning,
+                    format!(
+                        "{} is share-alike but has no licenseUrl to compare the license of the avatar with",
+                        p.license
+                    ),
+                );
+            } else if !same {
+                push(
+                    Severity::Error,
+                    format!(
+                        "{} is share-alike but neither licenseUrl nor otherLicenseUrl of the avatar is {}",
+                        p.license, urls[0]
+                    ),
+                );
+            }
+        }
+    }
+    findings
+}
+
+/// Credits of the avatar and its files in Markdown. Files with the same provenance are listed together.
+pub fn credits(meta: &Meta, sources: &[Source]) -> String {
+    let mut groups: Vec<(Option<&Provenance>, Vec<&str>)> = Vec::new();
+    for s in sources {
+        let provenance = s.provenance.as_ref();
+        match groups.iter_mut().find(|(p, _)| *p == provenance) {
+            Some((_, files)) => files.push(&s.file),
+            None => groups.push((provenance, vec![&s.file])),
+        }
+    }
+    let mut text = format!("# {}\n\n", meta.name);
+    text += &format!("By {}", meta.authors.join(", "));
+    if let Some(c) = &meta.copyright_information {
+        text += &format!(" ({})", c);
+    }
+    text += &format!("\n\nLicense: {}\n", meta.license_url);
+    if let Some(url) = &meta.other_license_url {
+        text += &format!("Other license: {}\n", url);
+    }
+    text += "\n## Parts and textures\n\n";
+    for (p, files) in groups {
+        match p {
+            Some(p) => {
+                text += &format!("- {}, {}", p.author, p.license);
+                if let Some(url) = &p.license_url {
+                    text += &format!(" ({})", url);
+                }
+                text += "\n";
+                if let Some(url) = &p.source_url {
+                    text += &format!("  - Source: {}\n", url);
+                }
+            }
+            None => text += "- Unknown provenance\n",
+        }
+        text += &format!("  - Files: {}\n", files.join(", "));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provenance(license: &str) -> Provenance {
+        Provenance {
+            author: "someone".to_string(),
+            license: license.to_string(),
+            license_url: None,
+            source_url: None,
+            allow_redistribution: None,
+            allow_modification: None,
+            commercial_usage: None,
+            credit_notation: None,
+            share_alike: None,
+        }
+    }
+
+    /// An avatar that can be redistributed, modified and used for personal profit with credit
+    fn meta() -> Meta {
+        Meta {
+            name: "Avatar".to_string(),
+            authors: vec!["author".to_string()],
+            license_url: "https://vrm.dev/licenses/1.0/".to_string(),
+            allow_redistribution: true,
+            modification: Modification::AllowModification,
+            commercial_usage: CommercialUsage::PersonalProfit,
+            credit_notation: CreditNotation::Required,
+            ..Default::default()
+        }
+    }
+
+    fn findings(meta: &Meta, provenance: Option<Provenance>) -> Vec<(Severity, String)> {
+        let source = Source {
+            file: "Body0_0.glb".to_string(),
+            provenance,
+            is_default: false,
+        };
+        check(meta, &[source])
+            .into_iter()
+            .map(|f| (f.severity, f.message))
+            .collect()
+    }
+
+    #[test]
+    fn terms_of_known_licenses() {
+        assert_eq!(
+            known_terms("CC-BY-NC-SA-4.0"),
+            Some(Terms {
+                allow_redistribution: Some(true),
+                allow_modification: Some(true),
+                commercial_usage: Some(CommercialUsage::PersonalNonProfit),
+                credit_notation: Some(CreditNotation::Required),
+                share_alike: Some(true),
+            })
+        );
+        assert_eq!(known_terms("cc-by-4.0"), None);
+        // Terms given in the provenance come first
+        let p = Provenance {
+            credit_notation: Some(CreditNotation::Unnecessary),
+            ..provenance("CC-BY-4.0")
+        };
+        assert_eq!(p.terms().credit_notation, Some(CreditNotation::Unnecessary));
+        assert_eq!(p.terms().allow_modification, Some(true));
+        assert_eq!(provenance("LicenseRef-Custom").terms(), Terms::default());
+    }
+
+    #[test]
+    fn license_urls_are_compared_loosely() {
+        let by_sa = "https://creativecommons.org/licenses/by-sa/4.0/";
+        assert!(is_license_url(by_sa, by_sa));
+        assert!(is_license_url(
+            "http://www.creativecommons.org/licenses/by-sa/4.0",
+            by_sa
+        ));
+        assert!(is_license_url(
+            "https://creativecommons.org/licenses/by-sa/4.0/deed.ja",
+            by_sa
+        ));
+        assert!(!is_license_url(
+            "https://creativecommons.org/licenses/by-sa/4.0x",
+            by_sa
+        ));
+        assert!(!is_license_url(
+            "https://creativecommons.org/licenses/by/4.0/",
+            by_sa
+        ));
+        assert!(!is_license_url(by_sa, ""));
+    }
+
+    #[test]
+    fn compatible_license_has_no_finding() {
+        assert_eq!(findings(&meta(), Some(provenance("CC-BY-4.0"))), vec![]);
+        assert_eq!(
+            findings(&meta(), None),
+            vec![(Severity::Warning, "has no provenance".to_string())]
+        );
+    }
+
+    #[test]
+    fn unknown_license_is_warned() {
+        assert_eq!(
+            findings(&meta(), Some(provenance("LicenseRef-Custom"))),
+            vec![(
+                Severity::Warning,
+                "allowRedistribution, allowModification, commercialUsage, creditNotation, \
+                 shareAlike of LicenseRef-Custom are not known. Add them to the provenance"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn no_redistribution_part_in_a_redistributable_avatar() {
+        let p = Provenance {
+            allow_redistribution: Some(false),
+            allow_modification: Some(true),
+            commercial_usage: Some(CommercialUsage::Corporation),
+            credit_notation: Some(CreditNotation::Unnecessary),
+            share_alike: Some(false),
+            ..provenance("LicenseRef-Shop")
+        };
+        assert_eq!(
+            findings(&meta(), Some(p.clone())),
+            vec![(
+                Severity::Error,
+                "LicenseRef-Shop doesn't allow redistribution but the avatar has allowRedistribution"
+                    .to_string()
+            )]
+        );
+        let private = Meta {
+            allow_redistribution: false,
+            ..meta()
+        };
+        assert_eq!(findings(&private, Some(p)), vec![]);
+    }
+
+    #[test]
+    fn modification_commercial_usage_and_credit() {
+        assert_eq!(
+            findings(&meta(), Some(provenance("CC-BY-ND-4.0"))),
+            vec![(
+                Severity::Error,
+                "CC-BY-ND-4.0 doesn't allow modification but the avatar has modification allowModification"
+                    .to_string()
+            )]
+        );
+        let unmodifiable = Meta {
+            modification: Modification::Prohibited,
+            ..meta()
+        };
+        assert_eq!(
+            findings(&unmodifiable, Some(provenance("CC-BY-ND-4.0"))),
+            vec![]
+        );
+
+        // personalNonProfit < personalProfit < corporation
+        assert_eq!(
+            findings(&meta(), Some(provenance("CC-BY-NC-4.0"))),
+            vec![(
+                Severity::Error,
+                "CC-BY-NC-4.0 allows commercial usage up to personalNonProfit but the avatar allows personalProfit"
+                    .to_string()
+            )]
+        );
+        let non_profit = Meta {
+            commercial_usage: CommercialUsage::PersonalNonProfit,
+            ..meta()
+        };
+        assert_eq!(
+            findings(&non_profit, Some(provenance("CC-BY-NC-4.0"))),
+            vec![]
+        );
+        let corporation = Meta {
+            commercial_usage: CommercialUsage::Corporation,
+            ..meta()
+        };
+        assert_eq!(findings(&corporation, Some(provenance("MIT"))), vec![]);
+
+        let no_credit = Meta {
+            credit_notation: CreditNotation::Unnecessary,
+            ..meta()
+        };
+        assert_eq!(
+            findings(&no_credit, Some(provenance("CC-BY-4.0"))),
+            vec![(
+                Severity::Error,
+                "CC-BY-4.0 requires credit but the avatar has creditNotation unnecessary"
+                    .to_string()
+            )]
+        );
+        assert_eq!(findings(&no_credit, Some(provenance("CC0-1.0"))), vec![]);
+    }
+
+    #[test]
+    fn share_alike_needs_the_same_license() {
+        assert_eq!(
+            findings(&meta(), Some(provenance("CC-BY-SA-4.0"))),
+            vec![(
+                Severity::Error,
+                "CC-BY-SA-4.0 is share-alike but neither licenseUrl nor otherLicenseUrl of the \
+                 avatar is https://creativecommons.org/licenses/by-sa/4.0/"
+                    .to_string()
+            )]
+        );
+        let same = Meta {
+            other_license_url: Some(
+                "http://creativecommons.org/licenses/by-sa/4.0/legalcode".to_string(),
+            ),
+            ..meta()
+        };
+        assert_eq!(findings(&same, Some(provenance("CC-BY-SA-4.0"))), vec![]);
+
+        let custom = Provenance {
+            allow_redistribution: Some(true),
+            allow_modification: Some(true),
+            commercial_usage: Some(CommercialUsage::Corporation),
+            credit_notation: Some(CreditNotation::Required),
+            share_alike: Some(true),
+            ..provenance("LicenseRef-Custom")
+        };
+        assert_eq!(
+            findings(&meta(), Some(custom.clone())),
+            vec![(
+                Severity::Warning,
+                "LicenseRef-Custom is share-alike but has no licenseUrl to compare the license of the \
+                 avatar with"
+                    .to_string()
+            )]
+        );
+        let custom = Provenance {
+            license_url: Some("https://vrm.dev/licenses/1.0".to_string()),
+            ..custom
+        };
+        assert_eq!(findings(&meta(), Some(custom)), vec![]);
+    }
+}
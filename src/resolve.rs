@@ -0,0 +1,97 @@
+//! Lookup of the objects of a document given on the command line or in a source file,
+//! either by their name or by their index.
+
+use crate::morph;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+/// Find an object by its name or index in a list of `len` objects
+pub fn by_name_or_index<'a>(
+    kind: &str,
+    spec: &str,
+    len: usize,
+    names: impl Iterator<Item = Option<&'a str>>,
+) -> Result<u32> {
+    if let Ok(i) = spec.parse::<usize>() {
+        if i < len {
+            return Ok(i as u32);
+        }
+        bail!("{} #{} does not exist", kind, i);
+    }
+    let mut found = names
+        .enumerate()
+        .filter(|(_, name)| *name == Some(spec))
+        .map(|(i, _)| i as u32);
+    match (found.next(), found.next()) {
+        (Some(i), None) => Ok(i),
+        (Some(_), Some(_)) => bail!("There are multiple {}s named {}", kind, spec),
+        (None, _) => bail!("No {} is named {}", kind, spec),
+    }
+}
+
+/// Find a node by its name or index
+pub fn node(root: &gltf_json::Root, spec: &str) -> Result<u32> {
+    by_name_or_index(
+        "node",
+        spec,
+        root.nodes.len(),
+        root.nodes.iter().map(|n| n.name.as_deref()),
+    )
+}
+
+/// Find a material by its name or index
+pub fn material(root: &gltf_json::Root, spec: &str) -> Result<u32> {
+    by_name_or_index(
+        "material",
+        spec,
+        root.materials.len(),
+        root.materials.iter().map(|m| m.name.as_deref()),
+    )
+}
+
+/// Find an image by its name or index
+pub fn image(root: &gltf_json::Root, spec: &str) -> Result<u32> {
+    by_name_or_index(
+        "image",
+        spec,
+        root.images.len(),
+        root.images.iter().map(|i| i.name.as_deref()),
+    )
+}
+
+/// Find an animation by its name or index
+pub fn animation(root: &gltf_json::Root, spec: &str) -> Result<u32> {
+    by_name_or_index(
+        "animation",
+        spec,
+        root.animations.len(),
+        root.animations.iter().map(|a| a.name.as_deref()),
+    )
+}
+
+/// Find a morph target given as `<node>:<target>` and return (node, target index)
+pub fn morph_target(root: &gltf_json::Root, spec: &str) -> Result<(u32, u32)> {
+    let (node, target) = spec
+        .split_once(':')
+        .with_context(|| format!("{} should be <node>:<morph target>", spec))?;
+    let node = self::node(root, node)?;
+    let mesh = root.nodes[node as usize]
+        .mesh
+        .and_then(|m| root.meshes.get(m.value()))
+        .with_context(|| format!("node #{} has no mesh", node))?;
+    let count = mesh
+        .primitives
+        .first()
+        .and_then(|p| p.targets.as_ref())
+        .map(|t| t.len())
+        .unwrap_or(0);
+    let names = morph::target_names(mesh, 0);
+    let target = by_name_or_index(
+        "morph target",
+        target,
+        count,
+        (0..count).map(|i| names.get(i).map(|s| s.as_str())),
+    )?;
+    Ok((node, target))
+}
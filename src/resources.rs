@@ -0,0 +1,251 @@
+//! Buffers and images outside the BIN chunk: external files next to a .gltf and `data:` URIs.
+//! They are embedded into the BIN chunk when a document is loaded, so the rest of the crate
+//! only deals with a single buffer.
+
+use crate::glb::append_bytes;
+use crate::image::ImageFormat;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::json;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+fn decode_base64(data: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in data.bytes().filter(|c| *c != b'=') {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => bail!("{:?} is not a base64 character", c as char),
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+/// Decode %XX escapes of a relative URI into a path
+fn decode_percent(uri: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(uri.len());
+    let mut it = uri.bytes();
+    while let Some(c) = it.next() {
+        if c == b'%' {
+            let hex = [it.next().unwrap_or(0), it.next().unwrap_or(0)];
+            let hex = std::str::from_utf8(&hex).unwrap_or_default();
+            bytes
+                .push(u8::from_str_radix(hex, 16).with_context(|| format!("Invalid URI {}", uri))?);
+        } else {
+            bytes.push(c);
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Data of a URI and the media type given in it if it is a `data:` URI.
+/// Relative URIs are resolved against `base_dir`, the directory of the .gltf file.
+pub fn read_uri(uri: &str, base_dir: Option<&Path>) -> Result<(Vec<u8>, Option<String>)> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (media_type, data) = data
+            .split_once(";base64,")
+            .context("Only base64 data URIs are supported")?;
+        let media_type = Some(media_type.to_string()).filter(|m| !m.is_empty());
+        return Ok((decode_base64(data)?, media_type));
+    }
+    if uri.contains(':') {
+        bail!("The scheme of {} is not supported", uri);
+    }
+    let base_dir = match base_dir {
+        Some(dir) => dir,
+        None => bail!("{} is an external file, but the document has no path", uri),
+    };
+    let path = base_dir.join(decode_percent(uri)?);
+    let data =
+        fs::read(&path).with_context(|| format!("Failed to read {}", path.to_string_lossy()))?;
+    Ok((data, None))
+}
+
+/// Move all the buffers (the BIN chunk `bin` and the ones given by URIs) and the images given by URIs
+/// into a single buffer, and return its data. The bufferViews are rewritten to point into it.
+/// A GLB that has nothing outside of its BIN chunk is returned as is.
+pub fn embed(json: &mut Value, bin: Option<Vec<u8>>, base_dir: Option<&Path>) -> Result<Vec<u8>> {
+    let has_uri = |key: &str| {
+        json[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .any(|o| o.get("uri").is_some())
+    };
+    if !has_uri("buffers") && !has_uri("images") {
+        return Ok(bin.unwrap_or_default());
+    }
+    let mut bin = bin;
+    let mut data = Vec::new();
+    let mut offsets = Vec::new();
+    for (i, buffer) in json["buffers"].as_array().into_iter().flatten().enumerate() {
+        let buffer_data = match buffer["uri"].as_str() {
+            Some(uri) => {
+                read_uri(uri, base_dir)
+                    .with_context(|| format!("Failed to load buffer #{}", i))?
+                    .0
+            }
+            None => bin
+                .take()
+                .with_context(|| format!("buffer #{} has no uri, but there is no BIN chunk", i))?,
+        };
+        let length = buffer["byteLength"].as_u64().unwrap_or(0) as usize;
+        if buffer_data.len() < length {
+            bail!(
+                "buffer #{} has {} bytes, less than its byteLength {}",
+                i,
+                buffer_data.len(),
+                length
+            );
+        }
+        offsets.push(data.len() as u64);
+        append_bytes(&mut data, &buffer_data[..length]);
+    }
+    let views = json.get_mut("bufferViews").and_then(|v| v.as_array_mut());
+    for (i, view) in views.into_iter().flatten().enumerate() {
+        let offset = view["buffer"]
+            .as_u64()
+            .and_then(|b| offsets.get(b as usize))
+            .with_context(|| format!("buffer of bufferView #{} does not exist", i))?;
+        view["byteOffset"] = json!(offset + view["byteOffset"].as_u64().unwrap_or(0));
+        view["buffer"] = json!(0);
+    }
+    let image_count = json["images"].as_array().map_or(0, |a| a.len());
+    for i in 0..image_count {
+        let uri = match json["images"][i]["uri"].as_str() {
+            Some(uri) => uri.to_string(),
+            None => continue,
+        };
+        let (image_data, media_type) =
+            read_uri(&uri, base_dir).with_context(|| format!("Failed to load image #{}", i))?;
+        let format = json["images"][i]["mimeType"]
+            .as_str()
+            .or(media_type.as_deref())
+            .and_then(ImageFormat::from_mime_type)
+            .or_else(|| ImageFormat::from_path(Path::new(&uri)))
+            .or_else(|| ImageFormat::detect(&image_data))
+            .with_context(|| format!("The format of image #{} is unknown", i))?;
+        let (offset, length) = append_bytes(&mut data, &image_data);
+        if !json["bufferViews"].is_array() {
+            json["bufferViews"] = json!([]);
+        }
+        let views = json["bufferViews"].as_array_mut().unwrap();
+        views.push(json!({ "buffer": 0, "byteOffset": offset, "byteLength": length }));
+        let view = views.len() - 1;
+        let image = json["images"][i].as_object_mut().unwrap();
+        image.remove("uri");
+        image.insert("bufferView".to_string(), json!(view));
+        image.insert("mimeType".to_string(), json!(format.mime_type()));
+    }
+    json["buffers"] = json!([{ "byteLength": data.len() }]);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accessor;
+    use crate::validate;
+    use crate::Document;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn data_uris_are_decoded() {
+        assert_eq!(
+            read_uri("data:image/png;base64,iVBORw0KGgo=", None).unwrap(),
+            (PNG.to_vec(), Some("image/png".to_string()))
+        );
+        assert_eq!(
+            read_uri("data:;base64,AAABAAIAAAA", None).unwrap(),
+            (vec![0, 0, 1, 0, 2, 0, 0, 0], None)
+        );
+        assert!(read_uri("data:text/plain,hello", None).is_err());
+        assert!(read_uri("https://example.com/a.bin", None).is_err());
+        assert!(read_uri("a.bin", None).is_err());
+    }
+
+    #[test]
+    fn gltf_with_external_files_and_data_uris_is_embedded() {
+        let dir = std::env::temp_dir().join(format!("vacation-gltf-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let positions = [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        let bin: Vec<u8> = positions
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        fs::write(dir.join("a b.bin"), &bin).unwrap();
+        fs::write(dir.join("face.png"), PNG).unwrap();
+        let json = json!({
+            "asset": { "version": "2.0" },
+            "buffers": [
+                { "uri": "a%20b.bin", "byteLength": 36 },
+                { "uri": "data:application/octet-stream;base64,AAABAAIAAAA=", "byteLength": 8 },
+            ],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 36, "target": 34962 },
+                { "buffer": 1, "byteLength": 6, "target": 34963 },
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+                { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" },
+            ],
+            "images": [
+                { "uri": "face.png" },
+                { "uri": "data:image/png;base64,iVBORw0KGgo=" },
+            ],
+            "textures": [{ "source": 0 }, { "source": 1 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1 }] }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }],
+        });
+        let path = dir.join("triangle.gltf");
+        fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+        let doc = Document::load(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            doc.json["buffers"],
+            json!([{ "byteLength": doc.bin.len() }])
+        );
+        let root = doc.root().unwrap();
+        assert_eq!(
+            accessor::read_f32::<3>(&root, doc.buffers(), 0).unwrap(),
+            positions
+        );
+        assert_eq!(
+            accessor::read_indices(&root, doc.buffers(), 1).unwrap(),
+            vec![0, 1, 2]
+        );
+        for image in doc.json["images"].as_array().unwrap() {
+            assert!(image.get("uri").is_none());
+            assert_eq!(image["mimeType"], "image/png");
+            let view = &doc.json["bufferViews"][image["bufferView"].as_u64().unwrap() as usize];
+            let ofs = view["byteOffset"].as_u64().unwrap() as usize;
+            assert_eq!(&doc.bin[ofs..ofs + PNG.len()], PNG);
+        }
+        let errors: Vec<_> = validate::validate_gltf(&doc)
+            .into_iter()
+            .filter(|i| i.severity == validate::Severity::Error)
+            .collect();
+        assert!(errors.is_empty(), "{:?}", errors);
+    }
+}
@@ -0,0 +1,760 @@
+//! Building a glTF scene with any number of nodes, meshes, materials and textures in one BIN chunk.
+//! Objects are added one by one and refer to each other by the indices returned when they are added.
+
+use crate::extensions;
+use crate::glb::append_bytes;
+use crate::glb::bounding_coords2d;
+use crate::glb::bounding_coords3d;
+use crate::image::ImageFormat;
+use crate::morph::MorphTarget;
+use crate::mtoon::MToon;
+use crate::skin::normalize_weights;
+use crate::skin::Skeleton;
+use crate::Document;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::accessor::ComponentType;
+use gltf_json::accessor::GenericComponentType;
+use gltf_json::accessor::Type;
+use gltf_json::extensions::material::Unlit;
+use gltf_json::extensions::texture::TextureTransform;
+use gltf_json::extras::RawValue;
+use gltf_json::image::MimeType;
+use gltf_json::material::AlphaCutoff;
+use gltf_json::material::AlphaMode;
+use gltf_json::material::EmissiveFactor;
+use gltf_json::material::PbrBaseColorFactor;
+use gltf_json::material::StrengthFactor;
+use gltf_json::scene::UnitQuaternion;
+use gltf_json::validation::Checked::Valid;
+use gltf_json::Index;
+use std::collections::HashMap;
+
+/// A material of the scene. The textures are mapped with TEXCOORD_0.
+/// The default is white, opaque, not metallic and mostly rough.
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    /// Index returned by `SceneBuilder::add_texture`, as are the other textures
+    pub base_color_texture: Option<u32>,
+    /// Written as KHR_texture_transform on the base color texture
+    pub texture_transform: Option<TextureTransform>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Metalness in the blue channel and roughness in the green channel
+    pub metallic_roughness_texture: Option<u32>,
+    pub normal_texture: Option<u32>,
+    pub normal_scale: f32,
+    /// Occlusion in the red channel
+    pub occlusion_texture: Option<u32>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<u32>,
+    pub alpha_mode: AlphaMode,
+    /// Only written with `AlphaMode::Mask`
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+    /// Written as KHR_materials_unlit
+    pub unlit: bool,
+    /// Written as VRMC_materials_mtoon. Its textures are indices returned by `SceneBuilder::add_texture`.
+    pub mtoon: Option<MToon>,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            name: None,
+            base_color_factor: [1.0, 1.0, 1.0, 1.0],
+            base_color_texture: None,
+            texture_transform: None,
+            metallic_factor: 0.0,
+            roughness_factor: 0.9,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_factor: [0.0, 0.0, 0.0],
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+            unlit: false,
+            mtoon: None,
+        }
+    }
+}
+
+/// Triangles of a mesh. All the per-vertex attributes have the same length as `vertices`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Primitive<'a> {
+    pub vertices: &'a [[f32; 3]],
+    pub normals: &'a [[f32; 3]],
+    pub tex_coords: Option<&'a [[f32; 2]]>,
+    pub indices: &'a [[u32; 3]],
+    /// Index returned by `SceneBuilder::add_material`
+    pub material: Option<u32>,
+    /// JOINTS_0, indices into the joints of the skin of the nodes using the mesh
+    pub joints: Option<&'a [[u32; 4]]>,
+    /// WEIGHTS_0, normalized when written
+    pub weights: Option<&'a [[f32; 4]]>,
+    pub targets: &'a [MorphTarget],
+}
+
+/// A node of the scene, placed under its parent given to `SceneBuilder::add_node`
+#[derive(Clone, Debug, Default)]
+pub struct Node {
+    pub name: Option<String>,
+    pub translation: Option<[f32; 3]>,
+    /// Quaternion in (x, y, z, w) order
+    pub rotation: Option<[f32; 4]>,
+    pub scale: Option<[f32; 3]>,
+    /// Index returned by `SceneBuilder::add_mesh`
+    pub mesh: Option<u32>,
+    /// Index returned by `SceneBuilder::add_skin`
+    pub skin: Option<u32>,
+}
+
+/// Collects the objects of a scene and their data, and assigns their indices
+#[derive(Default)]
+pub struct SceneBuilder {
+    root: gltf_json::Root,
+    bin: Vec<u8>,
+    scene_nodes: Vec<Index<gltf_json::Node>>,
+    /// Extensions unknown to gltf_json, written into the JSON by `build`
+    mtoons: Vec<(usize, MToon)>,
+    texture_extensions: Vec<(usize, &'static str)>,
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn push_view<T>(&mut self, data: &[T]) -> Index<gltf_json::buffer::View> {
+        let (ofs, len) = append_bytes(&mut self.bin, data);
+        let idx = Index::new(self.root.buffer_views.len() as u32);
+        self.root.buffer_views.push(gltf_json::buffer::View {
+            buffer: Index::new(0),
+            byte_length: len,
+            byte_offset: Some(ofs),
+            byte_stride: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            target: None,
+        });
+        idx
+    }
+    fn push_accessor<T>(
+        &mut self,
+        data: &[T],
+        count: usize,
+        component_type: ComponentType,
+        type_: Type,
+        min_max: Option<(Vec<f32>, Vec<f32>)>,
+    ) -> Index<gltf_json::Accessor> {
+        let view = self.push_view(data);
+        let (min, max) = match min_max {
+            Some((min, max)) => (Some(min.into()), Some(max.into())),
+            None => (None, None),
+        };
+        let idx = Index::new(self.root.accessors.len() as u32);
+        self.root.accessors.push(gltf_json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: 0,
+            count: count as u32,
+            component_type: Valid(GenericComponentType(component_type)),
+            extensions: Default::default(),
+            extras: Default::default(),
+            type_: Valid(type_),
+            min,
+            max,
+            name: None,
+            normalized: false,
+            sparse: None,
+        });
+        idx
+    }
+    fn push_vec3(&mut self, data: &[[f32; 3]], with_bounds: bool) -> Index<gltf_json::Accessor> {
+        let bounds = with_bounds.then(|| {
+            let (min, max) = bounding_coords3d(data);
+            (Vec::from(min), Vec::from(max))
+        });
+        self.push_accessor(data, data.len(), ComponentType::F32, Type::Vec3, bounds)
+    }
+    /// Add an image and a texture sampling it with a linear and repeating sampler.
+    /// WebP and KTX2 images are sampled through their texture extension, which is required then.
+    /// Returns the index of the texture.
+    pub fn add_texture(&mut self, name: Option<&str>, format: ImageFormat, data: &[u8]) -> u32 {
+        if self.root.samplers.is_empty() {
+            self.root.samplers.push(gltf_json::texture::Sampler {
+                mag_filter: Some(Valid(gltf::texture::MagFilter::Linear)),
+                min_filter: Some(Valid(gltf::texture::MinFilter::Linear)),
+                wrap_s: Valid(gltf::texture::WrappingMode::Repeat),
+                wrap_t: Valid(gltf::texture::WrappingMode::Repeat),
+                name: None,
+                extensions: None,
+                extras: Default::default(),
+            });
+        }
+        let view = self.push_view(data);
+        let image_idx = Index::new(self.root.images.len() as u32);
+        self.root.images.push(gltf_json::image::Image {
+            name: name.map(|s| s.to_string()),
+            buffer_view: Some(view),
+            mime_type: Some(MimeType(format.mime_type().to_string())),
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+        self.root.textures.push(gltf_json::texture::Texture {
+            name: name.map(|s| s.to_string()),
+            sampler: Some(Index::new(0)),
+            source: image_idx,
+            extensions: None,
+            extras: Default::default(),
+        });
+        if let Some(extension) = format.texture_extension() {
+            self.texture_extensions
+                .push((self.root.textures.len() - 1, extension));
+        }
+        self.root.textures.len() as u32 - 1
+    }
+    fn use_extension(&mut self, name: &str) {
+        if !self.root.extensions_used.iter().any(|e| e == name) {
+            self.root.extensions_used.push(name.to_string());
+        }
+    }
+    fn texture_index(&self, texture: u32) -> Result<Index<gltf_json::Texture>> {
+        if texture as usize >= self.root.textures.len() {
+            bail!("texture #{} does not exist", texture)
+        }
+        Ok(Index::new(texture))
+    }
+    fn texture_info(&self, texture: Option<u32>) -> Result<Option<gltf_json::texture::Info>> {
+        Ok(match texture {
+            Some(t) => Some(gltf_json::texture::Info {
+                index: self.texture_index(t)?,
+                tex_coord: 0,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            None => None,
+        })
+    }
+    pub fn add_material(&mut self, material: &Material) -> Result<u32> {
+        let mut base_color_texture = self.texture_info(material.base_color_texture)?;
+        if let (Some(info), Some(t)) = (&mut base_color_texture, &material.texture_transform) {
+            info.extensions = Some(gltf_json::extensions::texture::Info {
+                // texCoord of the extension is written as null if omitted
+                texture_transform: Some(TextureTransform {
+                    tex_coord: Some(0),
+                    ..t.clone()
+                }),
+            });
+            self.use_extension("KHR_texture_transform");
+        }
+        let normal_texture = match material.normal_texture {
+            Some(t) => Some(gltf_json::material::NormalTexture {
+                index: self.texture_index(t)?,
+                scale: material.normal_scale,
+                tex_coord: 0,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            None => None,
+        };
+        let occlusion_texture = match material.occlusion_texture {
+            Some(t) => Some(gltf_json::material::OcclusionTexture {
+                index: self.texture_index(t)?,
+                strength: StrengthFactor(material.occlusion_strength),
+                tex_coord: 0,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            None => None,
+        };
+        let extensions = if material.unlit {
+            self.use_extension("KHR_materials_unlit");
+            Some(gltf_json::extensions::material::Material {
+                unlit: Some(Unlit {}),
+            })
+        } else {
+            None
+        };
+        if let Some(mtoon) = &material.mtoon {
+            self.mtoons.push((self.root.materials.len(), mtoon.clone()));
+        }
+        self.root.materials.push(gltf_json::material::Material {
+            name: material.name.clone(),
+            alpha_cutoff: match material.alpha_mode {
+                AlphaMode::Mask => Some(AlphaCutoff(material.alpha_cutoff)),
+                _ => None,
+            },
+            alpha_mode: Valid(material.alpha_mode),
+            double_sided: material.double_sided,
+            pbr_metallic_roughness: gltf_json::material::PbrMetallicRoughness {
+                base_color_factor: PbrBaseColorFactor(material.base_color_factor),
+                base_color_texture,
+                metallic_factor: StrengthFactor(material.metallic_factor),
+                roughness_factor: StrengthFactor(material.roughness_factor),
+                metallic_roughness_texture: self
+                    .texture_info(material.metallic_roughness_texture)?,
+                extensions: None,
+                extras: Default::default(),
+            },
+            normal_texture,
+            occlusion_texture,
+            emissive_texture: self.texture_info(material.emissive_texture)?,
+            emissive_factor: EmissiveFactor(material.emissive_factor),
+            extensions,
+            extras: Default::default(),
+        });
+        Ok(self.root.materials.len() as u32 - 1)
+    }
+    fn push_primitive(&mut self, p: &Primitive) -> Result<gltf_json::mesh::Primitive> {
+        let vertex_count = p.vertices.len();
+        if p.normals.len() != vertex_count
+            || p.tex_coords.map(|t| t.len() != vertex_count) == Some(true)
+            || p.joints.map(|j| j.len() != vertex_count) == Some(true)
+            || p.weights.map(|w| w.len() != vertex_count) == Some(true)
+        {
+            bail!("The numbers of positions, normals, texCoords, joints and weights are different");
+        }
+        if let Some(i) = p
+            .indices
+            .flatten()
+            .iter()
+            .find(|i| **i as usize >= vertex_count)
+        {
+            bail!("Index {} is out of the {} vertices", i, vertex_count);
+        }
+        if let Some(m) = p
+            .material
+            .filter(|m| *m as usize >= self.root.materials.len())
+        {
+            bail!("material #{} does not exist", m);
+        }
+        let mut attributes = HashMap::new();
+        attributes.insert(
+            Valid(gltf_json::mesh::Semantic::Positions),
+            self.push_vec3(p.vertices, true),
+        );
+        attributes.insert(
+            Valid(gltf_json::mesh::Semantic::Normals),
+            self.push_vec3(p.normals, false),
+        );
+        if let Some(tex_coords) = p.tex_coords {
+            let (min, max) = bounding_coords2d(tex_coords);
+            attributes.insert(
+                Valid(gltf_json::mesh::Semantic::TexCoords(0)),
+                self.push_accessor(
+                    tex_coords,
+                    vertex_count,
+                    ComponentType::F32,
+                    Type::Vec2,
+                    Some((Vec::from(min), Vec::from(max))),
+                ),
+            );
+        }
+        let indices = p.indices.flatten();
+        let indices = self.push_accessor(
+            indices,
+            indices.len(),
+            ComponentType::U32,
+            Type::Scalar,
+            None,
+        );
+        match (p.joints, p.weights) {
+            (Some(joints), Some(weights)) => {
+                // JOINTS_0 has to be an unsigned byte or short, and is not normalized
+                let max_joint = joints.iter().flatten().max().copied().unwrap_or(0);
+                let accessor = if max_joint < 1 << 8 {
+                    let joints: Vec<[u8; 4]> = joints.iter().map(|j| j.map(|j| j as u8)).collect();
+                    self.push_accessor(&joints, vertex_count, ComponentType::U8, Type::Vec4, None)
+                } else if max_joint < 1 << 16 {
+                    let joints: Vec<[u16; 4]> =
+                        joints.iter().map(|j| j.map(|j| j as u16)).collect();
+                    self.push_accessor(&joints, vertex_count, ComponentType::U16, Type::Vec4, None)
+                } else {
+                    bail!("Joint {} can't be written as JOINTS_0", max_joint);
+                };
+                attributes.insert(Valid(gltf_json::mesh::Semantic::Joints(0)), accessor);
+                let weights = normalize_weights(weights);
+                attributes.insert(
+                    Valid(gltf_json::mesh::Semantic::Weights(0)),
+                    self.push_accessor(
+                        &weights,
+                        vertex_count,
+                        ComponentType::F32,
+                        Type::Vec4,
+                        None,
+                    ),
+                );
+            }
+            (None, None) => {}
+            _ => bail!("Joints and weights have to be given together"),
+        }
+        let mut targets = Vec::new();
+        for (i, t) in p.targets.iter().enumerate() {
+            let mut write = |data: &Option<Vec<[f32; 3]>>, with_bounds: bool| {
+                let data = match data {
+                    Some(data) => data,
+                    None => return Ok(None),
+                };
+                if data.len() != vertex_count {
+                    bail!(
+                        "Morph target #{} has {} elements for {} vertices",
+                        i,
+                        data.len(),
+                        vertex_count
+                    );
+                }
+                // POSITION of a morph target requires the bounds
+                Ok(Some(self.push_vec3(data, with_bounds)))
+            };
+            targets.push(gltf_json::mesh::MorphTarget {
+                positions: write(&t.positions, true)?,
+                normals: write(&t.normals, false)?,
+                tangents: write(&t.tangents, false)?,
+            });
+        }
+        Ok(gltf_json::mesh::Primitive {
+            attributes,
+            extensions: Default::default(),
+            extras: Default::default(),
+            indices: Some(indices),
+            material: p.material.map(Index::new),
+            mode: Valid(gltf_json::mesh::Mode::Triangles),
+            targets: if targets.is_empty() {
+                None
+            } else {
+                Some(targets)
+            },
+        })
+    }
+    /// Add a mesh made of `primitives`. Morph targets are written with their names
+    /// in `extras.targetNames` of the mesh, so all the primitives need the same number of them.
+    pub fn add_mesh(&mut self, name: Option<&str>, primitives: &[Primitive]) -> Result<u32> {
+        let target_count = primitives.first().map(|p| p.targets.len()).unwrap_or(0);
+        if primitives.iter().any(|p| p.targets.len() != target_count) {
+            bail!("The primitives have different numbers of morph targets");
+        }
+        let mut written = Vec::new();
+        for (i, p) in primitives.iter().enumerate() {
+            written.push(
+                self.push_primitive(p)
+                    .map_err(|e| e.context(format!("Primitive #{}", i)))?,
+            );
+        }
+        let targets = primitives.first().map(|p| p.targets).unwrap_or_default();
+        let target_names = if targets.iter().any(|t| t.name.is_some()) {
+            let names: Vec<_> = targets
+                .iter()
+                .enumerate()
+                .map(|(i, t)| t.name.clone().unwrap_or_else(|| format!("target{}", i)))
+                .collect();
+            Some(RawValue::from_string(serde_json::to_string(
+                &serde_json::json!({ "targetNames": names }),
+            )?)?)
+        } else {
+            None
+        };
+        self.root.meshes.push(gltf_json::Mesh {
+            extensions: Default::default(),
+            extras: target_names,
+            name: name.map(|s| s.to_string()),
+            primitives: written,
+            weights: if target_count == 0 {
+                None
+            } else {
+                Some(vec![0.0; target_count])
+            },
+        });
+        Ok(self.root.meshes.len() as u32 - 1)
+    }
+    /// Add a node as a child of `parent`, or as a root node of the scene if `parent` is None
+    pub fn add_node(&mut self, node: &Node, parent: Option<u32>) -> Result<u32> {
+        if let Some(m) = node.mesh.filter(|m| *m as usize >= self.root.meshes.len()) {
+            bail!("mesh #{} does not exist", m);
+        }
+        if let Some(s) = node.skin.filter(|s| *s as usize >= self.root.skins.len()) {
+            bail!("skin #{} does not exist", s);
+        }
+        let idx = Index::new(self.root.nodes.len() as u32);
+        match parent {
+            Some(p) => self
+                .root
+                .nodes
+                .get_mut(p as usize)
+                .with_context(|| format!("node #{} does not exist", p))?
+                .children
+                .get_or_insert_with(Vec::new)
+                .push(idx),
+            None => self.scene_nodes.push(idx),
+        }
+        self.root.nodes.push(gltf_json::Node {
+            camera: None,
+            children: None,
+            extensions: Default::default(),
+            extras: Default::default(),
+            matrix: None,
+            mesh: node.mesh.map(Index::new),
+            name: node.name.clone(),
+            rotation: node.rotation.map(UnitQuaternion),
+            scale: node.scale,
+            translation: node.translation,
+            skin: node.skin.map(Index::new),
+            weights: None,
+        });
+        Ok(idx.value() as u32)
+    }
+    /// Add the bones of `skeleton` as nodes and a skin over its joints.
+    /// The root bones are placed under `parent`, or at the root of the scene if it is None.
+    /// Returns the index of the skin.
+    pub fn add_skin(&mut self, skeleton: &Skeleton, parent: Option<u32>) -> Result<u32> {
+        if skeleton.inverse_bind_matrices.len() != skeleton.joints.len() {
+            bail!(
+                "The skeleton has {} inverse bind matrices for {} joints",
+                skeleton.inverse_bind_matrices.len(),
+                skeleton.joints.len()
+            );
+        }
+        if let Some(p) = parent.filter(|p| *p as usize >= self.root.nodes.len()) {
+            bail!("node #{} does not exist", p);
+        }
+        let bone_count = skeleton.bones.len();
+        if let Some(b) = skeleton
+            .bones
+            .iter()
+            .find(|b| b.parent.map(|p| p >= bone_count) == Some(true))
+        {
+            bail!("The parent of bone {:?} does not exist", b.name);
+        }
+        if let Some(j) = skeleton.joints.iter().find(|j| **j >= bone_count) {
+            bail!("Joint bone #{} does not exist", j);
+        }
+        // Bones are placed in the order of the skeleton
+        let base = self.root.nodes.len() as u32;
+        let bone_node = |bone: usize| Index::new(base + bone as u32);
+        for (i, bone) in skeleton.bones.iter().enumerate() {
+            let children: Vec<_> = skeleton
+                .bones
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.parent == Some(i))
+                .map(|(c, _)| bone_node(c))
+                .collect();
+            self.root.nodes.push(gltf_json::Node {
+                camera: None,
+                children: if children.is_empty() {
+                    None
+                } else {
+                    Some(children)
+                },
+                extensions: Default::default(),
+                extras: Default::default(),
+                matrix: bone.matrix,
+                mesh: None,
+                name: bone.name.clone(),
+                rotation: bone.rotation.map(UnitQuaternion),
+                scale: bone.scale,
+                translation: bone.translation,
+                skin: None,
+                weights: None,
+            });
+        }
+        for (i, bone) in skeleton.bones.iter().enumerate() {
+            if bone.parent.is_some() {
+                continue;
+            }
+            match parent {
+                Some(p) => self.root.nodes[p as usize]
+                    .children
+                    .get_or_insert_with(Vec::new)
+                    .push(bone_node(i)),
+                None => self.scene_nodes.push(bone_node(i)),
+            }
+        }
+        let joints = skeleton.joints.iter().map(|j| bone_node(*j)).collect();
+        Ok(self.push_skin(joints, &skeleton.inverse_bind_matrices))
+    }
+    /// Add a skin over nodes that are already added, e.g. the bones of a humanoid.
+    /// Returns the index of the skin.
+    pub fn add_node_skin(
+        &mut self,
+        joints: &[u32],
+        inverse_bind_matrices: &[[f32; 16]],
+    ) -> Result<u32> {
+        if inverse_bind_matrices.len() != joints.len() {
+            bail!(
+                "{} inverse bind matrices are given for {} joints",
+                inverse_bind_matrices.len(),
+                joints.len()
+            );
+        }
+        if let Some(j) = joints
+            .iter()
+            .find(|j| **j as usize >= self.root.nodes.len())
+        {
+            bail!("node #{} does not exist", j);
+        }
+        let joints = joints.iter().map(|j| Index::new(*j)).collect();
+        Ok(self.push_skin(joints, inverse_bind_matrices))
+    }
+    fn push_skin(
+        &mut self,
+        joints: Vec<Index<gltf_json::Node>>,
+        inverse_bind_matrices: &[[f32; 16]],
+    ) -> u32 {
+        let inverse_bind_matrices = self.push_accessor(
+            inverse_bind_matrices,
+            inverse_bind_matrices.len(),
+            ComponentType::F32,
+            Type::Mat4,
+            None,
+        );
+        self.root.skins.push(gltf_json::Skin {
+            extensions: Default::default(),
+            extras: Default::default(),
+            inverse_bind_matrices: Some(inverse_bind_matrices),
+            joints,
+            name: None,
+            skeleton: None,
+        });
+        self.root.skins.len() as u32 - 1
+    }
+    /// Bind the mesh of a node added before to a skin
+    pub fn set_skin(&mut self, node: u32, skin: u32) -> Result<()> {
+        if skin as usize >= self.root.skins.len() {
+            bail!("skin #{} does not exist", skin);
+        }
+        self.root
+            .nodes
+            .get_mut(node as usize)
+            .with_context(|| format!("node #{} does not exist", node))?
+            .skin = Some(Index::new(skin));
+        Ok(())
+    }
+    /// The document with all the objects added so far, in a single scene
+    pub fn build(mut self) -> Result<Document> {
+        self.root.buffers = vec![gltf_json::Buffer {
+            byte_length: self.bin.len() as u32,
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            uri: None,
+        }];
+        self.root.scenes = vec![gltf_json::Scene {
+            extensions: Default::default(),
+            extras: Default::default(),
+            name: None,
+            nodes: self.scene_nodes,
+        }];
+        self.root.scene = Some(Index::new(0));
+        self.root.asset = gltf_json::Asset {
+            generator: Some("hikalium/vacation".to_string()),
+            ..Default::default()
+        };
+        let mut doc = Document::new(serde_json::to_value(&self.root)?, self.bin);
+        for (texture, extension) in &self.texture_extensions {
+            // There is no fallback image in PNG or JPEG, so the source moves into the extension
+            let texture = &mut doc.json["textures"][texture];
+            let source = texture.as_object_mut().and_then(|t| t.remove("source"));
+            texture["extensions"][extension] = serde_json::json!({ "source": source });
+            extensions::mark_required(&mut doc.json, extension);
+        }
+        for (material, mtoon) in &self.mtoons {
+            doc.set_mtoon(*material, mtoon)?;
+        }
+        Ok(doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validate;
+    use serde_json::json;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    #[test]
+    fn pbr_material_is_written_in_full() {
+        let mut scene = SceneBuilder::new();
+        let textures: Vec<u32> = ["base", "metal", "normal", "occlusion", "emissive"]
+            .iter()
+            .map(|name| scene.add_texture(Some(name), ImageFormat::Png, PNG))
+            .collect();
+        let pbr = scene
+            .add_material(&Material {
+                name: Some("Metal".to_string()),
+                base_color_factor: [1.0, 0.5, 0.25, 1.0],
+                base_color_texture: Some(textures[0]),
+                metallic_factor: 1.0,
+                roughness_factor: 0.25,
+                metallic_roughness_texture: Some(textures[1]),
+                normal_texture: Some(textures[2]),
+                normal_scale: 0.5,
+                occlusion_texture: Some(textures[3]),
+                occlusion_strength: 0.75,
+                emissive_factor: [1.0, 1.0, 0.0],
+                emissive_texture: Some(textures[4]),
+                alpha_mode: AlphaMode::Mask,
+                alpha_cutoff: 0.25,
+                double_sided: true,
+                ..Default::default()
+            })
+            .unwrap();
+        let unlit = scene
+            .add_material(&Material {
+                alpha_mode: AlphaMode::Blend,
+                unlit: true,
+                ..Default::default()
+            })
+            .unwrap();
+        scene.add_node(&Node::default(), None).unwrap();
+        let doc = scene.build().unwrap();
+        validate::check_glb(&doc.to_vec().unwrap()).unwrap();
+
+        assert_eq!(
+            doc.json["materials"][pbr as usize],
+            json!({
+                "name": "Metal",
+                "alphaCutoff": 0.25,
+                "alphaMode": "MASK",
+                "doubleSided": true,
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": [1.0, 0.5, 0.25, 1.0],
+                    "baseColorTexture": { "index": 0, "texCoord": 0 },
+                    "metallicFactor": 1.0,
+                    "roughnessFactor": 0.25,
+                    "metallicRoughnessTexture": { "index": 1, "texCoord": 0 },
+                },
+                "normalTexture": { "index": 2, "scale": 0.5, "texCoord": 0 },
+                "occlusionTexture": { "index": 3, "strength": 0.75, "texCoord": 0 },
+                "emissiveTexture": { "index": 4, "texCoord": 0 },
+                "emissiveFactor": [1.0, 1.0, 0.0],
+            })
+        );
+        let unlit = &doc.json["materials"][unlit as usize];
+        assert_eq!(unlit["alphaMode"], "BLEND");
+        // The cutoff is only written for MASK
+        assert!(unlit.get("alphaCutoff").is_none());
+        assert_eq!(unlit["extensions"], json!({ "KHR_materials_unlit": {} }));
+        assert_eq!(doc.json["extensionsUsed"], json!(["KHR_materials_unlit"]));
+    }
+
+    #[test]
+    fn textures_of_a_material_should_exist() {
+        let mut scene = SceneBuilder::new();
+        scene.add_texture(None, ImageFormat::Png, PNG);
+        let material = Material {
+            occlusion_texture: Some(1),
+            ..Default::default()
+        };
+        assert!(scene.add_material(&material).is_err());
+    }
+}
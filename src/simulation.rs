@@ -0,0 +1,454 @@
+//! Headless simulation of VRMC_springBone. Each joint moves the tail (the next joint of the spring)
+//! by inertia, stiffness toward the animated pose and gravity, keeps it at the bone length and
+//! pushes it out of the colliders, then turns to face it. The steps are fixed, so a bake gives
+//! the same result on every run.
+
+use crate::animation::Animation;
+use crate::animation::RotationTrack;
+use crate::constraint;
+use crate::constraint::NodeConstraint;
+use crate::math;
+use crate::math::Vec3;
+use crate::pose::Pose;
+use crate::pose::Transform;
+use crate::spring_bone::Collider;
+use crate::spring_bone::ExtendedShape;
+use crate::spring_bone::Shape;
+use crate::spring_bone::SpringBone;
+use crate::spring_bone::SpringJoint;
+use crate::Document;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Simulation steps (and baked key frames) per second
+    pub fps: f32,
+    /// Length of the simulation in seconds
+    pub duration: f32,
+    /// Force added to the gravity of every joint, e.g. wind
+    pub external_force: Vec3,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            fps: 60.0,
+            duration: 1.0,
+            external_force: [0.0; 3],
+        }
+    }
+}
+
+/// A joint that has a next joint in its spring, with the position of that tail
+#[derive(Clone, Debug)]
+struct JointState {
+    joint: SpringJoint,
+    node: usize,
+    tail_node: usize,
+    /// Direction of the tail in the local space of the joint
+    bone_axis: Vec3,
+    length: f32,
+    /// Positions of the tail in the space of the center node (or the world) now and one step before
+    current_tail: Vec3,
+    prev_tail: Vec3,
+}
+
+#[derive(Clone, Debug)]
+struct SpringState {
+    center: Option<usize>,
+    colliders: Vec<Collider>,
+    joints: Vec<JointState>,
+}
+
+pub struct Simulator {
+    springs: Vec<SpringState>,
+    initialized: bool,
+}
+
+impl Simulator {
+    pub fn new(spring_bone: &SpringBone, node_count: usize) -> Result<Self> {
+        spring_bone.check_indices(node_count)?;
+        let springs = spring_bone
+            .springs
+            .iter()
+            .map(|s| {
+                let mut colliders = Vec::new();
+                for g in &s.collider_groups {
+                    for c in &spring_bone.collider_groups[*g as usize].colliders {
+                        colliders.push(spring_bone.colliders[*c as usize].clone());
+                    }
+                }
+                let joints = s
+                    .joints
+                    .windows(2)
+                    .map(|j| JointState {
+                        joint: j[0].clone(),
+                        node: j[0].node as usize,
+                        tail_node: j[1].node as usize,
+                        bone_axis: [0.0; 3],
+                        length: 0.0,
+                        current_tail: [0.0; 3],
+                        prev_tail: [0.0; 3],
+                    })
+                    .collect();
+                SpringState {
+                    center: s.center.map(|c| c as usize),
+                    colliders,
+                    joints,
+                }
+            })
+            .collect();
+        Ok(Self {
+            springs,
+            initialized: false,
+        })
+    }
+    /// Nodes whose rotations the simulation changes
+    pub fn joint_nodes(&self) -> Vec<usize> {
+        let mut nodes: Vec<usize> = self
+            .springs
+            .iter()
+            .flat_map(|s| s.joints.iter().map(|j| j.node))
+            .collect();
+        nodes.sort();
+        nodes.dedup();
+        nodes
+    }
+    /// Advance the simulation by `dt` seconds from `pose`, the animated pose of this step,
+    /// and write the rotations of the joints into it. The first step starts from rest.
+    pub fn step(&mut self, pose: &mut Pose, dt: f32, external_force: Vec3) {
+        if !self.initialized {
+            for s in &mut self.springs {
+                let center = s.center.map(|c| pose.world(c)).unwrap_or_default();
+                for j in &mut s.joints {
+                    let tail = pose.world(j.tail_node).translation;
+                    j.bone_axis = math::normalize(pose.local[j.tail_node].translation);
+                    j.length = math::length(math::sub(tail, pose.world(j.node).translation));
+                    j.current_tail = center.inverse_transform_point(tail);
+                    j.prev_tail = j.current_tail;
+                }
+            }
+            self.initialized = true;
+        }
+        for s in &mut self.springs {
+            let center = s.center.map(|c| pose.world(c)).unwrap_or_default();
+            let colliders: Vec<(Transform, &Collider)> = s
+                .colliders
+                .iter()
+                .map(|c| (pose.world(c.node as usize), c))
+                .collect();
+            for j in &mut s.joints {
+                let head = pose.world(j.node).translation;
+                let local = pose.local[j.node].rotation;
+                let rotation = math::quat_mul(pose.parent_world(j.node).rotation, local);
+                let current = center.transform_point(j.current_tail);
+                let prev = center.transform_point(j.prev_tail);
+                let inertia = math::scale(math::sub(current, prev), 1.0 - j.joint.drag_force);
+                let stiffness = math::scale(
+                    math::quat_rotate(rotation, j.bone_axis),
+                    j.joint.stiffness * dt,
+                );
+                let gravity = math::add(
+                    math::scale(j.joint.gravity_dir, j.joint.gravity_power),
+                    external_force,
+                );
+                let next = math::add(
+                    math::add(current, inertia),
+                    math::add(stiffness, math::scale(gravity, dt)),
+                );
+                let mut next = keep_length(head, next, j.length);
+                for (transform, c) in &colliders {
+                    if let Some(p) = collide(transform, c, next, j.joint.hit_radius) {
+                        next = keep_length(head, p, j.length);
+                    }
+                }
+                j.prev_tail = j.current_tail;
+                j.current_tail = center.inverse_transform_point(next);
+                let to = math::quat_rotate(math::quat_inverse(rotation), math::sub(next, head));
+                pose.local[j.node].rotation = math::quat_normalize(math::quat_mul(
+                    local,
+                    math::quat_from_to(j.bone_axis, to),
+                ));
+            }
+        }
+    }
+}
+
+fn keep_length(head: Vec3, tail: Vec3, length: f32) -> Vec3 {
+    math::add(
+        head,
+        math::scale(math::normalize(math::sub(tail, head)), length),
+    )
+}
+
+fn nearest_on_segment(a: Vec3, b: Vec3, p: Vec3) -> Vec3 {
+    let ab = math::sub(b, a);
+    let l = math::dot(ab, ab);
+    if l == 0.0 {
+        return a;
+    }
+    let t = (math::dot(math::sub(p, a), ab) / l).clamp(0.0, 1.0);
+    math::add(a, math::scale(ab, t))
+}
+
+/// Where a tail at `p` is pushed to by a collider, or None if it doesn't touch it
+fn collide(transform: &Transform, collider: &Collider, p: Vec3, hit_radius: f32) -> Option<Vec3> {
+    let scale = transform.scale.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+    let shape = collider
+        .extended_shape()
+        .copied()
+        .unwrap_or(match collider.shape {
+            Shape::Sphere { offset, radius } => ExtendedShape::Sphere {
+                offset,
+                radius,
+                inside: false,
+            },
+            Shape::Capsule {
+                offset,
+                radius,
+                tail,
+            } => ExtendedShape::Capsule {
+                offset,
+                radius,
+                tail,
+                inside: false,
+            },
+        });
+    let (nearest, radius, inside) = match shape {
+        ExtendedShape::Sphere {
+            offset,
+            radius,
+            inside,
+        } => (transform.transform_point(offset), radius, inside),
+        ExtendedShape::Capsule {
+            offset,
+            radius,
+            tail,
+            inside,
+        } => (
+            nearest_on_segment(
+                transform.transform_point(offset),
+                transform.transform_point(tail),
+                p,
+            ),
+            radius,
+            inside,
+        ),
+        ExtendedShape::Plane { offset, normal } => {
+            let origin = transform.transform_point(offset);
+            let normal = math::normalize(math::quat_rotate(transform.rotation, normal));
+            let distance = math::dot(math::sub(p, origin), normal);
+            return (distance < hit_radius)
+                .then(|| math::add(p, math::scale(normal, hit_radius - distance)));
+        }
+    };
+    let d = math::sub(p, nearest);
+    let distance = math::length(d);
+    let direction = math::normalize(d);
+    if inside {
+        let limit = (radius * scale - hit_radius).max(0.0);
+        (distance > limit).then(|| math::add(nearest, math::scale(direction, limit)))
+    } else {
+        let limit = radius * scale + hit_radius;
+        (distance < limit).then(|| math::add(nearest, math::scale(direction, limit)))
+    }
+}
+
+/// Simulate the springs of a document over animation `animation` (or the rest pose without one)
+/// and return the times of the steps with the rotations of the joints at each of them.
+/// Node constraints are applied to the animated pose before the springs.
+pub fn bake(
+    doc: &Document,
+    animation: Option<usize>,
+    settings: &Settings,
+) -> Result<(Vec<f32>, Vec<RotationTrack>)> {
+    if settings.fps.is_nan() || settings.fps <= 0.0 {
+        bail!("fps has to be positive");
+    }
+    if settings.duration.is_nan() || settings.duration < 0.0 {
+        bail!("duration can't be negative");
+    }
+    let spring_bone = doc
+        .spring_bone()?
+        .context("The document has no VRMC_springBone")?;
+    let root = doc.root()?;
+    let animation = match animation {
+        Some(a) => Animation::read(&root, doc.buffers(), a)?,
+        None => Animation::default(),
+    };
+    let constraints: Vec<(usize, NodeConstraint)> = doc.node_constraints()?;
+    let rest = Pose::rest(&root)?;
+    let mut simulator = Simulator::new(&spring_bone, root.nodes.len())?;
+    let nodes = simulator.joint_nodes();
+    let mut tracks: Vec<RotationTrack> = nodes
+        .iter()
+        .map(|n| RotationTrack {
+            node: *n,
+            rotations: Vec::new(),
+        })
+        .collect();
+    let dt = 1.0 / settings.fps;
+    let frames = (settings.duration * settings.fps).round() as usize;
+    let mut times = Vec::with_capacity(frames + 1);
+    for frame in 0..=frames {
+        let time = frame as f32 * dt;
+        let mut pose = rest.clone();
+        animation.apply(time, &mut pose);
+        constraint::evaluate(&constraints, &rest, &mut pose)?;
+        simulator.step(&mut pose, dt, settings.external_force);
+        for t in &mut tracks {
+            t.rotations.push(pose.local[t.node].rotation);
+        }
+        times.push(time);
+    }
+    Ok((times, tracks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Quat;
+    use crate::spring_bone::ColliderGroup;
+    use crate::spring_bone::Spring;
+
+    fn translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    fn joint(node: u32) -> SpringJoint {
+        SpringJoint {
+            node,
+            stiffness: 0.0,
+            ..Default::default()
+        }
+    }
+
+    fn assert_near<const N: usize>(actual: [f32; N], expected: [f32; N]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() < 1e-4),
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    /// q and -q are the same rotation
+    fn assert_rotation(actual: Quat, expected: Quat) {
+        let d = math::quat_mul(math::quat_inverse(expected), actual);
+        assert!(
+            d[3].abs() > 1.0 - 1e-5,
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn chain_falls_under_gravity() {
+        // A joint at the origin with its tail at +X
+        let mut pose = Pose {
+            local: vec![Transform::default(), translation([1.0, 0.0, 0.0])],
+            parents: vec![None, Some(0)],
+        };
+        let spring_bone = SpringBone {
+            springs: vec![Spring {
+                joints: vec![
+                    SpringJoint {
+                        gravity_power: 1.0,
+                        ..joint(0)
+                    },
+                    joint(1),
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(&spring_bone, 2).unwrap();
+        for _ in 0..600 {
+            simulator.step(&mut pose, 1.0 / 60.0, [0.0; 3]);
+        }
+        // Hanging down: -90 degrees around Z
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        assert_rotation(pose.local[0].rotation, [0.0, 0.0, -s, s]);
+        assert_near(pose.world(1).translation, [0.0, -1.0, 0.0]);
+    }
+
+    #[test]
+    fn sphere_pushes_the_tail_out() {
+        let mut pose = Pose {
+            local: vec![
+                Transform::default(),
+                translation([1.0, 0.0, 0.0]),
+                Transform::default(),
+            ],
+            parents: vec![None, Some(0), None],
+        };
+        // The tail is 0.5 above the center of a sphere of radius 0.6
+        let spring_bone = SpringBone {
+            colliders: vec![Collider::new(
+                2,
+                Shape::Sphere {
+                    offset: [1.0, -0.5, 0.0],
+                    radius: 0.6,
+                },
+            )],
+            collider_groups: vec![ColliderGroup {
+                colliders: vec![0],
+                ..Default::default()
+            }],
+            springs: vec![Spring {
+                joints: vec![joint(0), joint(1)],
+                collider_groups: vec![0],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut simulator = Simulator::new(&spring_bone, 3).unwrap();
+        simulator.step(&mut pose, 1.0 / 60.0, [0.0; 3]);
+        // Pushed up to (1, 0.1, 0), then back to the bone length: atan(0.1) around Z
+        let y = 0.1 / 1.01f32.sqrt();
+        assert_near(pose.world(1).translation, [10.0 * y, y, 0.0]);
+        assert_rotation(pose.local[0].rotation, [0.0, 0.0, 0.049813705, 0.9987586]);
+    }
+
+    #[test]
+    fn center_cancels_its_motion() {
+        // Node 0 carries a joint (node 1) whose tail (node 2) hangs below it
+        let rest = Pose {
+            local: vec![
+                Transform::default(),
+                Transform::default(),
+                translation([0.0, -1.0, 0.0]),
+            ],
+            parents: vec![None, Some(0), Some(1)],
+        };
+        let rotation_after_move = |center: Option<u32>| {
+            let spring_bone = SpringBone {
+                springs: vec![Spring {
+                    joints: vec![joint(1), joint(2)],
+                    center,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let mut simulator = Simulator::new(&spring_bone, 3).unwrap();
+            let mut pose = rest.clone();
+            simulator.step(&mut pose, 1.0 / 60.0, [0.0; 3]);
+            pose.local[0].translation = [1.0, 0.0, 0.0];
+            simulator.step(&mut pose, 1.0 / 60.0, [0.0; 3]);
+            pose.local[1].rotation
+        };
+        assert_rotation(rotation_after_move(Some(0)), math::IDENTITY_QUAT);
+        // Without the center the tail is left behind
+        let r = rotation_after_move(None);
+        assert!(r[3].abs() < 0.99, "{:?} should have turned", r);
+    }
+}
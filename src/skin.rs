@@ -0,0 +1,382 @@
+//! Skinning: the joints and weights of vertices, and the skeleton they are bound to.
+
+use crate::accessor;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use gltf_json::mesh::Semantic;
+use gltf_json::validation::Checked::Valid;
+use std::collections::BTreeSet;
+
+pub const IDENTITY_MATRIX: [f32; 16] = [
+    1.0, 0.0, 0.0, 0.0, //
+    0.0, 1.0, 0.0, 0.0, //
+    0.0, 0.0, 1.0, 0.0, //
+    0.0, 0.0, 0.0, 1.0, //
+];
+
+/// A node of a skeleton with its local transform
+#[derive(Clone, Debug, Default)]
+pub struct Bone {
+    pub name: Option<String>,
+    /// Index of the parent bone
+    pub parent: Option<usize>,
+    pub translation: Option<[f32; 3]>,
+    pub rotation: Option<[f32; 4]>,
+    pub scale: Option<[f32; 3]>,
+    pub matrix: Option<[f32; 16]>,
+}
+
+/// The nodes a skin needs: its joints and their ancestors
+#[derive(Clone, Debug, Default)]
+pub struct Skeleton {
+    /// Parents come before their children
+    pub bones: Vec<Bone>,
+    /// Index of the bone of each joint
+    pub joints: Vec<usize>,
+    /// One column-major matrix per joint
+    pub inverse_bind_matrices: Vec<[f32; 16]>,
+}
+
+impl Skeleton {
+    /// Add a joint placed at `translation` from the parent joint without rotation, and return its index.
+    /// The inverse bind matrix assumes that no ancestor is rotated or scaled.
+    pub fn add_joint(&mut self, name: &str, parent: Option<u32>, translation: [f32; 3]) -> u32 {
+        let parent = parent.map(|p| self.joints[p as usize]);
+        let mut global = translation;
+        let mut bone = parent;
+        while let Some(b) = bone {
+            let t = self.bones[b].translation.unwrap_or_default();
+            for i in 0..3 {
+                global[i] += t[i];
+            }
+            bone = self.bones[b].parent;
+        }
+        let mut inverse_bind_matrix = IDENTITY_MATRIX;
+        for i in 0..3 {
+            inverse_bind_matrix[12 + i] = -global[i];
+        }
+        self.bones.push(Bone {
+            name: Some(name.to_string()),
+            parent,
+            translation: Some(translation),
+            ..Default::default()
+        });
+        self.joints.push(self.bones.len() - 1);
+        self.inverse_bind_matrices.push(inverse_bind_matrix);
+        self.joints.len() as u32 - 1
+    }
+    /// Read a skin of a document together with the nodes above its joints
+    pub fn read(root: &gltf_json::Root, buffers: &[Vec<u8>], skin: usize) -> Result<Self> {
+        let s = root
+            .skins
+            .get(skin)
+            .with_context(|| format!("skin #{} does not exist", skin))?;
+        let mut parents = vec![None; root.nodes.len()];
+        for (i, n) in root.nodes.iter().enumerate() {
+            for c in n.children.iter().flatten() {
+                parents[c.value()] = Some(i);
+            }
+        }
+        // Joints and all their ancestors, in the order of the document
+        let mut nodes = BTreeSet::new();
+        for j in &s.joints {
+            let mut node = Some(j.value());
+            while let Some(n) = node {
+                if n >= root.nodes.len() {
+                    bail!("joint node #{} does not exist", n);
+                }
+                if !nodes.insert(n) {
+                    break;
+                }
+                node = parents[n];
+            }
+        }
+        // Sort them so that parents come first
+        let mut order = Vec::new();
+        let visit = |start: usize, order: &mut Vec<usize>| {
+            let mut stack = vec![start];
+            while let Some(n) = stack.pop() {
+                order.push(n);
+                for c in root.nodes[n].children.iter().flatten().rev() {
+                    if nodes.contains(&c.value()) {
+                        stack.push(c.value());
+                    }
+                }
+            }
+        };
+        for n in &nodes {
+            if !parents[*n].map(|p| nodes.contains(&p)).unwrap_or(false) {
+                visit(*n, &mut order);
+            }
+        }
+        let bone_of = |node: usize| order.iter().position(|n| *n == node);
+        let bones = order
+            .iter()
+            .map(|n| {
+                let node = &root.nodes[*n];
+                Bone {
+                    name: node.name.clone(),
+                    parent: parents[*n].and_then(bone_of),
+                    translation: node.translation,
+                    rotation: node.rotation.as_ref().map(|r| r.0),
+                    scale: node.scale,
+                    matrix: node.matrix,
+                }
+            })
+            .collect();
+        let joints = s
+            .joints
+            .iter()
+            .map(|j| bone_of(j.value()).unwrap())
+            .collect();
+        let inverse_bind_matrices = match s.inverse_bind_matrices {
+            Some(a) => accessor::read_f32::<16>(root, buffers, a.value())?,
+            None => vec![IDENTITY_MATRIX; s.joints.len()],
+        };
+        if inverse_bind_matrices.len() != s.joints.len() {
+            bail!(
+                "skin #{} has {} joints but {} inverse bind matrices",
+                skin,
+                s.joints.len(),
+                inverse_bind_matrices.len()
+            );
+        }
+        Ok(Self {
+            bones,
+            joints,
+            inverse_bind_matrices,
+        })
+    }
+}
+
+/// Skinning of the vertices of a primitive
+#[derive(Clone, Debug, Default)]
+pub struct Skinning {
+    /// Indices into `skeleton.joints`
+    pub joints: Vec<[u32; 4]>,
+    pub weights: Vec<[f32; 4]>,
+    pub skeleton: Skeleton,
+}
+
+impl Skinning {
+    /// Bind each vertex to a single joint with the full weight
+    pub fn rigid(joints: &[u32], skeleton: Skeleton) -> Self {
+        Self {
+            joints: joints.iter().map(|j| [*j, 0, 0, 0]).collect(),
+            weights: vec![[1.0, 0.0, 0.0, 0.0]; joints.len()],
+            skeleton,
+        }
+    }
+    /// Check that the skinning can be written for a primitive with `vertex_count` vertices
+    pub fn validate(&self, vertex_count: usize) -> Result<()> {
+        let joint_count = self.skeleton.joints.len();
+        if self.joints.len() != vertex_count || self.weights.len() != vertex_count {
+            bail!(
+                "{} joints and {} weights are given for {} vertices",
+                self.joints.len(),
+                self.weights.len(),
+                vertex_count
+            );
+        }
+        if joint_count > 1 << 16 {
+            bail!("Too many joints: {}", joint_count);
+        }
+        if self.skeleton.inverse_bind_matrices.len() != joint_count {
+            bail!(
+                "{} inverse bind matrices are given for {} joints",
+                self.skeleton.inverse_bind_matrices.len(),
+                joint_count
+            );
+        }
+        if let Some(j) = self
+            .joints
+            .flatten()
+            .iter()
+            .find(|j| **j as usize >= joint_count)
+        {
+            bail!("joint {} is out of the {} joints", j, joint_count);
+        }
+        for (i, w) in self.weights.iter().enumerate() {
+            if w.iter().any(|w| w.is_nan() || *w < 0.0) || w.iter().sum::<f32>() <= 0.0 {
+                bail!("vertex {} has invalid weights {:?}", i, w);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Scale the weights of each vertex so that they sum up to one, as glTF requires.
+/// Weights that are already normalized are kept as is.
+pub fn normalize_weights(weights: &[[f32; 4]]) -> Vec<[f32; 4]> {
+    weights
+        .iter()
+        .map(|w| {
+            let sum: f32 = w.iter().sum();
+            if (sum - 1.0).abs() <= 1e-6 || sum <= 0.0 {
+                *w
+            } else {
+                w.map(|w| w / sum)
+            }
+        })
+        .collect()
+}
+
+/// JOINTS_0 and WEIGHTS_0 of the vertices of a primitive
+pub type JointsAndWeights = (Vec<[u32; 4]>, Vec<[f32; 4]>);
+
+/// Read JOINTS_0 and WEIGHTS_0 of a primitive, if it has them
+pub fn read_joints_and_weights(
+    root: &gltf_json::Root,
+    buffers: &[Vec<u8>],
+    primitive: &gltf_json::mesh::Primitive,
+) -> Result<Option<JointsAndWeights>> {
+    let get = |semantic: Semantic| primitive.attributes.get(&Valid(semantic));
+    let (joints, weights) = match (get(Semantic::Joints(0)), get(Semantic::Weights(0))) {
+        (Some(j), Some(w)) => (j.value(), w.value()),
+        (None, None) => return Ok(None),
+        _ => bail!("JOINTS_0 and WEIGHTS_0 should be used together"),
+    };
+    let joints = accessor::read_u32::<4>(root, buffers, joints)?;
+    let weights = accessor::read_f32::<4>(root, buffers, weights)?;
+    if joints.len() != weights.len() {
+        bail!("JOINTS_0 and WEIGHTS_0 have different counts");
+    }
+    Ok(Some((joints, weights)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glb::write_glb;
+    use crate::model::Model;
+    use crate::Document;
+    use serde_json::json;
+
+    #[test]
+    fn skeleton_has_the_ancestors_of_the_joints() {
+        // Node 4 is not related to the skin
+        let root: gltf_json::Root = serde_json::from_value(json!({
+            "asset": { "version": "2.0" },
+            "nodes": [
+                { "name": "Root", "children": [1] },
+                { "name": "Spine", "children": [2, 3], "translation": [0.0, 1.0, 0.0] },
+                { "name": "Left" },
+                { "name": "Right" },
+                { "name": "Other" },
+            ],
+            "skins": [{ "joints": [3, 2] }],
+        }))
+        .unwrap();
+        let skeleton = Skeleton::read(&root, &[], 0).unwrap();
+        let names: Vec<_> = skeleton
+            .bones
+            .iter()
+            .map(|b| b.name.as_deref().unwrap())
+            .collect();
+        assert_eq!(names, vec!["Root", "Spine", "Left", "Right"]);
+        let parents: Vec<_> = skeleton.bones.iter().map(|b| b.parent).collect();
+        assert_eq!(parents, vec![None, Some(0), Some(1), Some(1)]);
+        assert_eq!(skeleton.bones[1].translation, Some([0.0, 1.0, 0.0]));
+        assert_eq!(skeleton.joints, vec![3, 2]);
+        // Without inverseBindMatrices, they are the identity
+        assert_eq!(skeleton.inverse_bind_matrices, vec![IDENTITY_MATRIX; 2]);
+    }
+
+    #[test]
+    fn inverse_bind_matrices_of_added_joints() {
+        let mut s = Skeleton::default();
+        let hips = s.add_joint("Hips", None, [0.0, 1.0, 0.0]);
+        let head = s.add_joint("Head", Some(hips), [0.0, 0.5, 0.25]);
+        assert_eq!(s.bones[head as usize].parent, Some(hips as usize));
+        assert_eq!(
+            s.inverse_bind_matrices[hips as usize][12..15],
+            [0.0, -1.0, 0.0]
+        );
+        assert_eq!(
+            s.inverse_bind_matrices[head as usize][12..15],
+            [0.0, -1.5, -0.25]
+        );
+    }
+
+    #[test]
+    fn weights_are_normalized() {
+        let weights = normalize_weights(&[[2.0, 2.0, 0.0, 0.0], [0.5, 0.25, 0.25, 0.0], [0.0; 4]]);
+        assert_eq!(
+            weights,
+            vec![[0.5, 0.5, 0.0, 0.0], [0.5, 0.25, 0.25, 0.0], [0.0; 4]]
+        );
+    }
+
+    #[test]
+    fn invalid_skinning_is_rejected() {
+        let mut s = Skeleton::default();
+        s.add_joint("Hips", None, [0.0; 3]);
+        let skinning = Skinning::rigid(&[0, 0], s);
+        assert!(skinning.validate(2).is_ok());
+        assert!(skinning.validate(3).is_err());
+
+        let mut out_of_range = skinning.clone();
+        out_of_range.joints[1] = [1, 0, 0, 0];
+        assert!(out_of_range.validate(2).is_err());
+
+        let mut negative = skinning.clone();
+        negative.weights[0] = [1.5, -0.5, 0.0, 0.0];
+        assert!(negative.validate(2).is_err());
+
+        let mut zero = skinning;
+        zero.weights[1] = [0.0; 4];
+        assert!(zero.validate(2).is_err());
+    }
+
+    #[test]
+    fn written_skin_reads_back_with_normalized_weights() {
+        let (model, mut skinning) = Model::rigged_figure();
+        let expected = skinning.weights.clone();
+        for w in &mut skinning.weights {
+            *w = w.map(|w| w * 2.0);
+        }
+        let path = std::env::temp_dir().join(format!("vacation-skin-{}.glb", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_glb(
+            &model.vertices,
+            &model.indices,
+            &model.normals,
+            None,
+            None,
+            None,
+            Some(&skinning),
+            &[],
+            None,
+            path,
+        )
+        .unwrap();
+        let doc = Document::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let root = doc.root().unwrap();
+        let skeleton = Skeleton::read(&root, doc.buffers(), 0).unwrap();
+        let names: Vec<_> = skeleton
+            .joints
+            .iter()
+            .map(|j| skeleton.bones[*j].name.clone())
+            .collect();
+        let expected_names: Vec<_> = skinning
+            .skeleton
+            .joints
+            .iter()
+            .map(|j| skinning.skeleton.bones[*j].name.clone())
+            .collect();
+        assert_eq!(names, expected_names);
+        assert_eq!(
+            skeleton.inverse_bind_matrices,
+            skinning.skeleton.inverse_bind_matrices
+        );
+        let (joints, weights) =
+            read_joints_and_weights(&root, doc.buffers(), &root.meshes[0].primitives[0])
+                .unwrap()
+                .unwrap();
+        assert_eq!(joints, skinning.joints);
+        assert_eq!(weights, expected);
+    }
+}
@@ -0,0 +1,387 @@
+//! The VRMC_springBone extension: chains of joints swayed by gravity and inertia (hair, skirts, ...)
+//! and the colliders they can't go through. The inside and plane shapes of colliders are
+//! VRMC_springBone_extended_collider, with a sphere or capsule as the fallback in the base shape.
+
+use crate::extensions;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+
+pub const EXTENSION_NAME: &str = "VRMC_springBone";
+pub const SPEC_VERSION: &str = "1.0";
+pub const EXTENDED_COLLIDER_EXTENSION_NAME: &str = "VRMC_springBone_extended_collider";
+
+/// The VRMC_springBone extension on the glTF root
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpringBone {
+    pub spec_version: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub colliders: Vec<Collider>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collider_groups: Vec<ColliderGroup>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub springs: Vec<Spring>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+impl Default for SpringBone {
+    fn default() -> Self {
+        Self {
+            spec_version: SPEC_VERSION.to_string(),
+            colliders: Vec::new(),
+            collider_groups: Vec::new(),
+            springs: Vec::new(),
+            extensions: None,
+            extras: None,
+        }
+    }
+}
+
+impl SpringBone {
+    /// Read the VRMC_springBone extension from the JSON of a glTF document, if any
+    pub fn from_json(root: &Value) -> Result<Option<Self>> {
+        extensions::get(root, EXTENSION_NAME)
+    }
+    /// Store this as the VRMC_springBone extension of the JSON of a glTF document,
+    /// and list VRMC_springBone_extended_collider too if a collider has it
+    pub fn write_to_json(&self, root: &mut Value) -> Result<()> {
+        extensions::set(root, EXTENSION_NAME, self)?;
+        extensions::mark_used(root, EXTENSION_NAME);
+        if self.colliders.iter().any(|c| c.extended_shape().is_some()) {
+            extensions::mark_used(root, EXTENDED_COLLIDER_EXTENSION_NAME);
+        }
+        Ok(())
+    }
+    /// Fail if a node, collider or collider group referred to does not exist
+    pub fn check_indices(&self, node_count: usize) -> Result<()> {
+        let check_node = |node: u32, what: &str| {
+            if node as usize >= node_count {
+                bail!("node #{} of {} does not exist", node, what);
+            }
+            Ok(())
+        };
+        for (i, c) in self.colliders.iter().enumerate() {
+            check_node(c.node, &format!("collider #{}", i))?;
+        }
+        for (i, g) in self.collider_groups.iter().enumerate() {
+            if let Some(c) = g
+                .colliders
+                .iter()
+                .find(|c| **c as usize >= self.colliders.len())
+            {
+                bail!("collider #{} of collider group #{} does not exist", c, i);
+            }
+        }
+        for (i, s) in self.springs.iter().enumerate() {
+            let what = format!("spring #{}", i);
+            for j in &s.joints {
+                check_node(j.node, &what)?;
+            }
+            if let Some(center) = s.center {
+                check_node(center, &what)?;
+            }
+            if let Some(g) = s
+                .collider_groups
+                .iter()
+                .find(|g| **g as usize >= self.collider_groups.len())
+            {
+                bail!("collider group #{} of {} does not exist", g, what);
+            }
+        }
+        Ok(())
+    }
+    /// Index of the spring given by its name or index
+    pub fn resolve_spring(&self, spring: &str) -> Result<usize> {
+        if let Some(i) = self
+            .springs
+            .iter()
+            .position(|s| s.name.as_deref() == Some(spring))
+        {
+            return Ok(i);
+        }
+        match spring.parse::<usize>() {
+            Ok(i) if i < self.springs.len() => Ok(i),
+            _ => bail!("Spring {} does not exist", spring),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Collider {
+    /// Index of the glTF node the collider moves with
+    pub node: u32,
+    pub shape: Shape,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<ColliderExtensions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+impl Collider {
+    pub fn new(node: u32, shape: Shape) -> Self {
+        Self {
+            node,
+            shape,
+            extensions: None,
+            extras: None,
+        }
+    }
+    /// A collider with VRMC_springBone_extended_collider, and its fallback as the base shape
+    pub fn new_extended(node: u32, shape: ExtendedShape) -> Self {
+        let mut collider = Self::new(node, shape.fallback());
+        collider.set_extended_shape(Some(shape));
+        collider
+    }
+    pub fn extended_shape(&self) -> Option<&ExtendedShape> {
+        self.extensions
+            .as_ref()
+            .and_then(|e| e.extended_collider.as_ref())
+            .map(|e| &e.shape)
+    }
+    pub fn set_extended_shape(&mut self, shape: Option<ExtendedShape>) {
+        let extensions = self.extensions.get_or_insert_with(Default::default);
+        extensions.extended_collider = shape.map(|shape| ExtendedCollider {
+            spec_version: SPEC_VERSION.to_string(),
+            shape,
+        });
+        if extensions.extended_collider.is_none() && extensions.others.is_empty() {
+            self.extensions = None;
+        }
+    }
+}
+
+/// Extensions of a collider. The ones other than VRMC_springBone_extended_collider are kept as is.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ColliderExtensions {
+    #[serde(
+        default,
+        rename = "VRMC_springBone_extended_collider",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub extended_collider: Option<ExtendedCollider>,
+    #[serde(flatten)]
+    pub others: serde_json::Map<String, Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Shape {
+    Sphere {
+        #[serde(default)]
+        offset: [f32; 3],
+        #[serde(default)]
+        radius: f32,
+    },
+    Capsule {
+        #[serde(default)]
+        offset: [f32; 3],
+        #[serde(default)]
+        radius: f32,
+        #[serde(default)]
+        tail: [f32; 3],
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendedCollider {
+    pub spec_version: String,
+    pub shape: ExtendedShape,
+}
+
+fn default_normal() -> [f32; 3] {
+    [0.0, 0.0, 1.0]
+}
+
+/// Shapes of VRMC_springBone_extended_collider. An `inside` shape keeps the joints inside of it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExtendedShape {
+    Sphere {
+        #[serde(default)]
+        offset: [f32; 3],
+        #[serde(default)]
+        radius: f32,
+        #[serde(default)]
+        inside: bool,
+    },
+    Capsule {
+        #[serde(default)]
+        offset: [f32; 3],
+        #[serde(default)]
+        radius: f32,
+        #[serde(default)]
+        tail: [f32; 3],
+        #[serde(default)]
+        inside: bool,
+    },
+    /// Joints are kept on the side the normal points to
+    Plane {
+        #[serde(default)]
+        offset: [f32; 3],
+        #[serde(default = "default_normal")]
+        normal: [f32; 3],
+    },
+}
+
+/// Radius of the sphere used in place of a plane by the implementations without the extended collider
+const PLANE_FALLBACK_RADIUS: f32 = 100.0;
+
+impl ExtendedShape {
+    /// The base shape for the implementations that don't support VRMC_springBone_extended_collider.
+    /// An inside shape has no fallback that works the same way, so the shape itself is used.
+    pub fn fallback(&self) -> Shape {
+        match *self {
+            ExtendedShape::Sphere { offset, radius, .. } => Shape::Sphere { offset, radius },
+            ExtendedShape::Capsule {
+                offset,
+                radius,
+                tail,
+                ..
+            } => Shape::Capsule {
+                offset,
+                radius,
+                tail,
+            },
+            ExtendedShape::Plane { offset, normal } => {
+                let length = normal.iter().map(|v| v * v).sum::<f32>().sqrt().max(1e-6);
+                Shape::Sphere {
+                    offset: [0, 1, 2]
+                        .map(|i| offset[i] - normal[i] / length * PLANE_FALLBACK_RADIUS),
+                    radius: PLANE_FALLBACK_RADIUS,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColliderGroup {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Indices of the colliders
+    pub colliders: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+/// A chain of joints, each of them a child node of the previous one
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spring {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub joints: Vec<SpringJoint>,
+    /// Indices of the collider groups the joints collide with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collider_groups: Vec<u32>,
+    /// Index of the node whose motion is not applied to the joints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub center: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+/// Settings of a joint. Missing values are read as their default values in the specification.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SpringJoint {
+    /// Index of the glTF node
+    pub node: u32,
+    pub hit_radius: f32,
+    pub stiffness: f32,
+    pub gravity_power: f32,
+    pub gravity_dir: [f32; 3],
+    pub drag_force: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+impl Default for SpringJoint {
+    fn default() -> Self {
+        Self {
+            node: 0,
+            hit_radius: 0.0,
+            stiffness: 1.0,
+            gravity_power: 0.0,
+            gravity_dir: [0.0, -1.0, 0.0],
+            drag_force: 0.5,
+            extensions: None,
+            extras: None,
+        }
+    }
+}
+
+impl SpringJoint {
+    pub fn new(node: u32) -> Self {
+        Self {
+            node,
+            ..Default::default()
+        }
+    }
+    /// Set a setting given by its name in the schema, e.g. `dragForce`.
+    /// `gravityDir` is given as numbers separated by commas.
+    pub fn set_property(&mut self, name: &str, value: &str) -> Result<()> {
+        // The ranges the schema allows
+        let number = |range: std::ops::RangeInclusive<f32>| {
+            let v = value
+                .trim()
+                .parse::<f32>()
+                .with_context(|| format!("{} of {} is not a number", value, name))?;
+            if !v.is_finite() {
+                bail!("{} of {} is not a number", value, name);
+            }
+            if !range.contains(&v) {
+                if *range.end() == f32::MAX {
+                    bail!("{} of {} can't be negative", value, name);
+                }
+                bail!(
+                    "{} of {} is not in [{}, {}]",
+                    value,
+                    name,
+                    range.start(),
+                    range.end()
+                );
+            }
+            Ok(v)
+        };
+        match name {
+            "hitRadius" => self.hit_radius = number(0.0..=f32::MAX)?,
+            "stiffness" => self.stiffness = number(0.0..=f32::MAX)?,
+            "gravityPower" => self.gravity_power = number(0.0..=f32::MAX)?,
+            "dragForce" => self.drag_force = number(0.0..=1.0)?,
+            "gravityDir" => {
+                let v = value
+                    .split(',')
+                    .map(|v| v.trim().parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .with_context(|| format!("{} of {} is not a number", value, name))?;
+                let v: [f32; 3] = v
+                    .try_into()
+                    .map_err(|_| anyhow!("gravityDir should have 3 numbers"))?;
+                if !v.iter().all(|c| c.is_finite()) || v == [0.0; 3] {
+                    bail!("gravityDir should be a direction, not {}", value);
+                }
+                self.gravity_dir = v;
+            }
+            _ => bail!("{} is not a setting of a spring joint", name),
+        }
+        Ok(())
+    }
+}
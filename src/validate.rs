@@ -0,0 +1,1257 @@
+//! Conformance checks against the glTF 2.0 and VRM 1.0 specifications.
+//! Each problem is reported with a JSON pointer into the document, e.g. `/accessors/3/max`.
+
+use crate::accessor;
+use crate::accessor::Layout;
+use crate::constraint;
+use crate::image::ImageFormat;
+use crate::image::IMAGE_FORMATS;
+use crate::mtoon;
+use crate::pose;
+use crate::pose::Pose;
+use crate::skin;
+use crate::spring_bone;
+use crate::spring_bone::ExtendedShape;
+use crate::vrm;
+use crate::vrm::Vrm;
+use crate::Document;
+use anyhow::bail;
+use anyhow::Result;
+use gltf_json::validation::Checked::Valid;
+use gltf_json::validation::Validate;
+use gltf_json::Path;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in a document
+#[derive(Clone, Debug)]
+pub struct Issue {
+    pub severity: Severity,
+    /// JSON pointer to the object that has the problem
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.pointer, self.message)
+    }
+}
+
+/// Parent of each humanoid bone in VRM 1.0. A bone must be a descendant of
+/// its nearest mapped ancestor bone.
+const HUMAN_BONE_PARENTS: [(&str, &str); 54] = [
+    ("spine", "hips"),
+    ("chest", "spine"),
+    ("upperChest", "chest"),
+    ("neck", "upperChest"),
+    ("head", "neck"),
+    ("leftEye", "head"),
+    ("rightEye", "head"),
+    ("jaw", "head"),
+    ("leftUpperLeg", "hips"),
+    ("leftLowerLeg", "leftUpperLeg"),
+    ("leftFoot", "leftLowerLeg"),
+    ("leftToes", "leftFoot"),
+    ("rightUpperLeg", "hips"),
+    ("rightLowerLeg", "rightUpperLeg"),
+    ("rightFoot", "rightLowerLeg"),
+    ("rightToes", "rightFoot"),
+    ("leftShoulder", "upperChest"),
+    ("leftUpperArm", "leftShoulder"),
+    ("leftLowerArm", "leftUpperArm"),
+    ("leftHand", "leftLowerArm"),
+    ("rightShoulder", "upperChest"),
+    ("rightUpperArm", "rightShoulder"),
+    ("rightLowerArm", "rightUpperArm"),
+    ("rightHand", "rightLowerArm"),
+    ("leftThumbMetacarpal", "leftHand"),
+    ("leftThumbProximal", "leftThumbMetacarpal"),
+    ("leftThumbDistal", "leftThumbProximal"),
+    ("leftIndexProximal", "leftHand"),
+    ("leftIndexIntermediate", "leftIndexProximal"),
+    ("leftIndexDistal", "leftIndexIntermediate"),
+    ("leftMiddleProximal", "leftHand"),
+    ("leftMiddleIntermediate", "leftMiddleProximal"),
+    ("leftMiddleDistal", "leftMiddleIntermediate"),
+    ("leftRingProximal", "leftHand"),
+    ("leftRingIntermediate", "leftRingProximal"),
+    ("leftRingDistal", "leftRingIntermediate"),
+    ("leftLittleProximal", "leftHand"),
+    ("leftLittleIntermediate", "leftLittleProximal"),
+    ("leftLittleDistal", "leftLittleIntermediate"),
+    ("rightThumbMetacarpal", "rightHand"),
+    ("rightThumbProximal", "rightThumbMetacarpal"),
+    ("rightThumbDistal", "rightThumbProximal"),
+    ("rightIndexProximal", "rightHand"),
+    ("rightIndexIntermediate", "rightIndexProximal"),
+    ("rightIndexDistal", "rightIndexIntermediate"),
+    ("rightMiddleProximal", "rightHand"),
+    ("rightMiddleIntermediate", "rightMiddleProximal"),
+    ("rightMiddleDistal", "rightMiddleIntermediate"),
+    ("rightRingProximal", "rightHand"),
+    ("rightRingIntermediate", "rightRingProximal"),
+    ("rightRingDistal", "rightRingIntermediate"),
+    ("rightLittleProximal", "rightHand"),
+    ("rightLittleIntermediate", "rightLittleProximal"),
+    ("rightLittleDistal", "rightLittleIntermediate"),
+];
+
+/// Convert a path reported by gltf_json (e.g. `meshes[0].primitives[1]`) into a JSON pointer
+fn to_pointer(path: &Path) -> String {
+    let mut pointer = String::new();
+    for part in path.as_str().split(['.', '[']) {
+        let part = part.trim_end_matches(']').trim_matches('"');
+        if !part.is_empty() {
+            pointer.push('/');
+            pointer.push_str(part);
+        }
+    }
+    pointer
+}
+
+#[derive(Default)]
+struct Validator {
+    issues: Vec<Issue>,
+}
+
+impl Validator {
+    fn error(&mut self, pointer: impl Into<String>, message: impl Into<String>) {
+        self.issues.push(Issue {
+            severity: Severity::Error,
+            pointer: pointer.into(),
+            message: message.into(),
+        });
+    }
+    fn warning(&mut self, pointer: impl Into<String>, message: impl Into<String>) {
+        self.issues.push(Issue {
+            severity: Severity::Warning,
+            pointer: pointer.into(),
+            message: message.into(),
+        });
+    }
+    fn check_buffers(&mut self, root: &gltf_json::Root, bin: &[u8]) {
+        for (i, b) in root.buffers.iter().enumerate() {
+            match &b.uri {
+                None if i == 0 => {
+                    if b.byte_length as usize > bin.len() {
+                        self.error(
+                            format!("/buffers/{}/byteLength", i),
+                            format!(
+                                "{} bytes is longer than the BIN chunk of {} bytes",
+                                b.byte_length,
+                                bin.len()
+                            ),
+                        );
+                    }
+                }
+                None => self.error(
+                    format!("/buffers/{}", i),
+                    "only the first buffer can refer to the BIN chunk",
+                ),
+                Some(_) => self.warning(
+                    format!("/buffers/{}/uri", i),
+                    "external buffers are not checked",
+                ),
+            }
+        }
+        for (i, v) in root.buffer_views.iter().enumerate() {
+            let pointer = format!("/bufferViews/{}", i);
+            let buffer = match root.buffers.get(v.buffer.value()) {
+                Some(b) => b,
+                None => continue,
+            };
+            let end = v.byte_offset.unwrap_or(0) as u64 + v.byte_length as u64;
+            if end > buffer.byte_length as u64 {
+                self.error(
+                    &pointer,
+                    format!(
+                        "ends at {} beyond the {} bytes of buffer #{}",
+                        end,
+                        buffer.byte_length,
+                        v.buffer.value()
+                    ),
+                );
+            }
+            if let Some(stride) = v.byte_stride {
+                if !(4..=252).contains(&stride) || stride % 4 != 0 {
+                    self.error(
+                        format!("{}/byteStride", pointer),
+                        format!("{} is not a multiple of 4 in 4..=252", stride),
+                    );
+                }
+            }
+        }
+    }
+    fn check_accessors(&mut self, root: &gltf_json::Root, buffers: &[Vec<u8>]) {
+        let mut vertex_attributes = BTreeSet::new();
+        for p in root.meshes.iter().flat_map(|m| &m.primitives) {
+            vertex_attributes.extend(p.attributes.values().map(|a| a.value()));
+            for t in p.targets.iter().flatten() {
+                vertex_attributes.extend(
+                    [t.positions, t.normals, t.tangents]
+                        .iter()
+                        .flatten()
+                        .map(|a| a.value()),
+                );
+            }
+        }
+        for (i, a) in root.accessors.iter().enumerate() {
+            let pointer = format!("/accessors/{}", i);
+            let layout = match Layout::of(a) {
+                Ok(layout) => layout,
+                Err(e) => {
+                    self.error(&pointer, format!("{:#}", e));
+                    continue;
+                }
+            };
+            let component_size = layout.component_type.size();
+            if let Some(view_idx) = a.buffer_view {
+                let view = match root.buffer_views.get(view_idx.value()) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let offset = view.byte_offset.unwrap_or(0) as usize + a.byte_offset as usize;
+                let is_vertex_attribute = vertex_attributes.contains(&i);
+                if offset % component_size != 0 {
+                    self.error(
+                        format!("{}/byteOffset", pointer),
+                        format!(
+                            "offset {} in the buffer is not aligned to the component size {}",
+                            offset, component_size
+                        ),
+                    );
+                } else if is_vertex_attribute && offset % 4 != 0 {
+                    self.error(
+                        format!("{}/byteOffset", pointer),
+                        format!(
+                            "offset {} of a vertex attribute in the buffer is not a multiple of 4",
+                            offset
+                        ),
+                    );
+                }
+                // byteStride itself is checked with the bufferView
+                if is_vertex_attribute && view.byte_stride.is_none() && layout.element_size % 4 != 0
+                {
+                    self.error(
+                        &pointer,
+                        format!(
+                            "elements of {} bytes of a vertex attribute need a byteStride",
+                            layout.element_size
+                        ),
+                    );
+                }
+                let stride = view
+                    .byte_stride
+                    .map(|s| s as usize)
+                    .unwrap_or(layout.element_size);
+                if stride < layout.element_size {
+                    self.error(
+                        format!("/bufferViews/{}/byteStride", view_idx.value()),
+                        format!(
+                            "{} is shorter than the element size {} of accessor #{}",
+                            stride, layout.element_size, i
+                        ),
+                    );
+                }
+                if a.count > 0 {
+                    let end = a.byte_offset as usize
+                        + stride * (a.count as usize - 1)
+                        + layout.element_size;
+                    if end > view.byte_length as usize {
+                        self.error(
+                            &pointer,
+                            format!(
+                                "ends at {} beyond the {} bytes of bufferView #{}",
+                                end,
+                                view.byte_length,
+                                view_idx.value()
+                            ),
+                        );
+                        continue;
+                    }
+                }
+            }
+            // min and max hold the values stored in the buffer, not the normalized ones
+            if layout.normalized {
+                continue;
+            }
+            let bounds = match (&a.min, &a.max) {
+                (Some(min), Some(max)) => (min, max),
+                (None, None) => continue,
+                _ => {
+                    self.error(&pointer, "only one of min and max is defined");
+                    continue;
+                }
+            };
+            let values = match accessor::read_values(root, buffers, i) {
+                Ok((_, values)) => values,
+                Err(e) => {
+                    self.error(&pointer, format!("{:#}", e));
+                    continue;
+                }
+            };
+            for (name, bound, is_min) in [("min", bounds.0, true), ("max", bounds.1, false)] {
+                let bound: Vec<f64> = match serde_json::from_value(bound.clone()) {
+                    Ok(b) => b,
+                    Err(_) => {
+                        self.error(format!("{}/{}", pointer, name), "should be numbers");
+                        continue;
+                    }
+                };
+                if bound.len() != layout.components {
+                    self.error(
+                        format!("{}/{}", pointer, name),
+                        format!(
+                            "has {} values for {} components",
+                            bound.len(),
+                            layout.components
+                        ),
+                    );
+                    continue;
+                }
+                let actual: Vec<f64> = (0..layout.components)
+                    .map(|c| {
+                        let column = values.iter().skip(c).step_by(layout.components);
+                        if is_min {
+                            column.fold(f64::MAX, |a, v| a.min(*v))
+                        } else {
+                            column.fold(f64::MIN, |a, v| a.max(*v))
+                        }
+                    })
+                    .collect();
+                // Bounds are often written in f32 precision
+                let off = bound
+                    .iter()
+                    .zip(&actual)
+                    .any(|(b, a)| (b - a).abs() > 1e-5 * a.abs().max(1.0));
+                if off && a.count > 0 {
+                    self.error(
+                        format!("{}/{}", pointer, name),
+                        format!("{:?} does not match the data {:?}", bound, actual),
+                    );
+                }
+            }
+        }
+    }
+    fn check_meshes(&mut self, root: &gltf_json::Root, buffers: &[Vec<u8>]) {
+        for (m, mesh) in root.meshes.iter().enumerate() {
+            let mut target_counts = BTreeSet::new();
+            for (p, primitive) in mesh.primitives.iter().enumerate() {
+                let pointer = format!("/meshes/{}/primitives/{}", m, p);
+                let position_idx = match primitive
+                    .attributes
+                    .get(&Valid(gltf_json::mesh::Semantic::Positions))
+                {
+                    Some(a) => a.value(),
+                    None => {
+                        self.warning(format!("{}/attributes", pointer), "has no POSITION");
+                        continue;
+                    }
+                };
+                let position = match root.accessors.get(position_idx) {
+                    Some(a) => a,
+                    None => continue,
+                };
+                if position.min.is_none() || position.max.is_none() {
+                    self.error(
+                        format!("/accessors/{}", position_idx),
+                        "POSITION accessors must have min and max",
+                    );
+                }
+                let vertex_count = position.count;
+                for (semantic, a) in &primitive.attributes {
+                    let count = match root.accessors.get(a.value()) {
+                        Some(a) => a.count,
+                        None => continue,
+                    };
+                    if count != vertex_count {
+                        let name = match semantic {
+                            Valid(s) => s.to_string(),
+                            _ => "an attribute".to_string(),
+                        };
+                        self.error(
+                            format!("{}/attributes/{}", pointer, name),
+                            format!("has {} elements for {} vertices", count, vertex_count),
+                        );
+                    }
+                }
+                if let Some(indices) = primitive.indices {
+                    match accessor::read_indices(root, buffers, indices.value()) {
+                        Ok(indices) => {
+                            if let Some(i) = indices.iter().find(|i| **i >= vertex_count) {
+                                self.error(
+                                    format!("{}/indices", pointer),
+                                    format!("index {} is out of the {} vertices", i, vertex_count),
+                                );
+                            }
+                        }
+                        Err(e) => self.error(format!("{}/indices", pointer), format!("{:#}", e)),
+                    }
+                }
+                let targets = primitive.targets.as_deref().unwrap_or_default();
+                target_counts.insert(targets.len());
+                for (t, target) in targets.iter().enumerate() {
+                    for (name, a) in [
+                        ("POSITION", target.positions),
+                        ("NORMAL", target.normals),
+                        ("TANGENT", target.tangents),
+                    ] {
+                        let count = match a.and_then(|a| root.accessors.get(a.value())) {
+                            Some(a) => a.count,
+                            None => continue,
+                        };
+                        if count != vertex_count {
+                            self.error(
+                                format!("{}/targets/{}/{}", pointer, t, name),
+                                format!("has {} elements for {} vertices", count, vertex_count),
+                            );
+                        }
+                    }
+                }
+            }
+            if target_counts.len() > 1 {
+                self.error(
+                    format!("/meshes/{}", m),
+                    "primitives have different numbers of morph targets",
+                );
+            }
+            if let (Some(weights), Some(count)) = (&mesh.weights, target_counts.iter().next()) {
+                if weights.len() != *count {
+                    self.error(
+                        format!("/meshes/{}/weights", m),
+                        format!("has {} weights for {} morph targets", weights.len(), count),
+                    );
+                }
+            }
+        }
+    }
+    fn check_skins(&mut self, root: &gltf_json::Root, buffers: &[Vec<u8>]) {
+        for (n, node) in root.nodes.iter().enumerate() {
+            let (mesh, skin) = match (node.mesh, node.skin) {
+                (Some(mesh), Some(skin)) => (mesh.value(), skin.value()),
+                (None, Some(_)) => {
+                    self.error(
+                        format!("/nodes/{}/skin", n),
+                        "a skin is given without a mesh",
+                    );
+                    continue;
+                }
+                _ => continue,
+            };
+            let joint_count = match root.skins.get(skin) {
+                Some(s) => s.joints.len(),
+                None => continue,
+            };
+            for (p, primitive) in root
+                .meshes
+                .get(mesh)
+                .iter()
+                .flat_map(|m| m.primitives.iter().enumerate())
+            {
+                let pointer = format!("/meshes/{}/primitives/{}/attributes", mesh, p);
+                match skin::read_joints_and_weights(root, buffers, primitive) {
+                    Ok(Some((joints, weights))) => {
+                        if let Some(j) = joints
+                            .iter()
+                            .flatten()
+                            .find(|j| **j as usize >= joint_count)
+                        {
+                            self.error(
+                                format!("{}/JOINTS_0", pointer),
+                                format!(
+                                    "joint {} is out of the {} joints of skin #{}",
+                                    j, joint_count, skin
+                                ),
+                            );
+                        }
+                        if let Some(v) = weights
+                            .iter()
+                            .position(|w| (w.iter().sum::<f32>() - 1.0).abs() > 1e-2)
+                        {
+                            self.warning(
+                                format!("{}/WEIGHTS_0", pointer),
+                                format!(
+                                    "the weights of vertex {} don't sum up to 1: {:?}",
+                                    v, weights[v]
+                                ),
+                            );
+                        }
+                    }
+                    Ok(None) => self.warning(
+                        &pointer,
+                        format!("node #{} has a skin but the primitive has no JOINTS_0", n),
+                    ),
+                    Err(e) => self.error(&pointer, format!("{:#}", e)),
+                }
+            }
+        }
+    }
+    fn check_textures(&mut self, json: &Value) {
+        let images = json["images"]
+            .as_array()
+            .map(|a| a.as_slice())
+            .unwrap_or_default();
+        let formats: Vec<Option<ImageFormat>> = images
+            .iter()
+            .map(
+                |image| match (image["mimeType"].as_str(), image["uri"].as_str()) {
+                    (Some(mime_type), _) => ImageFormat::from_mime_type(mime_type),
+                    (None, Some(uri)) => match uri.strip_prefix("data:") {
+                        Some(data) => data.split(';').next().and_then(ImageFormat::from_mime_type),
+                        None => ImageFormat::from_path(std::path::Path::new(uri)),
+                    },
+                    (None, None) => None,
+                },
+            )
+            .collect();
+        for (i, format) in formats.iter().enumerate() {
+            if format.is_none() {
+                self.warning(
+                    format!("/images/{}", i),
+                    "the format of the image is not PNG, JPEG, WebP or KTX2",
+                );
+            }
+        }
+        let required = json["extensionsRequired"].as_array();
+        let textures = json["textures"].as_array().into_iter().flatten();
+        for (i, texture) in textures.enumerate() {
+            let pointer = format!("/textures/{}", i);
+            if let Some(source) = texture["source"].as_u64() {
+                if let Some(Some(f)) = formats.get(source as usize) {
+                    if let Some(e) = f.texture_extension() {
+                        self.error(
+                            format!("{}/source", pointer),
+                            format!(
+                                "image #{} is {}, which can only be used with {}",
+                                source,
+                                f.mime_type(),
+                                e
+                            ),
+                        );
+                    }
+                }
+            }
+            let mut has_extension_source = false;
+            for format in IMAGE_FORMATS {
+                let e = match format.texture_extension() {
+                    Some(e) => e,
+                    None => continue,
+                };
+                let source = match texture["extensions"].get(e) {
+                    Some(extension) => extension["source"].as_u64(),
+                    None => continue,
+                };
+                let pointer = format!("{}/extensions/{}/source", pointer, e);
+                match source.map(|s| (s, formats.get(s as usize))) {
+                    None => self.error(pointer, "is missing"),
+                    Some((s, None)) => self.error(pointer, format!("image #{} does not exist", s)),
+                    Some((s, Some(f))) if *f != Some(format) => self.error(
+                        pointer,
+                        format!("image #{} is not {}", s, format.mime_type()),
+                    ),
+                    Some(_) => has_extension_source = true,
+                }
+                if texture.get("source").is_none()
+                    && !required.map_or(false, |r| r.iter().any(|r| r == e))
+                {
+                    self.error(
+                        "/extensionsRequired",
+                        format!(
+                            "{} must be required since texture #{} has no fallback source",
+                            e, i
+                        ),
+                    );
+                }
+            }
+            if texture.get("source").is_none() && !has_extension_source {
+                self.error(pointer, "the texture has no source");
+            }
+        }
+    }
+    fn check_extensions(&mut self, json: &Value) {
+        fn collect(value: &Value, pointer: String, found: &mut BTreeMap<String, String>) {
+            match value {
+                Value::Object(o) => {
+                    for (k, v) in o {
+                        let child =
+                            format!("{}/{}", pointer, k.replace('~', "~0").replace('/', "~1"));
+                        // Anything can be written in extras
+                        if k == "extras" {
+                            continue;
+                        }
+                        if k == "extensions" {
+                            for name in v.as_object().into_iter().flat_map(|e| e.keys()) {
+                                found
+                                    .entry(name.clone())
+                                    .or_insert_with(|| format!("{}/{}", child, name));
+                            }
+                        }
+                        collect(v, child, found);
+                    }
+                }
+                Value::Array(a) => {
+                    for (i, v) in a.iter().enumerate() {
+                        collect(v, format!("{}/{}", pointer, i), found);
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut found = BTreeMap::new();
+        collect(json, String::new(), &mut found);
+        let list = |key: &str| -> Vec<String> {
+            json[key]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|e| e.as_str().map(|s| s.to_string()))
+                .collect()
+        };
+        let used = list("extensionsUsed");
+        let required = list("extensionsRequired");
+        for (name, pointer) in &found {
+            if !used.contains(name) {
+                self.error(pointer, format!("{} is not listed in extensionsUsed", name));
+            }
+        }
+        for (i, name) in used.iter().enumerate() {
+            if !found.contains_key(name) {
+                self.warning(
+                    format!("/extensionsUsed/{}", i),
+                    format!("{} is not used", name),
+                );
+            }
+        }
+        for (i, name) in required.iter().enumerate() {
+            if !used.contains(name) {
+                self.error(
+                    format!("/extensionsRequired/{}", i),
+                    format!("{} is not listed in extensionsUsed", name),
+                );
+            }
+        }
+    }
+    fn check_humanoid(&mut self, root: &gltf_json::Root, humanoid: &vrm::Humanoid) {
+        let pointer = "/extensions/VRMC_vrm/humanoid/humanBones";
+        // A broken hierarchy is reported by check_gltf
+        let parents = match pose::parents(root) {
+            Ok(parents) => parents,
+            Err(_) => return,
+        };
+        let mut used = BTreeMap::new();
+        for (bone, b) in &humanoid.human_bones {
+            let pointer = format!("{}/{}/node", pointer, bone);
+            if !vrm::HUMAN_BONES.contains(&bone.as_str()) {
+                self.error(
+                    &pointer,
+                    format!("{} is not a humanoid bone of VRM 1.0", bone),
+                );
+            }
+            if b.node as usize >= root.nodes.len() {
+                self.error(&pointer, format!("node #{} does not exist", b.node));
+                continue;
+            }
+            if let Some(other) = used.insert(b.node, bone) {
+                self.error(
+                    &pointer,
+                    format!("node #{} is also used by {}", b.node, other),
+                );
+            }
+        }
+        for bone in humanoid.missing_required_bones() {
+            self.error(pointer, format!("required bone {} is not mapped", bone));
+        }
+        for (bone, b) in &humanoid.human_bones {
+            if b.node as usize >= root.nodes.len() {
+                continue;
+            }
+            // The nearest ancestor bone that is mapped
+            let mut parent = bone.as_str();
+            let parent_node = loop {
+                parent = match HUMAN_BONE_PARENTS.iter().find(|(b, _)| *b == parent) {
+                    Some((_, p)) => p,
+                    None => break None,
+                };
+                if let Some(p) = humanoid.human_bones.get(parent) {
+                    break Some(p.node as usize);
+                }
+            };
+            let parent_node = match parent_node {
+                Some(p) if p < root.nodes.len() => p,
+                _ => continue,
+            };
+            let mut node = parents[b.node as usize];
+            while let Some(n) = node {
+                if n == parent_node {
+                    break;
+                }
+                node = parents[n];
+            }
+            if node.is_none() {
+                self.error(
+                    format!("{}/{}/node", pointer, bone),
+                    format!(
+                        "node #{} is not a descendant of node #{} of {}",
+                        b.node, parent_node, parent
+                    ),
+                );
+            }
+        }
+    }
+    fn check_vrm(&mut self, root: &gltf_json::Root, vrm: &Vrm) {
+        let pointer = "/extensions/VRMC_vrm";
+        if vrm.spec_version != vrm::SPEC_VERSION {
+            self.warning(
+                format!("{}/specVersion", pointer),
+                format!("{} is not {}", vrm.spec_version, vrm::SPEC_VERSION),
+            );
+        }
+        if vrm.meta.name.is_empty() {
+            self.error(format!("{}/meta/name", pointer), "is empty");
+        }
+        if vrm.meta.authors.is_empty() {
+            self.error(
+                format!("{}/meta/authors", pointer),
+                "must have at least one author",
+            );
+        }
+        if vrm.meta.license_url.is_empty() {
+            self.error(format!("{}/meta/licenseUrl", pointer), "is empty");
+        }
+        for conflict in vrm.meta.license_conflicts() {
+            self.warning(format!("{}/meta/modification", pointer), conflict);
+        }
+        if let Some(t) = vrm.meta.thumbnail_image {
+            if t as usize >= root.images.len() {
+                self.error(
+                    format!("{}/meta/thumbnailImage", pointer),
+                    format!("image #{} does not exist", t),
+                );
+            }
+        }
+        self.check_humanoid(root, &vrm.humanoid);
+        let expressions = match &vrm.expressions {
+            Some(e) => e,
+            None => return,
+        };
+        for (kind, map) in [
+            ("preset", &expressions.preset),
+            ("custom", &expressions.custom),
+        ] {
+            for (name, e) in map {
+                let pointer = format!("{}/expressions/{}/{}", pointer, kind, name);
+                if kind == "preset" && !vrm::EXPRESSION_PRESETS.contains(&name.as_str()) {
+                    self.error(&pointer, format!("{} is not a preset expression", name));
+                }
+                if kind == "custom" && vrm::EXPRESSION_PRESETS.contains(&name.as_str()) {
+                    self.error(
+                        &pointer,
+                        format!("{} is the name of a preset expression", name),
+                    );
+                }
+                for (i, b) in e.morph_target_binds.iter().enumerate() {
+                    let targets = root
+                        .nodes
+                        .get(b.node as usize)
+                        .and_then(|n| n.mesh)
+                        .and_then(|m| root.meshes.get(m.value()))
+                        .and_then(|m| m.primitives.first())
+                        .map(|p| p.targets.as_ref().map(|t| t.len()).unwrap_or(0));
+                    match targets {
+                        None => self.error(
+                            format!("{}/morphTargetBinds/{}/node", pointer, i),
+                            format!("node #{} does not have a mesh", b.node),
+                        ),
+                        Some(n) if b.index as usize >= n => self.error(
+                            format!("{}/morphTargetBinds/{}/index", pointer, i),
+                            format!("morph target {} is out of the {} targets", b.index, n),
+                        ),
+                        _ => {}
+                    }
+                }
+                let materials = e
+                    .material_color_binds
+                    .iter()
+                    .map(|b| ("materialColorBinds", b.material))
+                    .chain(
+                        e.texture_transform_binds
+                            .iter()
+                            .map(|b| ("textureTransformBinds", b.material)),
+                    );
+                let mut counts = BTreeMap::new();
+                for (binds, material) in materials {
+                    let i = counts.entry(binds).or_insert(0);
+                    if material as usize >= root.materials.len() {
+                        self.error(
+                            format!("{}/{}/{}/material", pointer, binds, i),
+                            format!("material #{} does not exist", material),
+                        );
+                    }
+                    *i += 1;
+                }
+            }
+        }
+    }
+    fn check_mtoon(&mut self, root: &gltf_json::Root, doc: &Document) {
+        for i in 0..root.materials.len() {
+            let pointer = format!("/materials/{}/extensions/{}", i, mtoon::EXTENSION_NAME);
+            let mtoon = match doc.mtoon(i) {
+                Ok(Some(mtoon)) => mtoon,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.error(pointer, format!("{:#}", e));
+                    continue;
+                }
+            };
+            if mtoon.spec_version != mtoon::SPEC_VERSION {
+                self.warning(
+                    format!("{}/specVersion", pointer),
+                    format!("{} is not {}", mtoon.spec_version, mtoon::SPEC_VERSION),
+                );
+            }
+            for (name, t) in mtoon.textures() {
+                if t as usize >= root.textures.len() {
+                    self.error(
+                        format!("{}/{}/index", pointer, name),
+                        format!("texture #{} does not exist", t),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_node_constraints(&mut self, root: &gltf_json::Root, doc: &Document) {
+        let mut constraints = Vec::new();
+        for i in 0..root.nodes.len() {
+            let pointer = format!("/nodes/{}/extensions/{}", i, constraint::EXTENSION_NAME);
+            let c = match doc.node_constraint(i) {
+                Ok(Some(c)) => c,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.error(pointer, format!("{:#}", e));
+                    continue;
+                }
+            };
+            if c.spec_version != constraint::SPEC_VERSION {
+                self.warning(
+                    format!("{}/specVersion", pointer),
+                    format!("{} is not {}", c.spec_version, constraint::SPEC_VERSION),
+                );
+            }
+            let source = c.constraint.source();
+            if source as usize >= root.nodes.len() {
+                self.error(
+                    format!("{}/constraint", pointer),
+                    format!("source node #{} does not exist", source),
+                );
+                continue;
+            }
+            if source as usize == i {
+                self.error(
+                    format!("{}/constraint", pointer),
+                    "the source is the node itself",
+                );
+                continue;
+            }
+            if !(0.0..=1.0).contains(&c.constraint.weight()) {
+                self.error(
+                    format!("{}/constraint", pointer),
+                    format!("weight {} is not in [0, 1]", c.constraint.weight()),
+                );
+            }
+            constraints.push((i, c));
+        }
+        // A broken hierarchy is reported by check_gltf
+        if let Ok(rest) = Pose::rest(root) {
+            if let Err(e) = constraint::evaluation_order(&constraints, &rest) {
+                self.error("/nodes", format!("{:#}", e));
+            }
+        }
+    }
+
+    fn check_spring_bone(&mut self, root: &gltf_json::Root, doc: &Document) {
+        let pointer = format!("/extensions/{}", spring_bone::EXTENSION_NAME);
+        let spring_bone = match doc.spring_bone() {
+            Ok(Some(spring_bone)) => spring_bone,
+            Ok(None) => return,
+            Err(e) => {
+                self.error(pointer, format!("{:#}", e));
+                return;
+            }
+        };
+        if spring_bone.spec_version != spring_bone::SPEC_VERSION {
+            self.warning(
+                format!("{}/specVersion", pointer),
+                format!(
+                    "{} is not {}",
+                    spring_bone.spec_version,
+                    spring_bone::SPEC_VERSION
+                ),
+            );
+        }
+        let node_exists = |node: u32| (node as usize) < root.nodes.len();
+        for (i, c) in spring_bone.colliders.iter().enumerate() {
+            let pointer = format!("{}/colliders/{}", pointer, i);
+            if !node_exists(c.node) {
+                self.error(
+                    format!("{}/node", pointer),
+                    format!("node #{} does not exist", c.node),
+                );
+            }
+            if let Some(ExtendedShape::Plane { normal, .. }) = c.extended_shape() {
+                if normal.iter().all(|v| *v == 0.0) {
+                    self.error(
+                        format!(
+                            "{}/extensions/{}/shape/plane/normal",
+                            pointer,
+                            spring_bone::EXTENDED_COLLIDER_EXTENSION_NAME
+                        ),
+                        "is a zero vector",
+                    );
+                }
+            }
+        }
+        for (i, g) in spring_bone.collider_groups.iter().enumerate() {
+            for (j, c) in g.colliders.iter().enumerate() {
+                if *c as usize >= spring_bone.colliders.len() {
+                    self.error(
+                        format!("{}/colliderGroups/{}/colliders/{}", pointer, i, j),
+                        format!("collider #{} does not exist", c),
+                    );
+                }
+            }
+        }
+        let mut joint_springs = BTreeMap::new();
+        for (i, s) in spring_bone.springs.iter().enumerate() {
+            let pointer = format!("{}/springs/{}", pointer, i);
+            if s.joints.is_empty() {
+                self.error(format!("{}/joints", pointer), "is empty");
+            }
+            let mut parent: Option<u32> = None;
+            for (j, joint) in s.joints.iter().enumerate() {
+                let pointer = format!("{}/joints/{}/node", pointer, j);
+                if !node_exists(joint.node) {
+                    self.error(pointer, format!("node #{} does not exist", joint.node));
+                    parent = None;
+                    continue;
+                }
+                if let Some(parent) = parent {
+                    let is_child = root.nodes[parent as usize]
+                        .children
+                        .iter()
+                        .flatten()
+                        .any(|c| c.value() == joint.node as usize);
+                    if !is_child {
+                        self.error(
+                            &pointer,
+                            format!(
+                                "node #{} is not a child of the previous joint #{}",
+                                joint.node, parent
+                            ),
+                        );
+                    }
+                }
+                if let Some(other) = joint_springs.insert(joint.node, i) {
+                    if other != i {
+                        self.warning(
+                            &pointer,
+                            format!("node #{} is also a joint of spring #{}", joint.node, other),
+                        );
+                    }
+                }
+                parent = Some(joint.node);
+            }
+            for (j, g) in s.collider_groups.iter().enumerate() {
+                if *g as usize >= spring_bone.collider_groups.len() {
+                    self.error(
+                        format!("{}/colliderGroups/{}", pointer, j),
+                        format!("collider group #{} does not exist", g),
+                    );
+                }
+            }
+            if let Some(center) = s.center {
+                if !node_exists(center) {
+                    self.error(
+                        format!("{}/center", pointer),
+                        format!("node #{} does not exist", center),
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl Validator {
+    /// Run the glTF 2.0 checks and return the typed view of the document if it could be parsed
+    fn check_gltf(&mut self, doc: &Document) -> Option<gltf_json::Root> {
+        let root = match doc.root() {
+            Ok(root) => root,
+            Err(e) => {
+                self.error("", format!("not a valid glTF document: {:#}", e));
+                return None;
+            }
+        };
+        root.validate(&root, Path::new, &mut |path, error| {
+            self.error(to_pointer(&path()), error.to_string());
+        });
+        if let Err(e) = pose::parents(&root) {
+            self.error("/nodes", format!("{:#}", e));
+        }
+        if root.asset.version != "2.0" {
+            self.error(
+                "/asset/version",
+                format!("{} is not 2.0", root.asset.version),
+            );
+        }
+        let buffers = doc.buffers();
+        self.check_buffers(&root, &doc.bin);
+        self.check_accessors(&root, buffers);
+        self.check_meshes(&root, buffers);
+        self.check_skins(&root, buffers);
+        self.check_textures(&doc.json);
+        self.check_extensions(&doc.json);
+        Some(root)
+    }
+    fn into_issues(mut self) -> Vec<Issue> {
+        self.issues.sort_by_key(|i| i.severity);
+        self.issues
+    }
+}
+
+/// Check a document against glTF 2.0 only, and return the problems found, errors first
+pub fn validate_gltf(doc: &Document) -> Vec<Issue> {
+    let mut v = Validator::default();
+    v.check_gltf(doc);
+    v.into_issues()
+}
+
+/// Check a document against glTF 2.0 and VRM 1.0, and return the problems found, errors first
+pub fn validate(doc: &Document) -> Vec<Issue> {
+    let mut v = Validator::default();
+    let root = match v.check_gltf(doc) {
+        Some(root) => root,
+        None => return v.into_issues(),
+    };
+    match doc.vrm() {
+        Ok(Some(vrm)) => v.check_vrm(&root, &vrm),
+        Ok(None) => match doc.vrm0() {
+            Ok(Some(_)) => v.error(
+                "/extensions/VRM",
+                "this is a VRM 0.x file. Run `vacation migrate` to convert it into VRM 1.0",
+            ),
+            _ => v.error("/extensions", "VRMC_vrm is missing"),
+        },
+        Err(e) => v.error("/extensions/VRMC_vrm", format!("{:#}", e)),
+    }
+    v.check_mtoon(&root, doc);
+    v.check_spring_bone(&root, doc);
+    v.check_node_constraints(&root, doc);
+    v.into_issues()
+}
+
+/// Read back a GLB that is about to be written, and fail if it breaks glTF 2.0.
+/// VRM 1.0 is not required since files are also written while an avatar is being built.
+pub fn check_glb(data: &[u8]) -> Result<()> {
+    let doc = Document::from_slice(data)?;
+    doc.gltf()?;
+    let errors: Vec<String> = validate_gltf(&doc)
+        .iter()
+        .filter(|i| i.severity == Severity::Error)
+        .map(|i| i.to_string())
+        .collect();
+    if !errors.is_empty() {
+        bail!(
+            "The written data is not valid glTF 2.0:\n{}",
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compile::compile_file;
+    use serde_json::json;
+
+    /// A triangle with POSITION, indices and TEXCOORD_0 of 16-bit components
+    fn triangle() -> Document {
+        let mut bin = Vec::new();
+        for v in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            bin.extend(v.iter().flat_map(|x| x.to_le_bytes()));
+        }
+        bin.extend([0u16, 1, 2, 0].iter().flat_map(|i| i.to_le_bytes()));
+        bin.extend(
+            [0u16, 0, 65535, 0, 0, 65535, 0, 0]
+                .iter()
+                .flat_map(|i| i.to_le_bytes()),
+        );
+        let json = json!({
+            "asset": { "version": "2.0" },
+            "buffers": [{ "byteLength": bin.len() }],
+            "bufferViews": [
+                { "buffer": 0, "byteLength": 36, "target": 34962 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 6, "target": 34963 },
+                { "buffer": 0, "byteOffset": 44, "byteLength": 16, "target": 34962 },
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0.0, 0.0, 0.0], "max": [1.0, 1.0, 0.0] },
+                { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" },
+                { "bufferView": 2, "componentType": 5123, "normalized": true, "count": 3,
+                  "type": "VEC2" },
+            ],
+            "meshes": [{
+                "primitives": [{ "attributes": { "POSITION": 0, "TEXCOORD_0": 2 }, "indices": 1 }],
+            }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }],
+        });
+        Document::new(json, bin)
+    }
+
+    fn errors(issues: Vec<Issue>) -> Vec<(String, String)> {
+        issues
+            .into_iter()
+            .filter(|i| i.severity == Severity::Error)
+            .map(|i| (i.pointer, i.message))
+            .collect()
+    }
+
+    fn assert_error(issues: Vec<Issue>, pointer: &str, message: &str) {
+        let errors = errors(issues);
+        assert!(
+            errors
+                .iter()
+                .any(|(p, m)| p == pointer && m.contains(message)),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn triangle_is_valid() {
+        assert_eq!(errors(validate_gltf(&triangle())), vec![]);
+    }
+
+    #[test]
+    fn bounds_should_match_the_data() {
+        let mut doc = triangle();
+        doc.json["accessors"][0]["max"] = json!([1.0, 0.5, 0.0]);
+        assert_error(
+            validate_gltf(&doc),
+            "/accessors/0/max",
+            "does not match the data",
+        );
+    }
+
+    #[test]
+    fn indices_should_be_in_the_vertices() {
+        let mut doc = triangle();
+        doc.bin[40..42].copy_from_slice(&5u16.to_le_bytes());
+        assert_error(
+            validate_gltf(&doc),
+            "/meshes/0/primitives/0/indices",
+            "index 5 is out of the 3 vertices",
+        );
+    }
+
+    #[test]
+    fn vertex_attributes_are_aligned_to_4_bytes() {
+        let mut doc = triangle();
+        doc.json["accessors"][0]["byteOffset"] = json!(2);
+        doc.json["bufferViews"][0]["byteLength"] = json!(40);
+        assert_error(
+            validate_gltf(&doc),
+            "/accessors/0/byteOffset",
+            "not aligned to the component size 4",
+        );
+
+        // Aligned to the 2-byte components, but not to 4 bytes
+        let mut doc = triangle();
+        doc.json["accessors"][2]["byteOffset"] = json!(2);
+        assert_error(
+            validate_gltf(&doc),
+            "/accessors/2/byteOffset",
+            "offset 46 of a vertex attribute in the buffer is not a multiple of 4",
+        );
+
+        // Elements of 3 bytes are packed without padding
+        let mut doc = triangle();
+        doc.json["accessors"][2]["componentType"] = json!(5121);
+        doc.json["accessors"][2]["type"] = json!("VEC3");
+        assert_error(
+            validate_gltf(&doc),
+            "/accessors/2",
+            "elements of 3 bytes of a vertex attribute need a byteStride",
+        );
+        doc.json["bufferViews"][2]["byteStride"] = json!(4);
+        assert_eq!(errors(validate_gltf(&doc)), vec![]);
+
+        // Indices don't have to be aligned to 4 bytes
+        let mut doc = triangle();
+        doc.json["bufferViews"][1]["byteOffset"] = json!(38);
+        doc.json["bufferViews"][1]["byteLength"] = json!(4);
+        doc.json["accessors"][1]["count"] = json!(2);
+        assert!(!errors(validate_gltf(&doc))
+            .iter()
+            .any(|(p, _)| p.starts_with("/accessors/1")));
+    }
+
+    #[test]
+    fn extensions_should_be_listed() {
+        let mut doc = triangle();
+        doc.json["nodes"][0]["extensions"] = json!({ "EXT_example": {} });
+        assert_error(
+            validate_gltf(&doc),
+            "/nodes/0/extensions/EXT_example",
+            "EXT_example is not listed in extensionsUsed",
+        );
+        doc.json["extensionsUsed"] = json!(["EXT_example"]);
+        assert_eq!(errors(validate_gltf(&doc)), vec![]);
+    }
+
+    fn sample() -> Document {
+        let source = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("samples/avatar.json");
+        compile_file(&source).unwrap()
+    }
+
+    #[test]
+    fn required_bones_should_be_mapped() {
+        let mut doc = sample();
+        let bones = &mut doc.json["extensions"]["VRMC_vrm"]["humanoid"]["humanBones"];
+        bones.as_object_mut().unwrap().remove("head");
+        assert_error(
+            validate(&doc),
+            "/extensions/VRMC_vrm/humanoid/humanBones",
+            "required bone head is not mapped",
+        );
+    }
+
+    #[test]
+    fn bones_should_be_under_their_parent_bone() {
+        let mut doc = sample();
+        let bones = &mut doc.json["extensions"]["VRMC_vrm"]["humanoid"]["humanBones"];
+        let head = bones["head"]["node"].clone();
+        bones["head"]["node"] = bones["leftHand"]["node"].clone();
+        bones["leftHand"]["node"] = head;
+        assert_error(
+            validate(&doc),
+            "/extensions/VRMC_vrm/humanoid/humanBones/head/node",
+            "is not a descendant of",
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use crate::extensions;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+pub const EXTENSION_NAME: &str = "VRMC_vrm";
+pub const SPEC_VERSION: &str = "1.0";
+
+/// Names of all the humanoid bones defined in VRM 1.0, parents first
+pub const HUMAN_BONES: [&str; 55] = [
+    "hips",
+    "spine",
+    "chest",
+    "upperChest",
+    "neck",
+    "head",
+    "leftEye",
+    "rightEye",
+    "jaw",
+    "leftUpperLeg",
+    "leftLowerLeg",
+    "leftFoot",
+    "leftToes",
+    "rightUpperLeg",
+    "rightLowerLeg",
+    "rightFoot",
+    "rightToes",
+    "leftShoulder",
+    "leftUpperArm",
+    "leftLowerArm",
+    "leftHand",
+    "rightShoulder",
+    "rightUpperArm",
+    "rightLowerArm",
+    "rightHand",
+    "leftThumbMetacarpal",
+    "leftThumbProximal",
+    "leftThumbDistal",
+    "leftIndexProximal",
+    "leftIndexIntermediate",
+    "leftIndexDistal",
+    "leftMiddleProximal",
+    "leftMiddleIntermediate",
+    "leftMiddleDistal",
+    "leftRingProximal",
+    "leftRingIntermediate",
+    "leftRingDistal",
+    "leftLittleProximal",
+    "leftLittleIntermediate",
+    "leftLittleDistal",
+    "rightThumbMetacarpal",
+    "rightThumbProximal",
+    "rightThumbDistal",
+    "rightIndexProximal",
+    "rightIndexIntermediate",
+    "rightIndexDistal",
+    "rightMiddleProximal",
+    "rightMiddleIntermediate",
+    "rightMiddleDistal",
+    "rightRingProximal",
+    "rightRingIntermediate",
+    "rightRingDistal",
+    "rightLittleProximal",
+    "rightLittleIntermediate",
+    "rightLittleDistal",
+];
+
+/// Humanoid bones that every VRM 1.0 model must have
+pub const REQUIRED_HUMAN_BONES: [&str; 15] = [
+    "hips",
+    "spine",
+    "head",
+    "leftUpperLeg",
+    "leftLowerLeg",
+    "leftFoot",
+    "rightUpperLeg",
+    "rightLowerLeg",
+    "rightFoot",
+    "leftUpperArm",
+    "leftLowerArm",
+    "leftHand",
+    "rightUpperArm",
+    "rightLowerArm",
+    "rightHand",
+];
+
+/// Names of the preset expressions defined in VRM 1.0
+pub const EXPRESSION_PRESETS: [&str; 18] = [
+    "happy",
+    "angry",
+    "sad",
+    "relaxed",
+    "surprised",
+    "aa",
+    "ih",
+    "ou",
+    "ee",
+    "oh",
+    "blink",
+    "blinkLeft",
+    "blinkRight",
+    "lookUp",
+    "lookDown",
+    "lookLeft",
+    "lookRight",
+    "neutral",
+];
+
+/// The VRMC_vrm extension on the glTF root
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vrm {
+    pub spec_version: String,
+    pub meta: Meta,
+    pub humanoid: Humanoid,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_person: Option<FirstPerson>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at: Option<LookAt>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expressions: Option<Expressions>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+impl Vrm {
+    pub fn new(meta: Meta, humanoid: Humanoid) -> Self {
+        Self {
+            spec_version: SPEC_VERSION.to_string(),
+            meta,
+            humanoid,
+            first_person: None,
+            look_at: None,
+            expressions: None,
+            extensions: None,
+            extras: None,
+        }
+    }
+    /// Read the VRMC_vrm extension from the JSON of a glTF document, if any
+    pub fn from_json(root: &Value) -> Result<Option<Self>> {
+        extensions::get(root, EXTENSION_NAME)
+    }
+    /// Store this as the VRMC_vrm extension of the JSON of a glTF document
+    pub fn write_to_json(&self, root: &mut Value) -> Result<()> {
+        extensions::set(root, EXTENSION_NAME, self)?;
+        extensions::mark_used(root, EXTENSION_NAME);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AvatarPermission {
+    #[default]
+    OnlyAuthor,
+    OnlySeparatelyLicensedPerson,
+    Everyone,
+}
+
+/// Ordered from the narrowest use to the widest
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CommercialUsage {
+    #[default]
+    PersonalNonProfit,
+    PersonalProfit,
+    Corporation,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CreditNotation {
+    #[default]
+    Required,
+    Unnecessary,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Modification {
+    #[default]
+    Prohibited,
+    AllowModification,
+    AllowModificationRedistribution,
+}
+
+/// Metadata and license of the avatar
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    pub authors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copyright_information: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact_information: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub third_party_licenses: Option<String>,
+    /// Index of the glTF image used as the thumbnail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_image: Option<u32>,
+    pub license_url: String,
+    #[serde(default)]
+    pub avatar_permission: AvatarPermission,
+    #[serde(default)]
+    pub allow_excessively_violent_usage: bool,
+    #[serde(default)]
+    pub allow_excessively_sexual_usage: bool,
+    #[serde(default)]
+    pub commercial_usage: CommercialUsage,
+    #[serde(default)]
+    pub allow_political_or_religious_usage: bool,
+    #[serde(default)]
+    pub allow_antisocial_or_hate_usage: bool,
+    #[serde(default)]
+    pub credit_notation: CreditNotation,
+    #[serde(default)]
+    pub allow_redistribution: bool,
+    #[serde(default)]
+    pub modification: Modification,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other_license_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Humanoid {
+    pub human_bones: BTreeMap<String, HumanBone>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HumanBone {
+    /// Index of the glTF node
+    pub node: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FirstPersonType {
+    #[default]
+    Auto,
+    Both,
+    ThirdPersonOnly,
+    FirstPersonOnly,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstPerson {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mesh_annotations: Vec<MeshAnnotation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MeshAnnotation {
+    /// Index of the glTF node that has the mesh
+    pub node: u32,
+    #[serde(rename = "type")]
+    pub type_: FirstPersonType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LookAtType {
+    #[default]
+    Bone,
+    Expression,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LookAt {
+    /// Position of the eyes relative to the head bone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset_from_head_bone: Option<[f32; 3]>,
+    #[serde(default, rename = "type")]
+    pub type_: LookAtType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range_map_horizontal_inner: Option<RangeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range_map_horizontal_outer: Option<RangeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range_map_vertical_down: Option<RangeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range_map_vertical_up: Option<RangeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+/// Maps the angle of the gaze (in degrees) to a bone rotation or an expression weight
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeMap {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_max_value: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_scale: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Expressions {
+    /// Preset name (one of EXPRESSION_PRESETS) to expression
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub preset: BTreeMap<String, Expression>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom: BTreeMap<String, Expression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExpressionOverride {
+    #[default]
+    None,
+    Block,
+    Blend,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Expression {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub morph_target_binds: Vec<MorphTargetBind>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub material_color_binds: Vec<MaterialColorBind>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub texture_transform_binds: Vec<TextureTransformBind>,
+    #[serde(default)]
+    pub is_binary: bool,
+    #[serde(default)]
+    pub override_blink: ExpressionOverride,
+    #[serde(default)]
+    pub override_look_at: ExpressionOverride,
+    #[serde(default)]
+    pub override_mouth: ExpressionOverride,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MorphTargetBind {
+    /// Index of the glTF node that has the mesh
+    pub node: u32,
+    /// Index of the morph target of the mesh
+    pub index: u32,
+    pub weight: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MaterialColorType {
+    Color,
+    EmissionColor,
+    ShadeColor,
+    MatcapColor,
+    RimColor,
+    OutlineColor,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterialColorBind {
+    /// Index of the glTF material
+    pub material: u32,
+    #[serde(rename = "type")]
+    pub type_: MaterialColorType,
+    pub target_value: [f32; 4],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextureTransformBind {
+    /// Index of the glTF material
+    pub material: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<[f32; 2]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extras: Option<Value>,
+}
@@ -0,0 +1,279 @@
+use crate::extensions;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// VRM 0.x uses a single extension named "VRM".
+/// The field names (including typos like "Ussage" and "stiffiness") follow the 0.x schema.
+pub const EXTENSION_NAME: &str = "VRM";
+
+/// The VRM extension on the glTF root of a VRM 0.x file
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vrm0 {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exporter_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec_version: Option<String>,
+    #[serde(default)]
+    pub meta: Meta,
+    #[serde(default)]
+    pub humanoid: Humanoid,
+    #[serde(default)]
+    pub first_person: FirstPerson,
+    #[serde(default)]
+    pub blend_shape_master: BlendShapeMaster,
+    #[serde(default)]
+    pub secondary_animation: SecondaryAnimation,
+    #[serde(default)]
+    pub material_properties: Vec<MaterialProperties>,
+}
+
+impl Vrm0 {
+    /// Read the VRM extension from the JSON of a glTF document, if any
+    pub fn from_json(root: &Value) -> Result<Option<Self>> {
+        extensions::get(root, EXTENSION_NAME)
+    }
+}
+
+/// A vector stored as an object, as VRM 0.x does
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct Vec3 {
+    #[serde(default)]
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    #[serde(default)]
+    pub z: f32,
+}
+
+/// Enum-like values are kept as strings since files in the wild don't always follow the schema
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact_information: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Index of the glTF texture used as the thumbnail, or -1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture: Option<i32>,
+    /// "OnlyAuthor", "ExplicitlyLicensedPerson" or "Everyone"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_user_name: Option<String>,
+    /// "Disallow" or "Allow"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub violent_ussage_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sexual_ussage_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commercial_ussage_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other_permission_url: Option<String>,
+    /// "Redistribution_Prohibited", "CC0", "CC_BY", "CC_BY_NC", "CC_BY_SA", "CC_BY_NC_SA",
+    /// "CC_BY_ND", "CC_BY_NC_ND" or "Other"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub other_license_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Humanoid {
+    #[serde(default)]
+    pub human_bones: Vec<HumanBone>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HumanBone {
+    pub bone: String,
+    /// Index of the glTF node, or -1
+    pub node: i32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FirstPerson {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_person_bone: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_person_bone_offset: Option<Vec3>,
+    #[serde(default)]
+    pub mesh_annotations: Vec<MeshAnnotation>,
+    /// "Bone" or "BlendShape"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at_type_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at_horizontal_inner: Option<DegreeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at_horizontal_outer: Option<DegreeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at_vertical_down: Option<DegreeMap>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at_vertical_up: Option<DegreeMap>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeshAnnotation {
+    /// Index of the glTF mesh
+    pub mesh: i32,
+    /// "Auto", "Both", "ThirdPersonOnly" or "FirstPersonOnly"
+    pub first_person_flag: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DegreeMap {
+    #[serde(default)]
+    pub curve: Vec<f32>,
+    #[serde(default)]
+    pub x_range: f32,
+    #[serde(default)]
+    pub y_range: f32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlendShapeMaster {
+    #[serde(default)]
+    pub blend_shape_groups: Vec<BlendShapeGroup>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlendShapeGroup {
+    #[serde(default)]
+    pub name: String,
+    /// e.g. "joy", "a", "blink_l" or "unknown"
+    #[serde(default)]
+    pub preset_name: String,
+    #[serde(default)]
+    pub binds: Vec<BlendShapeBind>,
+    #[serde(default)]
+    pub material_values: Vec<MaterialValueBind>,
+    #[serde(default)]
+    pub is_binary: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlendShapeBind {
+    /// Index of the glTF mesh
+    pub mesh: i32,
+    /// Index of the morph target
+    pub index: i32,
+    /// 0 to 100
+    pub weight: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterialValueBind {
+    pub material_name: String,
+    /// Name of the Unity shader property, e.g. "_Color"
+    pub property_name: String,
+    pub target_value: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondaryAnimation {
+    #[serde(default)]
+    pub bone_groups: Vec<BoneGroup>,
+    #[serde(default)]
+    pub collider_groups: Vec<ColliderGroup>,
+}
+
+// Defaults of UniVRM 0.x for the properties a boneGroup leaves out
+fn default_stiffiness() -> f32 {
+    1.0
+}
+
+fn default_gravity_dir() -> Vec3 {
+    Vec3 {
+        x: 0.0,
+        y: -1.0,
+        z: 0.0,
+    }
+}
+
+fn default_drag_force() -> f32 {
+    0.4
+}
+
+fn default_center() -> i32 {
+    -1
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoneGroup {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(default = "default_stiffiness")]
+    pub stiffiness: f32,
+    #[serde(default)]
+    pub gravity_power: f32,
+    #[serde(default = "default_gravity_dir")]
+    pub gravity_dir: Vec3,
+    #[serde(default = "default_drag_force")]
+    pub drag_force: f32,
+    /// Index of the glTF node, or -1
+    #[serde(default = "default_center")]
+    pub center: i32,
+    #[serde(default)]
+    pub hit_radius: f32,
+    /// Root nodes of the spring chains
+    #[serde(default)]
+    pub bones: Vec<i32>,
+    #[serde(default)]
+    pub collider_groups: Vec<i32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColliderGroup {
+    pub node: i32,
+    #[serde(default)]
+    pub colliders: Vec<Collider>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Collider {
+    #[serde(default)]
+    pub offset: Vec3,
+    #[serde(default)]
+    pub radius: f32,
+}
+
+/// Unity material properties, mostly of the "VRM/MToon" shader
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterialProperties {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub shader: String,
+    #[serde(default)]
+    pub render_queue: i32,
+    #[serde(default)]
+    pub float_properties: BTreeMap<String, f32>,
+    #[serde(default)]
+    pub vector_properties: BTreeMap<String, Vec<f32>>,
+    /// Property name to the index of the glTF texture
+    #[serde(default)]
+    pub texture_properties: BTreeMap<String, i32>,
+    #[serde(default)]
+    pub keyword_map: BTreeMap<String, bool>,
+    #[serde(default)]
+    pub tag_map: BTreeMap<String, String>,
+}